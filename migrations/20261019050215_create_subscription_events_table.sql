@@ -0,0 +1,6 @@
+CREATE TABLE subscription_events (
+  subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+  event TEXT NOT NULL,
+  occurred_at timestamptz NOT NULL
+);
+CREATE INDEX subscription_events_subscriber_id_idx ON subscription_events (subscriber_id);
@@ -0,0 +1,8 @@
+CREATE TABLE audit_log (
+  id uuid NOT NULL,
+  actor TEXT NOT NULL,
+  action TEXT NOT NULL,
+  details JSONB NOT NULL,
+  occurred_at timestamptz NOT NULL,
+  PRIMARY KEY (id)
+);
@@ -0,0 +1,8 @@
+-- Confirmation tokens expire and can only be used once
+ALTER TABLE subscription_tokens
+    ADD COLUMN created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    ADD COLUMN expires_at TIMESTAMPTZ,
+    ADD COLUMN consumed_at TIMESTAMPTZ;
+-- Give tokens issued before this migration the default lifetime from now on
+UPDATE subscription_tokens SET expires_at = created_at + INTERVAL '72 hours';
+ALTER TABLE subscription_tokens ALTER COLUMN expires_at SET NOT NULL;
@@ -0,0 +1,9 @@
+-- Email contents edited by admins, replacing the default ones
+CREATE TABLE custom_emails (
+    email TEXT PRIMARY KEY,
+    subject TEXT NOT NULL,
+    html TEXT NOT NULL,
+    text TEXT NOT NULL,
+    updated_at timestamptz NOT NULL DEFAULT now(),
+    updated_by uuid REFERENCES users (user_id) ON DELETE SET NULL
+);
@@ -0,0 +1,42 @@
+-- Each newsletter is a list that people subscribe to separately
+CREATE TABLE lists (
+  id uuid NOT NULL,
+  slug TEXT NOT NULL UNIQUE,
+  name TEXT NOT NULL,
+  created_at timestamptz NOT NULL DEFAULT now(),
+  PRIMARY KEY (id)
+);
+-- The list everyone was subscribed to before there were several
+INSERT INTO lists (id, slug, name) VALUES (gen_random_uuid(), 'newsletter', 'Newsletter');
+
+-- `subscriptions.status` is about the address (confirmed, or unsubscribed from everything),
+-- this one is about a single list
+CREATE TABLE list_subscriptions (
+  list_id uuid NOT NULL REFERENCES lists (id),
+  subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+  status TEXT NOT NULL,
+  subscribed_at timestamptz NOT NULL,
+  PRIMARY KEY (list_id, subscriber_id)
+);
+CREATE INDEX list_subscriptions_subscriber_id_idx ON list_subscriptions (subscriber_id);
+INSERT INTO list_subscriptions (list_id, subscriber_id, status, subscribed_at)
+SELECT lists.id, subscriptions.id, subscriptions.status, subscriptions.subscribed_at
+FROM subscriptions, lists
+WHERE lists.slug = 'newsletter';
+
+-- A confirmation token confirms the subscription to one list
+ALTER TABLE subscription_tokens ADD COLUMN list_id uuid REFERENCES lists (id);
+UPDATE subscription_tokens SET list_id = (SELECT id FROM lists WHERE slug = 'newsletter');
+ALTER TABLE subscription_tokens ALTER COLUMN list_id SET NOT NULL;
+
+ALTER TABLE subscription_events ADD COLUMN list_id uuid REFERENCES lists (id);
+
+CREATE TABLE newsletter_issue_lists (
+  newsletter_issue_id uuid NOT NULL REFERENCES newsletter_issues (newsletter_issue_id),
+  list_id uuid NOT NULL REFERENCES lists (id),
+  PRIMARY KEY (newsletter_issue_id, list_id)
+);
+INSERT INTO newsletter_issue_lists (newsletter_issue_id, list_id)
+SELECT newsletter_issues.newsletter_issue_id, lists.id
+FROM newsletter_issues, lists
+WHERE lists.slug = 'newsletter';
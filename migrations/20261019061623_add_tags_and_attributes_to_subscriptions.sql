@@ -0,0 +1,9 @@
+-- Used to send an issue to a segment of a list
+ALTER TABLE subscriptions
+    ADD COLUMN tags TEXT[] NOT NULL DEFAULT '{}',
+    ADD COLUMN attributes JSONB NOT NULL DEFAULT '{}';
+CREATE INDEX subscriptions_tags_idx ON subscriptions USING GIN (tags);
+CREATE INDEX subscriptions_attributes_idx ON subscriptions USING GIN (attributes);
+
+-- The segment an issue was sent to, NULL if it went to everyone on its lists
+ALTER TABLE newsletter_issues ADD COLUMN segment JSONB;
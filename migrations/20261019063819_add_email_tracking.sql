@@ -0,0 +1,25 @@
+-- Tracking is opt-in for each issue
+ALTER TABLE newsletter_issues ADD COLUMN track_opens BOOLEAN NOT NULL DEFAULT false;
+ALTER TABLE newsletter_issues ADD COLUMN track_clicks BOOLEAN NOT NULL DEFAULT false;
+
+-- Links of an issue whose clicks are tracked, numbered in order of appearance.
+-- `href` is the attribute value as written in the HTML content.
+CREATE TABLE issue_links (
+    newsletter_issue_id uuid NOT NULL
+        REFERENCES newsletter_issues (newsletter_issue_id) ON DELETE CASCADE,
+    link_id INT NOT NULL,
+    href TEXT NOT NULL,
+    PRIMARY KEY (newsletter_issue_id, link_id)
+);
+
+-- Opens and clicks, removed with the subscriber when their data is erased
+CREATE TABLE email_events (
+    id BIGSERIAL PRIMARY KEY,
+    newsletter_issue_id uuid NOT NULL
+        REFERENCES newsletter_issues (newsletter_issue_id) ON DELETE CASCADE,
+    subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+    event TEXT NOT NULL,
+    link_id INT,
+    occurred_at timestamptz NOT NULL DEFAULT now()
+);
+CREATE INDEX email_events_issue_idx ON email_events (newsletter_issue_id);
@@ -0,0 +1,16 @@
+-- Bounces and spam complaints reported by the email provider. Events about addresses that
+-- are not subscribed are kept without a subscriber; the others are removed with the
+-- subscriber when their data is erased.
+CREATE TABLE bounce_events (
+    id BIGSERIAL PRIMARY KEY,
+    subscriber_id uuid REFERENCES subscriptions (id) ON DELETE CASCADE,
+    email TEXT NOT NULL,
+    -- `Bounce` or `SpamComplaint`
+    record_type TEXT NOT NULL,
+    -- The kind of bounce, e.g. `HardBounce` or `SoftBounce`
+    bounce_type TEXT,
+    message_id TEXT,
+    payload JSONB NOT NULL,
+    received_at timestamptz NOT NULL DEFAULT now()
+);
+CREATE INDEX bounce_events_subscriber_idx ON bounce_events (subscriber_id);
@@ -0,0 +1,15 @@
+-- Addresses and domains nothing is ever sent to, whatever the subscriber's status.
+-- Not tied to a subscriber: a suppression outlives the subscription, so that subscribing
+-- again doesn't bring a bounced or complained address back.
+CREATE TABLE suppressions (
+    id uuid PRIMARY KEY,
+    -- A lowercase email address, or a domain for every address at it
+    entry TEXT NOT NULL UNIQUE,
+    -- `manual`, `hard_bounce` or `spam_complaint`
+    reason TEXT NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT now()
+);
+
+-- Issues that were not sent because the address is suppressed are logged with the
+-- deliveries, so they are not retried
+ALTER TABLE issue_deliveries ADD COLUMN outcome TEXT NOT NULL DEFAULT 'delivered';
@@ -0,0 +1,20 @@
+-- The delivery log becomes keyed by subscriber rather than address, and records sends before
+-- they happen: a row in `sending` is never sent again, even if the worker died before knowing
+-- whether the provider accepted it.
+ALTER TABLE issue_deliveries RENAME TO deliveries;
+ALTER TABLE deliveries ADD COLUMN subscriber_id uuid REFERENCES subscriptions (id) ON DELETE CASCADE;
+UPDATE deliveries d SET subscriber_id = s.id FROM subscriptions s WHERE s.email = d.subscriber_email;
+-- Deliveries to addresses that no longer belong to a subscriber can't be keyed
+DELETE FROM deliveries WHERE subscriber_id IS NULL;
+ALTER TABLE deliveries ALTER COLUMN subscriber_id SET NOT NULL;
+ALTER TABLE deliveries DROP CONSTRAINT issue_deliveries_pkey;
+ALTER TABLE deliveries ADD PRIMARY KEY (newsletter_issue_id, subscriber_id);
+-- Assigned by the email provider once it accepted the email
+ALTER TABLE deliveries ADD COLUMN message_id TEXT;
+-- When the send was attempted, `delivered_at` is only set once the provider accepted it.
+-- `outcome` is now `sending`, `delivered`, `failed` or `suppressed`.
+ALTER TABLE deliveries ADD COLUMN attempted_at timestamptz;
+UPDATE deliveries SET attempted_at = delivered_at;
+ALTER TABLE deliveries ALTER COLUMN attempted_at SET NOT NULL;
+ALTER TABLE deliveries ALTER COLUMN attempted_at SET DEFAULT now();
+ALTER TABLE deliveries ALTER COLUMN delivered_at DROP NOT NULL;
@@ -0,0 +1,19 @@
+-- Confirmation emails are written here in the same transaction as the token they carry, and
+-- sent by the background worker. `status` is `pending`, `sent`, `failed` or `suppressed`.
+CREATE TABLE confirmation_emails (
+    id uuid PRIMARY KEY,
+    subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+    subscription_token TEXT NOT NULL,
+    recipient TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    html_body TEXT NOT NULL,
+    text_body TEXT NOT NULL,
+    status TEXT NOT NULL DEFAULT 'pending',
+    n_attempts INT NOT NULL DEFAULT 0,
+    next_attempt_at timestamptz NOT NULL DEFAULT now(),
+    last_error TEXT,
+    created_at timestamptz NOT NULL DEFAULT now(),
+    sent_at timestamptz
+);
+CREATE INDEX confirmation_emails_pending_idx ON confirmation_emails (next_attempt_at)
+    WHERE status = 'pending';
@@ -0,0 +1,48 @@
+-- One queue for all background work. `job_type` picks the handler and `payload` is its input.
+-- Finished jobs are deleted, jobs that ran out of attempts stay as `failed` with their last error.
+CREATE TABLE jobs (
+    id uuid PRIMARY KEY DEFAULT gen_random_uuid(),
+    job_type TEXT NOT NULL,
+    payload JSONB NOT NULL,
+    status TEXT NOT NULL DEFAULT 'queued',
+    -- Higher runs first, among jobs that are due
+    priority SMALLINT NOT NULL DEFAULT 0,
+    n_attempts INT NOT NULL DEFAULT 0,
+    max_attempts INT NOT NULL,
+    run_at timestamptz NOT NULL DEFAULT now(),
+    last_error TEXT,
+    created_at timestamptz NOT NULL DEFAULT now()
+);
+CREATE INDEX jobs_due_idx ON jobs (priority DESC, run_at) WHERE status = 'queued';
+
+INSERT INTO jobs (job_type, payload, max_attempts)
+SELECT
+    'deliver_issue',
+    jsonb_build_object(
+        'newsletter_issue_id', newsletter_issue_id,
+        'subscriber_email', subscriber_email
+    ),
+    30
+FROM issue_delivery_queue;
+DROP TABLE issue_delivery_queue;
+
+INSERT INTO jobs (job_type, payload, status, priority, n_attempts, max_attempts, run_at, last_error)
+SELECT
+    'send_confirmation_email',
+    jsonb_build_object(
+        'subscriber_id', subscriber_id,
+        'recipient', recipient,
+        'subscription_token', subscription_token,
+        'subject', subject,
+        'html_body', html_body,
+        'text_body', text_body
+    ),
+    CASE WHEN status = 'failed' THEN 'failed' ELSE 'queued' END,
+    10,
+    n_attempts,
+    30,
+    next_attempt_at,
+    last_error
+FROM confirmation_emails
+WHERE status IN ('pending', 'failed');
+DROP TABLE confirmation_emails;
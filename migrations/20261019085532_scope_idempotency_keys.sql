@@ -0,0 +1,3 @@
+-- Keys belong to a user or to an API token, both identified by their id
+ALTER TABLE idempotency DROP CONSTRAINT idempotency_user_id_fkey;
+ALTER TABLE idempotency RENAME COLUMN user_id TO scope_id;
@@ -0,0 +1,9 @@
+-- Saved bodies are gzipped, except those saved before
+ALTER TABLE idempotency ADD COLUMN response_body_gzipped BOOLEAN NOT NULL DEFAULT false;
+
+-- Cookies are no longer saved, they would be replayed to other sessions
+UPDATE idempotency
+SET response_headers = ARRAY(
+    SELECT h FROM unnest(response_headers) AS h WHERE lower((h).name) <> 'set-cookie'
+)
+WHERE response_headers IS NOT NULL;
@@ -0,0 +1,3 @@
+-- The path a key was used on, so the responses saved for a subscriber's requests can be erased
+-- with them. Unknown for keys saved before, they expire with the key TTL.
+ALTER TABLE idempotency ADD COLUMN request_path TEXT;
@@ -0,0 +1,26 @@
+use sqlx::PgExecutor;
+use uuid::Uuid;
+
+// Append an entry to the audit trail. `actor` identifies who performed the action,
+// e.g. `admin:<user_id>` or `subscriber:<subscriber_id>`.
+#[tracing::instrument(name = "Recording audit event", skip(executor, details))]
+pub async fn record_audit_event(
+    executor: impl PgExecutor<'_>,
+    actor: &str,
+    action: &str,
+    details: serde_json::Value,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO audit_log (id, actor, action, details, occurred_at)
+        VALUES ($1, $2, $3, $4, now())
+        "#,
+        Uuid::new_v4(),
+        actor,
+        action,
+        details
+    )
+    .execute(executor)
+    .await?;
+    Ok(())
+}
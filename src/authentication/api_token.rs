@@ -0,0 +1,113 @@
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{AUTHORIZATION, WWW_AUTHENTICATE};
+use actix_web::{web, HttpMessage, HttpResponse};
+use actix_web_lab::middleware::Next;
+use anyhow::Context;
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::utils::err500;
+
+const TOKEN_PREFIX: &str = "nl_";
+
+// The API token a request was authenticated with, inserted by `reject_invalid_api_tokens`
+#[derive(Copy, Clone, Debug)]
+pub struct ApiToken {
+    pub id: Uuid,
+    pub user_id: Uuid,
+}
+
+// Generates a random token and the hash to store for it. The token itself is only shown once.
+pub fn generate_api_token() -> (String, String) {
+    let mut rng = thread_rng();
+    let random: String = std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(char::from)
+        .take(40)
+        .collect();
+    let token = format!("{TOKEN_PREFIX}{random}");
+    let hash = hash_api_token(&token);
+    (token, hash)
+}
+
+// Tokens are long and random, a fast unsalted hash is enough to avoid storing them in clear
+pub fn hash_api_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+pub async fn reject_invalid_api_tokens(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, actix_web::Error> {
+    let token = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(|token| token.trim().to_string());
+    let api_token = match token {
+        Some(token) => {
+            let connection_pool = req
+                .app_data::<web::Data<PgPool>>()
+                .context("The connection pool is not registered")
+                .map_err(err500)?;
+            find_api_token(connection_pool, &token)
+                .await
+                .map_err(err500)?
+        }
+        None => None,
+    };
+    match api_token {
+        Some(api_token) => {
+            req.extensions_mut().insert(api_token);
+            next.call(req).await.map(|r| r.map_into_left_body())
+        }
+        None => {
+            let response = HttpResponse::Unauthorized()
+                .insert_header((WWW_AUTHENTICATE, "Bearer"))
+                .json(serde_json::json!({ "error": "A valid API token is required" }));
+            Ok(req.into_response(response).map_into_right_body())
+        }
+    }
+}
+
+#[tracing::instrument(name = "Look up an API token", skip_all)]
+async fn find_api_token(
+    connection_pool: &PgPool,
+    token: &str,
+) -> Result<Option<ApiToken>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        UPDATE api_tokens
+        SET last_used_at = now()
+        WHERE token_hash = $1 AND revoked_at IS NULL
+        RETURNING id, user_id
+        "#,
+        hash_api_token(token)
+    )
+    .fetch_optional(connection_pool)
+    .await
+    .context("Failed to look up the API token")?;
+    Ok(row.map(|r| ApiToken {
+        id: r.id,
+        user_id: r.user_id,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{generate_api_token, hash_api_token};
+
+    #[test]
+    fn generated_tokens_are_unique_and_match_their_hash() {
+        let (token, hash) = generate_api_token();
+        let (other_token, _) = generate_api_token();
+        assert_ne!(token, other_token);
+        assert!(token.starts_with("nl_"));
+        assert_eq!(hash_api_token(&token), hash);
+        assert_ne!(hash, token);
+    }
+}
@@ -1,5 +1,7 @@
+mod api_token;
 mod middleware;
 mod password;
 
+pub use api_token::{generate_api_token, hash_api_token, reject_invalid_api_tokens, ApiToken};
 pub use middleware::{reject_anonymous_users, UserId};
 pub use password::{change_password, validate_credentials, AuthError, Credentials};
@@ -0,0 +1,173 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// Stops calling a dependency that keeps failing, so callers fail fast instead of each waiting
+// for a timeout. After `failure_threshold` consecutive failures the circuit opens; once
+// `open_duration` has passed, a single call is let through to probe the dependency (half open)
+// and its outcome closes or re-opens the circuit.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    // Only used in logs and traces
+    name: &'static str,
+    failure_threshold: u32,
+    open_duration: Duration,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+impl CircuitState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CircuitState::Closed => "closed",
+            CircuitState::Open => "open",
+            CircuitState::HalfOpen => "half_open",
+        }
+    }
+}
+
+#[derive(Debug)]
+enum State {
+    Closed { consecutive_failures: u32 },
+    Open { until: Instant },
+    // A probe call is in flight. Another one is let through after `until`, in case the first
+    // one never reported back, e.g. because its future was dropped.
+    HalfOpen { until: Instant },
+}
+
+impl CircuitBreaker {
+    pub fn new(name: &'static str, failure_threshold: u32, open_duration: Duration) -> Self {
+        Self {
+            name,
+            failure_threshold,
+            open_duration,
+            state: Mutex::new(State::Closed {
+                consecutive_failures: 0,
+            }),
+        }
+    }
+
+    // Whether a call may go through now. Callers that are let through must report the outcome
+    // with `record_success` or `record_failure`.
+    pub fn try_acquire(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        match *state {
+            State::Closed { .. } => true,
+            State::Open { until } | State::HalfOpen { until } if Instant::now() >= until => {
+                tracing::info!(circuit = self.name, circuit.state = "half_open", "Probing");
+                *state = State::HalfOpen {
+                    until: Instant::now() + self.open_duration,
+                };
+                true
+            }
+            State::Open { .. } | State::HalfOpen { .. } => false,
+        }
+    }
+
+    pub fn record_success(&self) {
+        let mut state = self.state.lock().unwrap();
+        if !matches!(*state, State::Closed { .. }) {
+            tracing::info!(
+                circuit = self.name,
+                circuit.state = "closed",
+                "Circuit closed"
+            );
+        }
+        *state = State::Closed {
+            consecutive_failures: 0,
+        };
+    }
+
+    pub fn record_failure(&self) {
+        let mut state = self.state.lock().unwrap();
+        let consecutive_failures = match *state {
+            State::Closed {
+                consecutive_failures,
+            } => consecutive_failures + 1,
+            // The probe failed
+            State::HalfOpen { .. } => self.failure_threshold,
+            // Calls let through before the circuit opened
+            State::Open { .. } => return,
+        };
+        *state = if consecutive_failures >= self.failure_threshold {
+            tracing::warn!(
+                circuit = self.name,
+                circuit.state = "open",
+                circuit.open_seconds = self.open_duration.as_secs(),
+                "Circuit opened after {consecutive_failures} consecutive failures"
+            );
+            State::Open {
+                until: Instant::now() + self.open_duration,
+            }
+        } else {
+            State::Closed {
+                consecutive_failures,
+            }
+        };
+    }
+
+    pub fn state(&self) -> CircuitState {
+        match *self.state.lock().unwrap() {
+            State::Closed { .. } => CircuitState::Closed,
+            State::Open { .. } => CircuitState::Open,
+            State::HalfOpen { .. } => CircuitState::HalfOpen,
+        }
+    }
+
+    // How long until a call is let through again, if the circuit is open
+    pub fn retry_in(&self) -> Option<Duration> {
+        match *self.state.lock().unwrap() {
+            State::Open { until } => Some(until.saturating_duration_since(Instant::now())),
+            State::Closed { .. } | State::HalfOpen { .. } => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CircuitBreaker, CircuitState};
+    use std::time::Duration;
+
+    #[test]
+    fn the_circuit_opens_after_consecutive_failures_only() {
+        let breaker = CircuitBreaker::new("test", 3, Duration::from_secs(60));
+
+        breaker.record_failure();
+        breaker.record_failure();
+        breaker.record_success();
+        breaker.record_failure();
+        breaker.record_failure();
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        assert!(breaker.try_acquire());
+
+        breaker.record_failure();
+        assert_eq!(breaker.state(), CircuitState::Open);
+        assert!(!breaker.try_acquire());
+        assert!(breaker.retry_in().unwrap() > Duration::from_secs(59));
+    }
+
+    #[test]
+    fn a_single_probe_is_let_through_once_the_open_duration_passed() {
+        let breaker = CircuitBreaker::new("test", 1, Duration::from_millis(10));
+        breaker.record_failure();
+        std::thread::sleep(Duration::from_millis(20));
+
+        assert!(breaker.try_acquire());
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        assert!(!breaker.try_acquire());
+
+        // A failed probe opens the circuit again, a successful one closes it
+        breaker.record_failure();
+        assert_eq!(breaker.state(), CircuitState::Open);
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(breaker.try_acquire());
+        breaker.record_success();
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        assert!(breaker.try_acquire());
+    }
+}
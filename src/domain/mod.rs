@@ -1,7 +1,15 @@
 mod new_subscriber;
+mod segment;
+mod subscriber_attributes;
 mod subscriber_email;
 mod subscriber_name;
+mod subscriber_tags;
+mod suppression_entry;
 
 pub use new_subscriber::NewSubscriber;
+pub use segment::{AttributeFilter, Segment};
+pub use subscriber_attributes::SubscriberAttributes;
 pub use subscriber_email::SubscriberEmail;
 pub use subscriber_name::SubscriberName;
+pub use subscriber_tags::SubscriberTags;
+pub use suppression_entry::SuppressionEntry;
@@ -0,0 +1,146 @@
+use chrono::{DateTime, NaiveDate, Utc};
+
+use crate::domain::SubscriberTags;
+
+// Restricts the recipients of an issue, on top of the lists it is sent to.
+// Every filter that is set must match.
+#[derive(Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Segment {
+    // Subscribers with at least one of these tags
+    pub tags: Vec<String>,
+    pub attribute: Option<AttributeFilter>,
+    pub subscribed_before: Option<NaiveDate>,
+    // Inclusive
+    pub subscribed_after: Option<NaiveDate>,
+}
+
+// Subscribers with the attribute, and with this value if one is given.
+// Values are compared as text, so `42` matches both `42` and `"42"`.
+#[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct AttributeFilter {
+    pub name: String,
+    pub value: Option<String>,
+}
+
+impl Segment {
+    // Parses the filters of the publish form, where blank fields are not filtered on
+    pub fn parse(
+        tags: &str,
+        attribute_name: &str,
+        attribute_value: &str,
+        subscribed_before: &str,
+        subscribed_after: &str,
+    ) -> Result<Segment, String> {
+        let tags = SubscriberTags::parse(tags)?.inner();
+        let attribute = match (attribute_name.trim(), attribute_value.trim()) {
+            ("", "") => None,
+            ("", _) => return Err("The attribute to filter on needs a name".into()),
+            (name, "") => Some(AttributeFilter {
+                name: name.into(),
+                value: None,
+            }),
+            (name, value) => Some(AttributeFilter {
+                name: name.into(),
+                value: Some(value.into()),
+            }),
+        };
+        let segment = Segment {
+            tags,
+            attribute,
+            subscribed_before: parse_date(subscribed_before)?,
+            subscribed_after: parse_date(subscribed_after)?,
+        };
+        if let (Some(before), Some(after)) = (segment.subscribed_before, segment.subscribed_after) {
+            if before <= after {
+                return Err("No subscriber can match these subscription dates".into());
+            }
+        }
+        Ok(segment)
+    }
+
+    // Whether the segment lets every subscriber of the lists through
+    pub fn is_empty(&self) -> bool {
+        self == &Segment::default()
+    }
+
+    pub fn attribute_name(&self) -> Option<&str> {
+        self.attribute.as_ref().map(|a| a.name.as_str())
+    }
+
+    pub fn attribute_value(&self) -> Option<&str> {
+        self.attribute.as_ref().and_then(|a| a.value.as_deref())
+    }
+
+    // Dates are midnight UTC
+    pub fn subscribed_before(&self) -> Option<DateTime<Utc>> {
+        self.subscribed_before.map(start_of_day)
+    }
+
+    pub fn subscribed_after(&self) -> Option<DateTime<Utc>> {
+        self.subscribed_after.map(start_of_day)
+    }
+}
+
+fn parse_date(s: &str) -> Result<Option<NaiveDate>, String> {
+    let s = s.trim();
+    if s.is_empty() {
+        return Ok(None);
+    }
+    NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .map(Some)
+        .map_err(|_| format!("{s} is not a date in the YYYY-MM-DD format"))
+}
+
+fn start_of_day(date: NaiveDate) -> DateTime<Utc> {
+    DateTime::from_utc(date.and_hms_opt(0, 0, 0).unwrap(), Utc)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::Segment;
+    use chrono::NaiveDate;
+    use claims::{assert_err, assert_ok};
+
+    #[test]
+    fn blank_fields_give_an_empty_segment() {
+        let segment = assert_ok!(Segment::parse("", " ", "", "", ""));
+        assert!(segment.is_empty());
+    }
+
+    #[test]
+    fn every_filter_is_parsed() {
+        let segment = assert_ok!(Segment::parse(
+            "VIP, beta",
+            "country",
+            "DE",
+            "2026-02-01",
+            "2026-01-01"
+        ));
+        assert_eq!(segment.tags, vec!["beta", "vip"]);
+        assert_eq!(segment.attribute_name(), Some("country"));
+        assert_eq!(segment.attribute_value(), Some("DE"));
+        assert_eq!(
+            segment.subscribed_before,
+            NaiveDate::from_ymd_opt(2026, 2, 1)
+        );
+        assert_eq!(
+            segment.subscribed_after().unwrap().to_rfc3339(),
+            "2026-01-01T00:00:00+00:00"
+        );
+    }
+
+    #[test]
+    fn an_attribute_name_alone_filters_on_having_the_attribute() {
+        let segment = assert_ok!(Segment::parse("", "country", "", "", ""));
+        assert_eq!(segment.attribute_name(), Some("country"));
+        assert_eq!(segment.attribute_value(), None);
+    }
+
+    #[test]
+    fn invalid_filters_are_rejected() {
+        assert_err!(Segment::parse("", "", "DE", "", ""));
+        assert_err!(Segment::parse("", "", "", "01/02/2026", ""));
+        assert_err!(Segment::parse("", "", "", "2026-01-01", "2026-02-01"));
+        assert_err!(Segment::parse("not a tag!", "", "", "", ""));
+    }
+}
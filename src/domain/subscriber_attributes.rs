@@ -0,0 +1,97 @@
+use serde_json::{Map, Value};
+
+const MAX_ATTRIBUTES: usize = 50;
+
+// Free-form data about a subscriber, e.g. `{"country": "DE"}`, that issues can be targeted on
+#[derive(Debug, Default, PartialEq)]
+pub struct SubscriberAttributes(Map<String, Value>);
+
+impl SubscriberAttributes {
+    pub fn parse(value: Value) -> Result<SubscriberAttributes, String> {
+        let Value::Object(attributes) = value else {
+            return Err("Attributes must be a JSON object".into());
+        };
+        if attributes.len() > MAX_ATTRIBUTES {
+            return Err(format!(
+                "A subscriber can't have more than {MAX_ATTRIBUTES} attributes"
+            ));
+        }
+        if let Some(name) = attributes
+            .keys()
+            .find(|name| name.trim().is_empty() || name.len() > 64)
+        {
+            return Err(format!("{name:?} is not a valid attribute name"));
+        }
+        Ok(Self(attributes))
+    }
+
+    pub fn parse_str(s: &str) -> Result<SubscriberAttributes, String> {
+        if s.trim().is_empty() {
+            return Ok(Self::default());
+        }
+        let value = serde_json::from_str(s).map_err(|e| format!("Invalid attributes: {e}"))?;
+        Self::parse(value)
+    }
+
+    pub fn insert(&mut self, name: String, value: Value) {
+        self.0.insert(name, value);
+    }
+
+    // Applies `patch` on top of these attributes, a `null` value removes the attribute
+    pub fn merge(self, patch: SubscriberAttributes) -> Result<SubscriberAttributes, String> {
+        let mut attributes = self.0;
+        for (name, value) in patch.0 {
+            if value.is_null() {
+                attributes.remove(&name);
+            } else {
+                attributes.insert(name, value);
+            }
+        }
+        Self::parse(Value::Object(attributes))
+    }
+
+    pub fn into_value(self) -> Value {
+        Value::Object(self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::SubscriberAttributes;
+    use claims::{assert_err, assert_ok};
+    use serde_json::json;
+
+    #[test]
+    fn a_json_object_is_valid() {
+        let attributes = assert_ok!(SubscriberAttributes::parse_str(
+            r#"{"country": "DE", "age": 42}"#
+        ));
+        assert_eq!(attributes.into_value(), json!({"country": "DE", "age": 42}));
+    }
+
+    #[test]
+    fn an_empty_string_has_no_attributes() {
+        let attributes = assert_ok!(SubscriberAttributes::parse_str(""));
+        assert_eq!(attributes.into_value(), json!({}));
+    }
+
+    #[test]
+    fn other_json_values_are_rejected() {
+        assert_err!(SubscriberAttributes::parse_str("[1, 2]"));
+        assert_err!(SubscriberAttributes::parse_str("\"DE\""));
+        assert_err!(SubscriberAttributes::parse_str("{not json"));
+    }
+
+    #[test]
+    fn merging_overwrites_and_removes_attributes() {
+        let attributes = assert_ok!(SubscriberAttributes::parse(json!({"a": 1, "b": 2})));
+        let patch = assert_ok!(SubscriberAttributes::parse(json!({"b": null, "c": 3})));
+        let merged = assert_ok!(attributes.merge(patch));
+        assert_eq!(merged.into_value(), json!({"a": 1, "c": 3}));
+    }
+
+    #[test]
+    fn blank_attribute_names_are_rejected() {
+        assert_err!(SubscriberAttributes::parse(json!({" ": 1})));
+    }
+}
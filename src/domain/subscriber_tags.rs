@@ -0,0 +1,74 @@
+// Labels used to target a subset of subscribers, e.g. `beta-testers`. Stored lowercased, sorted
+// and without duplicates.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct SubscriberTags(Vec<String>);
+
+impl SubscriberTags {
+    // Parses tags separated by commas (or semicolons, as CSV files can't use commas in a cell
+    // without quoting it)
+    pub fn parse(s: &str) -> Result<SubscriberTags, String> {
+        let mut tags = Vec::new();
+        for tag in s.split([',', ';']) {
+            let tag = tag.trim().to_lowercase();
+            if tag.is_empty() {
+                continue;
+            }
+            let is_valid = tag.len() <= 64
+                && tag
+                    .chars()
+                    .all(|c| c.is_alphanumeric() || c == '-' || c == '_');
+            if !is_valid {
+                return Err(format!(
+                    "{tag} is not a valid tag, use letters, digits, dashes and underscores"
+                ));
+            }
+            tags.push(tag);
+        }
+        tags.sort();
+        tags.dedup();
+        Ok(Self(tags))
+    }
+
+    pub fn from_list(tags: Vec<String>) -> Result<SubscriberTags, String> {
+        Self::parse(&tags.join(","))
+    }
+
+    pub fn inner(self) -> Vec<String> {
+        self.0
+    }
+}
+
+impl AsRef<[String]> for SubscriberTags {
+    fn as_ref(&self) -> &[String] {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::SubscriberTags;
+    use claims::{assert_err, assert_ok};
+
+    #[test]
+    fn tags_are_normalized() {
+        let tags = assert_ok!(SubscriberTags::parse(" VIP, beta;vip,,"));
+        assert_eq!(tags.as_ref(), ["beta".to_string(), "vip".to_string()]);
+    }
+
+    #[test]
+    fn an_empty_string_has_no_tags() {
+        let tags = assert_ok!(SubscriberTags::parse("  "));
+        assert!(tags.as_ref().is_empty());
+    }
+
+    #[test]
+    fn tags_with_invalid_chars_are_rejected() {
+        assert_err!(SubscriberTags::parse("good, not good"));
+        assert_err!(SubscriberTags::parse("<b>"));
+    }
+
+    #[test]
+    fn tags_longer_than_64_chars_are_rejected() {
+        assert_err!(SubscriberTags::parse(&"a".repeat(65)));
+    }
+}
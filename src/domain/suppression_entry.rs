@@ -0,0 +1,73 @@
+use validator::validate_email;
+
+// An entry of the suppression list: an email address, or a domain to suppress every address at.
+// Stored lowercase, since neither the provider nor subscribers are consistent about case.
+#[derive(Debug, PartialEq, Eq)]
+pub struct SuppressionEntry(String);
+
+impl SuppressionEntry {
+    pub fn parse(s: &str) -> Result<SuppressionEntry, String> {
+        let entry = s.trim().to_lowercase();
+        let is_valid = if entry.contains('@') {
+            validate_email(&entry)
+        } else {
+            is_domain(&entry)
+        };
+        if is_valid {
+            Ok(Self(entry))
+        } else {
+            Err(format!(
+                "{} is neither an email address nor a domain",
+                s.trim()
+            ))
+        }
+    }
+
+    pub fn is_domain(&self) -> bool {
+        !self.0.contains('@')
+    }
+}
+
+// e.g. `example.com`: at least two labels of letters, digits and hyphens
+fn is_domain(s: &str) -> bool {
+    let labels: Vec<&str> = s.split('.').collect();
+    labels.len() >= 2
+        && labels.iter().all(|label| {
+            !label.is_empty()
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+impl AsRef<str> for SuppressionEntry {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SuppressionEntry;
+    use claims::{assert_err, assert_ok};
+
+    #[test]
+    fn addresses_and_domains_are_lowercased() {
+        let entry = assert_ok!(SuppressionEntry::parse(" Ann@Test.com "));
+        assert_eq!(entry.as_ref(), "ann@test.com");
+        assert!(!entry.is_domain());
+
+        let entry = assert_ok!(SuppressionEntry::parse("Mail.Example.com"));
+        assert_eq!(entry.as_ref(), "mail.example.com");
+        assert!(entry.is_domain());
+    }
+
+    #[test]
+    fn anything_else_is_rejected() {
+        assert_err!(SuppressionEntry::parse(""));
+        assert_err!(SuppressionEntry::parse("localhost"));
+        assert_err!(SuppressionEntry::parse("ann@"));
+        assert_err!(SuppressionEntry::parse("*.example.com"));
+        assert_err!(SuppressionEntry::parse("-bad.example.com"));
+    }
+}
@@ -1,14 +1,115 @@
-use reqwest::Client;
+use reqwest::{Client, StatusCode};
 use secrecy::{ExposeSecret, Secret};
+use std::sync::Arc;
+use std::time::Duration;
 
+use crate::circuit_breaker::{CircuitBreaker, CircuitState};
 use crate::domain::SubscriberEmail;
 
-#[derive(Debug)]
+// Postmark error codes that mean our account or request is wrong, not the recipient.
+// See https://postmarkapp.com/developer/api/overview#error-codes
+const CONFIGURATION_ERROR_CODES: [i64; 6] = [
+    10,  // Bad or missing API token
+    400, // Sender signature not found
+    401, // Sender signature not confirmed
+    405, // Not allowed to send, e.g. the account ran out of credits
+    412, // Account is pending approval
+    413, // Account may not send, sending was restricted
+];
+
+// Why an email could not be sent, classified by what the caller should do about it
+#[derive(thiserror::Error, Debug)]
+pub enum EmailClientError {
+    // Timeouts, network errors, rate limiting or an outage on Postmark's side: sending the
+    // same email again later may work
+    #[error("The email could not be sent for now")]
+    Transient(#[source] ProviderError),
+    // Postmark will never deliver to this recipient, e.g. an inactive or invalid address
+    #[error("The email provider refused the recipient")]
+    Recipient(#[source] ProviderError),
+    // No email can be sent until our configuration is fixed, e.g. a wrong server token
+    #[error("The email client is misconfigured")]
+    Configuration(#[source] ProviderError),
+}
+
+impl EmailClientError {
+    // Postmark's `ErrorCode`, when it responded with one
+    pub fn error_code(&self) -> Option<i64> {
+        match self {
+            Self::Transient(e) | Self::Recipient(e) | Self::Configuration(e) => match e {
+                ProviderError::Response { error_code, .. } => *error_code,
+                ProviderError::Request(_) | ProviderError::CircuitOpen => None,
+            },
+        }
+    }
+
+    // Whether Postmark may have accepted the email despite the error, e.g. the request timed
+    // out after it was sent. Only errors that happened before the request was sent, or that
+    // Postmark answered, are sure to mean the email was not sent.
+    pub fn may_have_been_sent(&self) -> bool {
+        match self {
+            Self::Transient(e) | Self::Recipient(e) | Self::Configuration(e) => match e {
+                ProviderError::Request(e) => !e.is_connect(),
+                ProviderError::Response { .. } | ProviderError::CircuitOpen => false,
+            },
+        }
+    }
+
+    fn from_response(status: StatusCode, body: Option<PostmarkError>) -> Self {
+        let (error_code, message) = match body {
+            Some(body) => (Some(body.error_code), body.message),
+            None => (None, "no error details".to_string()),
+        };
+        let error = ProviderError::Response {
+            status: status.as_u16(),
+            error_code,
+            message,
+        };
+        if status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error() {
+            Self::Transient(error)
+        } else if status == StatusCode::UNAUTHORIZED
+            || error_code.is_some_and(|code| CONFIGURATION_ERROR_CODES.contains(&code))
+        {
+            Self::Configuration(error)
+        } else if status == StatusCode::UNPROCESSABLE_ENTITY {
+            // e.g. 406, inactive recipient, or 300, invalid email request
+            Self::Recipient(error)
+        } else {
+            Self::Configuration(error)
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ProviderError {
+    #[error(transparent)]
+    Request(#[from] reqwest::Error),
+    #[error("Postmark responded {status}: {message}")]
+    Response {
+        status: u16,
+        // Postmark's `ErrorCode`, missing if the response had no error body
+        error_code: Option<i64>,
+        message: String,
+    },
+    // Postmark was not called, it failed too many times in a row recently
+    #[error("Postmark keeps failing, not calling it for now")]
+    CircuitOpen,
+}
+
+impl From<reqwest::Error> for EmailClientError {
+    fn from(e: reqwest::Error) -> Self {
+        Self::Transient(ProviderError::Request(e))
+    }
+}
+
+// Clones share the same circuit breaker
+#[derive(Debug, Clone)]
 pub struct EmailClient {
     base_url: reqwest::Url,
     http_client: Client,
     sender: SubscriberEmail,
     token: Secret<String>,
+    circuit_breaker: Arc<CircuitBreaker>,
 }
 
 impl EmailClient {
@@ -24,15 +125,104 @@ impl EmailClient {
             base_url: reqwest::Url::parse(base_url.as_str()).expect("Could not parse URL"),
             sender,
             token,
+            circuit_breaker: Arc::new(CircuitBreaker::new(
+                "email_provider",
+                5,
+                Duration::from_secs(30),
+            )),
         }
     }
+
+    // Opens the circuit after `failure_threshold` transient failures in a row, sends then fail
+    // right away with `ProviderError::CircuitOpen` until `open_duration` has passed
+    pub fn with_circuit_breaker(mut self, failure_threshold: u32, open_duration: Duration) -> Self {
+        self.circuit_breaker = Arc::new(CircuitBreaker::new(
+            "email_provider",
+            failure_threshold,
+            open_duration,
+        ));
+        self
+    }
+
+    pub fn circuit_state(&self) -> CircuitState {
+        self.circuit_breaker.state()
+    }
+
+    // How long until Postmark is called again, if the circuit is open
+    pub fn circuit_open_for(&self) -> Option<Duration> {
+        self.circuit_breaker.retry_in()
+    }
+
+    // Returns the MessageID Postmark assigned to the email, if its response included one
     pub async fn send_email(
         &self,
         recipient: &SubscriberEmail,
         subject: &str,
         html_content: &str,
         text_content: &str,
-    ) -> Result<(), reqwest::Error> {
+    ) -> Result<Option<String>, EmailClientError> {
+        self.send(recipient, subject, html_content, text_content, None)
+            .await
+    }
+
+    // For emails that must reach the recipient at most once, such as an issue. Postmark does
+    // not deduplicate emails: the caller must not send again an email that
+    // `may_have_been_sent`. The key is only stored in the message metadata, to find in
+    // Postmark's activity whether such an email went out.
+    pub async fn send_email_once(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        idempotency_key: &str,
+    ) -> Result<Option<String>, EmailClientError> {
+        self.send(
+            recipient,
+            subject,
+            html_content,
+            text_content,
+            Some(idempotency_key),
+        )
+        .await
+    }
+
+    async fn send(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        idempotency_key: Option<&str>,
+    ) -> Result<Option<String>, EmailClientError> {
+        if !self.circuit_breaker.try_acquire() {
+            return Err(EmailClientError::Transient(ProviderError::CircuitOpen));
+        }
+        let outcome = self
+            .call_postmark(
+                recipient,
+                subject,
+                html_content,
+                text_content,
+                idempotency_key,
+            )
+            .await;
+        // Any answer other than a transient error shows Postmark is up
+        match &outcome {
+            Err(EmailClientError::Transient(_)) => self.circuit_breaker.record_failure(),
+            _ => self.circuit_breaker.record_success(),
+        }
+        outcome
+    }
+
+    async fn call_postmark(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        idempotency_key: Option<&str>,
+    ) -> Result<Option<String>, EmailClientError> {
         let url =
             reqwest::Url::join(&self.base_url, "/email").expect("Could not build request url");
         let request_body = SendEmailRequest {
@@ -41,16 +231,29 @@ impl EmailClient {
             subject,
             html_body: html_content,
             text_body: text_content,
+            metadata: idempotency_key.map(|key| EmailMetadata {
+                idempotency_key: key,
+            }),
         };
-        let _builder = self
+        let response = self
             .http_client
             .post(url.as_str())
             .json(&request_body)
             .header("X-Postmark-Server-Token", self.token.expose_secret())
             .send()
-            .await?
-            .error_for_status()?;
-        Ok(())
+            .await?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.json::<PostmarkError>().await.ok();
+            return Err(EmailClientError::from_response(status, body));
+        }
+        // The email was accepted whether or not the body can be read
+        let message_id = response
+            .json::<SendEmailResponse>()
+            .await
+            .ok()
+            .map(|r| r.message_id);
+        Ok(message_id)
     }
 }
 
@@ -64,18 +267,40 @@ struct SendEmailRequest<'a> {
     subject: &'a str,
     html_body: &'a str,
     text_body: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    metadata: Option<EmailMetadata<'a>>,
+}
+
+#[derive(serde::Serialize)]
+struct EmailMetadata<'a> {
+    idempotency_key: &'a str,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct PostmarkError {
+    error_code: i64,
+    message: String,
+}
+
+#[derive(serde::Deserialize)]
+struct SendEmailResponse {
+    #[serde(rename = "MessageID")]
+    message_id: String,
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::circuit_breaker::CircuitState;
     use crate::domain::SubscriberEmail;
-    use crate::email_client::EmailClient;
+    use crate::email_client::{EmailClient, EmailClientError, ProviderError};
     use claims::{assert_err, assert_ok};
     use fake::faker::internet::en::SafeEmail;
     use fake::faker::lorem::en::{Paragraph, Sentence};
     use fake::{Fake, Faker};
     use secrecy::Secret;
-    use wiremock::matchers::{any, header, header_exists, method, path};
+    use std::time::Duration;
+    use wiremock::matchers::{any, body_partial_json, header, header_exists, method, path};
     use wiremock::Request;
     use wiremock::{Mock, MockServer, ResponseTemplate};
 
@@ -136,6 +361,57 @@ mod tests {
         assert_ok!(outcome);
     }
 
+    #[tokio::test]
+    async fn send_email_returns_the_message_id() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "To": "ann@test.com",
+                "SubmittedAt": "2026-10-19T07:00:00Z",
+                "MessageID": "b7bc2f4a-e38e-4336-af7d-e6c392c2f817",
+                "ErrorCode": 0,
+                "Message": "OK"
+            })))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let message_id = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        assert_eq!(
+            assert_ok!(message_id).as_deref(),
+            Some("b7bc2f4a-e38e-4336-af7d-e6c392c2f817")
+        );
+    }
+
+    #[tokio::test]
+    async fn send_email_once_passes_the_idempotency_key_as_metadata() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        Mock::given(body_partial_json(serde_json::json!({
+            "Metadata": { "idempotency_key": "issue:subscriber" }
+        })))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+        let outcome = email_client
+            .send_email_once(
+                &email(),
+                &subject(),
+                &content(),
+                &content(),
+                "issue:subscriber",
+            )
+            .await;
+
+        assert_ok!(outcome);
+    }
+
     #[tokio::test]
     async fn send_email_fails_if_server_returns_a_500() {
         // Arrange
@@ -154,6 +430,65 @@ mod tests {
         assert_err!(outcome);
     }
 
+    #[tokio::test]
+    async fn failures_are_classified_by_what_can_be_done_about_them() {
+        let cases = [
+            (500, None, "transient"),
+            (429, None, "transient"),
+            (
+                422,
+                Some((
+                    406,
+                    "You tried to send to a recipient that has been marked as inactive.",
+                )),
+                "recipient",
+            ),
+            (422, Some((300, "Invalid 'To' address.")), "recipient"),
+            (
+                422,
+                Some((
+                    400,
+                    "The 'From' address you supplied is not a Sender Signature.",
+                )),
+                "configuration",
+            ),
+            (
+                401,
+                Some((10, "No Account or Server API tokens were supplied.")),
+                "configuration",
+            ),
+        ];
+        for (status, body, expected) in cases {
+            let mock_server = MockServer::start().await;
+            let email_client = email_client(mock_server.uri());
+            let mut response = ResponseTemplate::new(status);
+            if let Some((error_code, message)) = body {
+                response = response.set_body_json(serde_json::json!({
+                    "ErrorCode": error_code,
+                    "Message": message
+                }));
+            }
+            Mock::given(any())
+                .respond_with(response)
+                .mount(&mock_server)
+                .await;
+
+            let error = assert_err!(
+                email_client
+                    .send_email(&email(), &subject(), &content(), &content())
+                    .await
+            );
+
+            let class = match &error {
+                EmailClientError::Transient(_) => "transient",
+                EmailClientError::Recipient(_) => "recipient",
+                EmailClientError::Configuration(_) => "configuration",
+            };
+            assert_eq!(class, expected, "{status} {body:?}");
+            assert_eq!(error.error_code(), body.map(|(code, _)| code));
+        }
+    }
+
     #[tokio::test]
     async fn send_email_times_out_if_server_takes_too_long() {
         // Arrange
@@ -170,7 +505,67 @@ mod tests {
             .send_email(&email(), &subject(), &content(), &content())
             .await;
         // Assert
-        assert_err!(outcome);
+        let error = assert_err!(outcome);
+        assert!(matches!(
+            error,
+            EmailClientError::Transient(ProviderError::Request(_))
+        ));
+        // Postmark may have accepted it before the timeout
+        assert!(error.may_have_been_sent());
+    }
+
+    #[tokio::test]
+    async fn errors_before_the_request_is_sent_or_answered_by_postmark_were_not_sent() {
+        let mock_server = MockServer::start().await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(503))
+            .mount(&mock_server)
+            .await;
+        let client = email_client(mock_server.uri());
+
+        let error = assert_err!(
+            client
+                .send_email(&email(), &subject(), &content(), &content())
+                .await
+        );
+        assert!(!error.may_have_been_sent());
+
+        // Nothing listens on the port anymore
+        drop(mock_server);
+        let error = assert_err!(
+            client
+                .send_email(&email(), &subject(), &content(), &content())
+                .await
+        );
+        assert!(!error.may_have_been_sent());
+    }
+
+    #[tokio::test]
+    async fn postmark_is_not_called_while_the_circuit_is_open() {
+        let mock_server = MockServer::start().await;
+        let email_client =
+            email_client(mock_server.uri()).with_circuit_breaker(2, Duration::from_secs(60));
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(503))
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+
+        for _ in 0..2 {
+            let _ = email_client
+                .send_email(&email(), &subject(), &content(), &content())
+                .await;
+        }
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        assert!(matches!(
+            assert_err!(outcome),
+            EmailClientError::Transient(ProviderError::CircuitOpen)
+        ));
+        assert_eq!(email_client.circuit_state(), CircuitState::Open);
+        assert!(email_client.circuit_open_for().is_some());
     }
 
     // Generate random email subject
@@ -0,0 +1,447 @@
+use anyhow::Context;
+use htmlescape::encode_minimal;
+use sqlx::PgPool;
+use std::cell::Cell;
+use std::path::Path;
+
+// Placeholders each email can use
+pub const CONFIRMATION_PLACEHOLDERS: &[&str] = &["name", "list_name", "confirmation_link"];
+pub const DATA_REQUEST_PLACEHOLDERS: &[&str] = &["download_link", "erase_link", "expires_in_hours"];
+// Newsletter issues can also use `{{ attributes.<name> }}`, and give any placeholder a fallback
+// for when it is empty with `{{ attributes.country | default: "your country" }}`
+pub const ISSUE_PLACEHOLDERS: &[&str] = &["name", "email", "unsubscribe_url"];
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum PlaceholderError {
+    #[error("Unknown placeholder `{0}`")]
+    Unknown(String),
+    #[error("A `{{{{` is not closed by a matching `}}}}`")]
+    Unclosed,
+    #[error("Invalid filter in `{0}`, only `| default: \"value\"` is supported")]
+    InvalidFilter(String),
+}
+
+pub struct RenderedEmail {
+    pub subject: String,
+    pub html: String,
+    pub text: String,
+}
+
+// The content of an email, with `{{ placeholder }}`s still to be filled in
+#[derive(Clone, Debug)]
+pub struct EmailTemplate {
+    pub subject: String,
+    pub html: String,
+    pub text: String,
+}
+
+impl EmailTemplate {
+    // Fails on the first placeholder that is not in `placeholders`
+    pub fn validate(&self, placeholders: &[&str]) -> Result<(), PlaceholderError> {
+        let values: Vec<_> = placeholders.iter().map(|&p| (p, "")).collect();
+        self.render(&values).map(|_| ())
+    }
+
+    pub fn render(&self, values: &[(&str, &str)]) -> Result<RenderedEmail, PlaceholderError> {
+        Ok(RenderedEmail {
+            subject: render_placeholders(&self.subject, values, false)?,
+            html: render_placeholders(&self.html, values, true)?,
+            text: render_placeholders(&self.text, values, false)?,
+        })
+    }
+}
+
+// Who a newsletter issue is being rendered for
+pub struct Recipient<'a> {
+    pub name: &'a str,
+    pub email: &'a str,
+    pub unsubscribe_url: &'a str,
+    pub attributes: &'a serde_json::Value,
+}
+
+impl EmailTemplate {
+    // Fails on the first placeholder an issue can't use
+    pub fn validate_issue(&self) -> Result<(), PlaceholderError> {
+        let recipient = Recipient {
+            name: "",
+            email: "",
+            unsubscribe_url: "",
+            attributes: &serde_json::Value::Null,
+        };
+        self.render_issue(&recipient).map(|_| ())
+    }
+
+    // Bodies that don't link to `{{ unsubscribe_url }}` themselves get a footer with the link
+    pub fn render_issue(&self, recipient: &Recipient) -> Result<RenderedEmail, PlaceholderError> {
+        let links_to_unsubscribe = Cell::new(false);
+        let mut resolve = |expression: &str| {
+            let (name, default) = parse_filter(expression)?;
+            let value = match name {
+                "name" => recipient.name.to_string(),
+                "email" => recipient.email.to_string(),
+                "unsubscribe_url" => {
+                    links_to_unsubscribe.set(true);
+                    recipient.unsubscribe_url.to_string()
+                }
+                _ => match name.strip_prefix("attributes.") {
+                    Some(attribute) if !attribute.is_empty() => {
+                        attribute_text(&recipient.attributes[attribute])
+                    }
+                    _ => return Err(PlaceholderError::Unknown(name.to_string())),
+                },
+            };
+            match default {
+                Some(default) if value.is_empty() => Ok(default.to_string()),
+                _ => Ok(value),
+            }
+        };
+        let subject = render_with(&self.subject, false, &mut resolve)?;
+        links_to_unsubscribe.set(false);
+        let mut html = render_with(&self.html, true, &mut resolve)?;
+        if !links_to_unsubscribe.take() {
+            html.push_str(&format!(
+                r#"<p><a href="{}">Unsubscribe</a></p>"#,
+                encode_minimal(recipient.unsubscribe_url)
+            ));
+        }
+        let mut text = render_with(&self.text, false, &mut resolve)?;
+        if !links_to_unsubscribe.get() {
+            text.push_str(&format!("\n\nUnsubscribe: {}", recipient.unsubscribe_url));
+        }
+        Ok(RenderedEmail {
+            subject,
+            html,
+            text,
+        })
+    }
+}
+
+// Default email contents. The bodies are compiled in from `templates/emails/`, and any of them
+// can be replaced at runtime by a file with the same name (e.g. `confirmation.html`) in the
+// configured templates directory. The confirmation email can further be edited by admins, see
+// `confirmation_template`.
+#[derive(Clone, Debug)]
+pub struct EmailTemplates {
+    confirmation: EmailTemplate,
+    data_request: EmailTemplate,
+}
+
+impl Default for EmailTemplates {
+    fn default() -> Self {
+        Self::builtin()
+    }
+}
+
+impl EmailTemplates {
+    pub fn builtin() -> Self {
+        Self {
+            confirmation: EmailTemplate {
+                subject: "Welcome!".into(),
+                html: include_str!("../templates/emails/confirmation.html").into(),
+                text: include_str!("../templates/emails/confirmation.txt").into(),
+            },
+            data_request: EmailTemplate {
+                subject: "Your data request".into(),
+                html: include_str!("../templates/emails/data_request.html").into(),
+                text: include_str!("../templates/emails/data_request.txt").into(),
+            },
+        }
+    }
+
+    // Fails if a replacement uses a placeholder its email doesn't provide, so a typo is caught
+    // at startup rather than when sending
+    pub fn load(directory: &Path) -> Result<Self, anyhow::Error> {
+        let mut templates = Self::builtin();
+        for (email, template, placeholders) in [
+            (
+                "confirmation",
+                &mut templates.confirmation,
+                CONFIRMATION_PLACEHOLDERS,
+            ),
+            (
+                "data_request",
+                &mut templates.data_request,
+                DATA_REQUEST_PLACEHOLDERS,
+            ),
+        ] {
+            for (extension, body) in [("html", &mut template.html), ("txt", &mut template.text)] {
+                let path = directory.join(format!("{email}.{extension}"));
+                if !path.exists() {
+                    continue;
+                }
+                let replacement = std::fs::read_to_string(&path)
+                    .with_context(|| format!("Failed to read {}", path.display()))?;
+                let values: Vec<_> = placeholders.iter().map(|&p| (p, "")).collect();
+                render_placeholders(&replacement, &values, false)
+                    .with_context(|| format!("Invalid email template {}", path.display()))?;
+                *body = replacement;
+            }
+        }
+        Ok(templates)
+    }
+
+    pub fn default_confirmation(&self) -> &EmailTemplate {
+        &self.confirmation
+    }
+
+    pub fn data_request(
+        &self,
+        download_link: &str,
+        erase_link: &str,
+        expires_in_hours: i64,
+    ) -> Result<RenderedEmail, anyhow::Error> {
+        let hours = expires_in_hours.to_string();
+        Ok(self.data_request.render(&[
+            ("download_link", download_link),
+            ("erase_link", erase_link),
+            ("expires_in_hours", &hours),
+        ])?)
+    }
+}
+
+// The confirmation email as edited by an admin, or the default one if it was never edited
+#[tracing::instrument(name = "Get the confirmation email template", skip_all)]
+pub async fn confirmation_template(
+    connection_pool: &PgPool,
+    email_templates: &EmailTemplates,
+) -> Result<EmailTemplate, sqlx::Error> {
+    let custom = get_custom_email("confirmation", connection_pool).await?;
+    Ok(custom.unwrap_or_else(|| email_templates.default_confirmation().clone()))
+}
+
+pub async fn get_custom_email(
+    email: &str,
+    connection_pool: &PgPool,
+) -> Result<Option<EmailTemplate>, sqlx::Error> {
+    sqlx::query_as!(
+        EmailTemplate,
+        "SELECT subject, html, text FROM custom_emails WHERE email = $1",
+        email
+    )
+    .fetch_optional(connection_pool)
+    .await
+}
+
+pub fn render_confirmation(
+    template: &EmailTemplate,
+    name: &str,
+    list_name: &str,
+    confirmation_link: &str,
+) -> Result<RenderedEmail, PlaceholderError> {
+    template.render(&[
+        ("name", name),
+        ("list_name", list_name),
+        ("confirmation_link", confirmation_link),
+    ])
+}
+
+// Replaces every `{{ name }}` in `template` with its value, HTML-escaped if `escape_html` is set
+pub fn render_placeholders(
+    template: &str,
+    values: &[(&str, &str)],
+    escape_html: bool,
+) -> Result<String, PlaceholderError> {
+    render_with(template, escape_html, |name| {
+        values
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, v)| v.to_string())
+            .ok_or_else(|| PlaceholderError::Unknown(name.to_string()))
+    })
+}
+
+// Replaces every `{{ expression }}` in `template` with what `resolve` returns for it
+fn render_with(
+    template: &str,
+    escape_html: bool,
+    mut resolve: impl FnMut(&str) -> Result<String, PlaceholderError>,
+) -> Result<String, PlaceholderError> {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        rendered.push_str(&rest[..start]);
+        let after_open = &rest[start + 2..];
+        let end = after_open.find("}}").ok_or(PlaceholderError::Unclosed)?;
+        let value = resolve(after_open[..end].trim())?;
+        if escape_html {
+            rendered.push_str(&encode_minimal(&value));
+        } else {
+            rendered.push_str(&value);
+        }
+        rest = &after_open[end + 2..];
+    }
+    rendered.push_str(rest);
+    Ok(rendered)
+}
+
+// Splits `name | default: "value"` into the name and its default value
+fn parse_filter(expression: &str) -> Result<(&str, Option<&str>), PlaceholderError> {
+    let Some((name, filter)) = expression.split_once('|') else {
+        return Ok((expression, None));
+    };
+    let default = filter
+        .trim()
+        .strip_prefix("default:")
+        .map(str::trim)
+        .and_then(|value| value.strip_prefix('"'))
+        .and_then(|value| value.strip_suffix('"'))
+        .ok_or_else(|| PlaceholderError::InvalidFilter(expression.to_string()))?;
+    Ok((name.trim(), Some(default)))
+}
+
+// Strings are inserted without their quotes, missing attributes are empty
+fn attribute_text(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::Null => String::new(),
+        serde_json::Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        render_confirmation, render_placeholders, EmailTemplate, EmailTemplates, PlaceholderError,
+        Recipient, CONFIRMATION_PLACEHOLDERS,
+    };
+    use claims::{assert_err_eq, assert_ok, assert_ok_eq};
+
+    #[test]
+    fn placeholders_are_replaced_with_or_without_spaces() {
+        assert_ok_eq!(
+            render_placeholders("Hi {{name}}, {{ name }}!", &[("name", "bob")], false),
+            "Hi bob, bob!".to_string()
+        );
+    }
+
+    #[test]
+    fn values_are_escaped_in_html() {
+        assert_ok_eq!(
+            render_placeholders("<p>{{ name }}</p>", &[("name", "<b>bob</b>")], true),
+            "<p>&lt;b&gt;bob&lt;/b&gt;</p>".to_string()
+        );
+    }
+
+    #[test]
+    fn unknown_placeholders_are_rejected() {
+        assert_err_eq!(
+            render_placeholders("Hi {{ nmae }}", &[("name", "bob")], false),
+            PlaceholderError::Unknown("nmae".to_string())
+        );
+    }
+
+    #[test]
+    fn unclosed_placeholders_are_rejected() {
+        assert_err_eq!(
+            render_placeholders("Hi {{ name", &[("name", "bob")], false),
+            PlaceholderError::Unclosed
+        );
+    }
+
+    #[test]
+    fn builtin_confirmation_email_contains_the_link() {
+        let templates = EmailTemplates::builtin();
+        let email = assert_ok!(render_confirmation(
+            templates.default_confirmation(),
+            "bob",
+            "Newsletter",
+            "https://link"
+        ));
+        assert_eq!(email.subject, "Welcome!");
+        assert!(email.html.contains(r#"<a href="https://link">"#));
+        assert!(email.text.contains("https://link"));
+    }
+
+    #[test]
+    fn unknown_placeholders_in_the_subject_fail_validation() {
+        let mut template = EmailTemplates::builtin().default_confirmation().clone();
+        template.subject = "Welcome {{ first_name }}".into();
+        assert_err_eq!(
+            template.validate(CONFIRMATION_PLACEHOLDERS),
+            PlaceholderError::Unknown("first_name".to_string())
+        );
+    }
+
+    #[test]
+    fn files_in_the_templates_directory_replace_the_builtin_ones() {
+        let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        std::fs::create_dir(&directory).unwrap();
+        std::fs::write(
+            directory.join("confirmation.txt"),
+            "Confirm here: {{ confirmation_link }}",
+        )
+        .unwrap();
+
+        let templates = assert_ok!(EmailTemplates::load(&directory));
+        let email = assert_ok!(render_confirmation(
+            templates.default_confirmation(),
+            "bob",
+            "Newsletter",
+            "https://link"
+        ));
+
+        assert_eq!(email.text, "Confirm here: https://link");
+        // The HTML body was not replaced
+        assert!(email.html.contains(r#"<a href="https://link">"#));
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn a_replacement_with_an_unknown_placeholder_fails_to_load() {
+        let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        std::fs::create_dir(&directory).unwrap();
+        std::fs::write(directory.join("confirmation.html"), "{{ link }}").unwrap();
+
+        assert!(EmailTemplates::load(&directory).is_err());
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    fn issue(html: &str) -> EmailTemplate {
+        EmailTemplate {
+            subject: "Hi {{ name }}".into(),
+            html: html.into(),
+            text: "Text".into(),
+        }
+    }
+
+    #[test]
+    fn issues_are_rendered_for_their_recipient() {
+        let attributes = serde_json::json!({ "country": "DE", "age": 42 });
+        let recipient = Recipient {
+            name: "bob",
+            email: "bob@test.com",
+            unsubscribe_url: "https://unsubscribe",
+            attributes: &attributes,
+        };
+        let template = issue(
+            r#"{{ email }} {{ attributes.country }} {{ attributes.age }} {{ attributes.city | default: "nowhere" }} <a href="{{ unsubscribe_url }}">Leave</a>"#,
+        );
+
+        let email = assert_ok!(template.render_issue(&recipient));
+
+        assert_eq!(email.subject, "Hi bob");
+        assert_eq!(
+            email.html,
+            r#"bob@test.com DE 42 nowhere <a href="https://unsubscribe">Leave</a>"#
+        );
+        // Only the text body gets the footer, as the HTML one links to the URL already
+        assert_eq!(email.text, "Text\n\nUnsubscribe: https://unsubscribe");
+    }
+
+    #[test]
+    fn issues_with_unknown_placeholders_or_filters_fail_validation() {
+        assert_ok!(issue(r#"{{ attributes.plan | default: "free" }}"#).validate_issue());
+        assert_err_eq!(
+            issue("{{ first_name }}").validate_issue(),
+            PlaceholderError::Unknown("first_name".to_string())
+        );
+        assert_err_eq!(
+            issue("{{ attributes. }}").validate_issue(),
+            PlaceholderError::Unknown("attributes.".to_string())
+        );
+        assert_err_eq!(
+            issue("{{ name | upper }}").validate_issue(),
+            PlaceholderError::InvalidFilter("name | upper".to_string())
+        );
+    }
+}
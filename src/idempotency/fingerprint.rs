@@ -0,0 +1,88 @@
+use std::collections::BTreeMap;
+
+use actix_web::http::Method;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+// Identifies a request, to detect an idempotency key used again for a different one
+#[derive(Debug, PartialEq, Eq)]
+pub struct RequestFingerprint(String);
+
+impl RequestFingerprint {
+    // Forms and JSON bodies are normalized before they are hashed: the order of the fields does
+    // not matter and the idempotency key itself is left out. Other bodies are hashed as they are.
+    pub fn new(method: &Method, path: &str, content_type: &str, body: &[u8]) -> Self {
+        let normalized = match content_type {
+            "application/json" => serde_json::from_slice::<serde_json::Value>(body)
+                .ok()
+                .and_then(|body| normalize(&body)),
+            "application/x-www-form-urlencoded" => {
+                serde_urlencoded::from_bytes::<Vec<(String, String)>>(body)
+                    .ok()
+                    .and_then(|fields| {
+                        // Repeated fields keep the order of their values
+                        let mut by_name = BTreeMap::<_, Vec<_>>::new();
+                        for (name, value) in fields {
+                            by_name.entry(name).or_default().push(value);
+                        }
+                        normalize(&by_name)
+                    })
+            }
+            _ => None,
+        };
+        let mut hasher = Sha256::new();
+        hasher.update(method.as_str());
+        hasher.update(b"\n");
+        hasher.update(path);
+        hasher.update(b"\n");
+        hasher.update(normalized.as_deref().unwrap_or(body));
+        Self(format!("{:x}", hasher.finalize()))
+    }
+}
+
+// JSON with sorted keys, without the idempotency key
+fn normalize(body: &impl Serialize) -> Option<Vec<u8>> {
+    let mut body = serde_json::to_value(body).ok()?;
+    if let Some(fields) = body.as_object_mut() {
+        fields.remove("idempotency_key");
+    }
+    serde_json::to_vec(&body).ok()
+}
+
+impl AsRef<str> for RequestFingerprint {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RequestFingerprint;
+    use actix_web::http::Method;
+
+    const FORM: &str = "application/x-www-form-urlencoded";
+
+    fn fingerprint(path: &str, content_type: &str, body: &str) -> RequestFingerprint {
+        RequestFingerprint::new(&Method::POST, path, content_type, body.as_bytes())
+    }
+
+    #[test]
+    fn field_order_and_the_idempotency_key_do_not_change_the_fingerprint() {
+        assert_eq!(
+            fingerprint("/a", FORM, "title=t&list=a&list=b&idempotency_key=1"),
+            fingerprint("/a", FORM, "idempotency_key=2&list=a&title=t&list=b")
+        );
+        assert_eq!(
+            fingerprint("/a", "application/json", r#"{"a": 1, "b": [1, 2]}"#),
+            fingerprint("/a", "application/json", r#"{"b": [1, 2], "a": 1}"#)
+        );
+    }
+
+    #[test]
+    fn a_different_body_or_path_changes_the_fingerprint() {
+        let original = fingerprint("/a", FORM, "title=t&list=a&list=b");
+        assert_ne!(original, fingerprint("/a", FORM, "title=u&list=a&list=b"));
+        assert_ne!(original, fingerprint("/a", FORM, "title=t&list=b&list=a"));
+        assert_ne!(original, fingerprint("/b", FORM, "title=t&list=a&list=b"));
+    }
+}
@@ -0,0 +1,172 @@
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::http::Method;
+use actix_web::{web, HttpMessage};
+use actix_web_lab::middleware::Next;
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::transaction::{KeyTransaction, KeyTransactionSlot};
+use super::{
+    in_progress_response, key_reused_response, save_response, try_processing, IdempotencyKey,
+    NextAction, RequestFingerprint,
+};
+use crate::authentication::{ApiToken, UserId};
+use crate::configuration::IdempotencySettings;
+use crate::utils::{err400, err500};
+
+pub const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+// Set to `true` on replayed responses
+pub const IDEMPOTENT_REPLAYED_HEADER: &str = "Idempotent-Replayed";
+// Forms may carry their key in a hidden field instead of the header
+const IDEMPOTENCY_KEY_FIELD: &str = "idempotency_key";
+
+// Whose key it is: the same key sent by someone else is a different key
+#[derive(Copy, Clone, Debug)]
+pub enum IdempotencyScope {
+    User(Uuid),
+    ApiToken(Uuid),
+}
+
+impl IdempotencyScope {
+    // Inserted by `reject_anonymous_users` or `reject_invalid_api_tokens`
+    fn of(req: &ServiceRequest) -> Option<Self> {
+        let extensions = req.extensions();
+        if let Some(api_token) = extensions.get::<ApiToken>() {
+            return Some(Self::ApiToken(api_token.id));
+        }
+        extensions
+            .get::<UserId>()
+            .map(|user_id| Self::User(**user_id))
+    }
+
+    fn id(&self) -> Uuid {
+        match self {
+            Self::User(id) | Self::ApiToken(id) => *id,
+        }
+    }
+}
+
+// Saves the response to a request sent with an idempotency key and replays it when the key is
+// used again, instead of running the handler twice. Flash messages are not part of the saved
+// response: see `is_replayed` to send them again. Requests without a key go through as usual.
+// Must run after the authentication middleware: wrap the route, or add it to a scope before
+// the authentication middleware.
+// The key stays locked while the handler runs. Handlers make their changes in the transaction
+// holding the key, see `RequestTransaction`: they are committed with the saved response. Server
+// errors, and responses of handlers that dropped the transaction, roll the changes back and are
+// not saved: the request can be sent again with the same key.
+pub async fn save_idempotent_responses(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, actix_web::Error> {
+    if matches!(*req.method(), Method::GET | Method::HEAD) {
+        return next.call(req).await.map(|r| r.map_into_boxed_body());
+    }
+    let scope = IdempotencyScope::of(&req)
+        .context("Idempotency keys require an authenticated user or API token")
+        .map_err(err500)?;
+    // Read to find the key and fingerprint the request, then put back for the handler
+    let body = req.extract::<web::Bytes>().await?;
+    req.set_payload(payload_from(body.clone()));
+    let Some(idempotency_key) = find_idempotency_key(&req, &body) else {
+        return next.call(req).await.map(|r| r.map_into_boxed_body());
+    };
+    let idempotency_key: IdempotencyKey = idempotency_key.try_into().map_err(err400)?;
+    let fingerprint = RequestFingerprint::new(req.method(), req.path(), req.content_type(), &body);
+    let connection_pool = req
+        .app_data::<web::Data<PgPool>>()
+        .cloned()
+        .context("The connection pool is not registered")
+        .map_err(err500)?;
+    let settings = req
+        .app_data::<web::Data<IdempotencySettings>>()
+        .cloned()
+        .context("The idempotency settings are not registered")
+        .map_err(err500)?;
+
+    let transaction = match try_processing(
+        &connection_pool,
+        &idempotency_key,
+        scope.id(),
+        &fingerprint,
+        req.path(),
+        &settings,
+    )
+    .await
+    .map_err(err500)?
+    {
+        NextAction::StartProcessing(transaction) => transaction,
+        NextAction::ReturnSavedResponse(mut saved_response) => {
+            saved_response.headers_mut().insert(
+                HeaderName::from_static("idempotent-replayed"),
+                HeaderValue::from_static("true"),
+            );
+            return Ok(req.into_response(saved_response));
+        }
+        NextAction::RequestInProgress => return Ok(req.into_response(in_progress_response())),
+        NextAction::KeyReused => return Ok(req.into_response(key_reused_response())),
+    };
+    let slot = KeyTransactionSlot::new(transaction);
+    req.extensions_mut().insert(slot.clone());
+    let outcome = next.call(req).await;
+    let transaction = match slot.take() {
+        Some(KeyTransaction::Ready(transaction)) => transaction,
+        Some(KeyTransaction::Aborted(transaction)) => {
+            transaction.rollback().await.map_err(err500)?;
+            return outcome.map(|r| r.map_into_boxed_body());
+        }
+        None => {
+            return Err(err500(anyhow::anyhow!(
+                "The handler kept the transaction of the idempotency key"
+            )))
+        }
+    };
+    let response = match outcome {
+        Ok(response) if !response.status().is_server_error() => response,
+        outcome => {
+            // Releases the key right away, a dropped transaction keeps it locked for a while
+            transaction.rollback().await.map_err(err500)?;
+            return outcome.map(|r| r.map_into_boxed_body());
+        }
+    };
+    let (request, response) = response.into_parts();
+    let response = save_response(
+        transaction,
+        &idempotency_key,
+        scope.id(),
+        response.map_into_boxed_body(),
+        &settings,
+    )
+    .await
+    .map_err(err500)?;
+    Ok(ServiceResponse::new(request, response))
+}
+
+// The header, or the field of a form
+fn find_idempotency_key(req: &ServiceRequest, body: &[u8]) -> Option<String> {
+    if let Some(value) = req.headers().get(IDEMPOTENCY_KEY_HEADER) {
+        return Some(String::from_utf8_lossy(value.as_bytes()).into_owned());
+    }
+    if req.content_type() != "application/x-www-form-urlencoded" {
+        return None;
+    }
+    serde_urlencoded::from_bytes::<Vec<(String, String)>>(body)
+        .ok()?
+        .into_iter()
+        .find(|(name, _)| name == IDEMPOTENCY_KEY_FIELD)
+        .map(|(_, value)| value)
+}
+
+fn payload_from(body: web::Bytes) -> actix_web::dev::Payload {
+    let (_, mut payload) = actix_http::h1::Payload::create(true);
+    payload.unread_data(body);
+    payload.into()
+}
+
+// Whether the response was saved for an earlier request, by `save_idempotent_responses`
+pub fn is_replayed<B>(response: &ServiceResponse<B>) -> bool {
+    response.headers().contains_key(IDEMPOTENT_REPLAYED_HEADER)
+}
@@ -1,5 +1,16 @@
+mod fingerprint;
 mod key;
+mod middleware;
 mod persistence;
+mod transaction;
 
+pub use fingerprint::RequestFingerprint;
 pub use key::IdempotencyKey;
-pub use persistence::{get_saved_response, save_response, try_processing, NextAction};
+pub use middleware::{
+    is_replayed, save_idempotent_responses, IDEMPOTENCY_KEY_HEADER, IDEMPOTENT_REPLAYED_HEADER,
+};
+pub use persistence::{
+    delete_expired_responses, get_saved_response, in_progress_response, key_reused_response,
+    save_response, try_processing, NextAction,
+};
+pub use transaction::RequestTransaction;
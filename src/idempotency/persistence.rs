@@ -1,16 +1,40 @@
-use actix_web::body::to_bytes;
+use std::io::{Read, Write};
+
+use actix_web::body::{to_bytes, BodySize, MessageBody};
+use actix_web::http::header::{HeaderMap, RETRY_AFTER, SET_COOKIE};
 use actix_web::http::StatusCode;
+use actix_web::web::Bytes;
 use actix_web::HttpResponse;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
 use sqlx::postgres::PgHasArrayType;
-use sqlx::{PgPool, Postgres, Transaction};
+use sqlx::{PgExecutor, PgPool, Postgres, Transaction};
 use uuid::Uuid;
 
-use super::IdempotencyKey;
+use super::{IdempotencyKey, RequestFingerprint};
+use crate::configuration::IdempotencySettings;
+
+// Postgres error code for `lock_timeout`
+const LOCK_NOT_AVAILABLE: &str = "55P03";
 
 #[allow(clippy::large_enum_variant)]
 pub enum NextAction {
     StartProcessing(Transaction<'static, Postgres>),
     ReturnSavedResponse(HttpResponse),
+    // Another request with the same key did not finish in time, see `in_progress_response`
+    RequestInProgress,
+    // The key was used for a different request, see `key_reused_response`
+    KeyReused,
+}
+
+// 409 Conflict, asking the client to retry once the other request had time to finish
+pub fn in_progress_response() -> HttpResponse {
+    HttpResponse::Conflict()
+        .insert_header((RETRY_AFTER, "1"))
+        .json(serde_json::json!({
+            "error": "A request with the same idempotency key is still being processed"
+        }))
 }
 
 #[derive(Debug, sqlx::Type)]
@@ -26,26 +50,35 @@ impl PgHasArrayType for HeaderPairRecord {
     }
 }
 
+// 422 Unprocessable Entity: replaying the saved response would answer a different request
+pub fn key_reused_response() -> HttpResponse {
+    HttpResponse::UnprocessableEntity().json(serde_json::json!({
+        "error": "The idempotency key was already used for a different request"
+    }))
+}
+
 pub async fn get_saved_response(
-    connection_pool: &PgPool,
+    executor: impl PgExecutor<'_>,
     idempotency_key: &IdempotencyKey,
-    user_id: Uuid,
+    scope_id: Uuid,
 ) -> Result<Option<HttpResponse>, anyhow::Error> {
     let saved_response = sqlx::query!(
         r#"
         SELECT
             response_status_code as "response_status_code!",
             response_headers as "response_headers!: Vec<HeaderPairRecord>",
-            response_body as "response_body!"
+            response_body as "response_body!",
+            response_body_gzipped
         FROM idempotency
         WHERE
-            user_id = $1 AND
-            idempotency_key = $2
+            scope_id = $1 AND
+            idempotency_key = $2 AND
+            response_status_code IS NOT NULL
         "#,
-        user_id,
+        scope_id,
         idempotency_key.as_ref()
     )
-    .fetch_optional(connection_pool)
+    .fetch_optional(executor)
     .await?;
 
     if let Some(r) = saved_response {
@@ -54,29 +87,43 @@ pub async fn get_saved_response(
         for HeaderPairRecord { name, value } in r.response_headers {
             response.append_header((name, value));
         }
-        Ok(Some(response.body(r.response_body)))
+        let body = if r.response_body_gzipped {
+            gunzip(&r.response_body)?
+        } else {
+            r.response_body
+        };
+        Ok(Some(response.body(body)))
     } else {
         Ok(None)
     }
 }
 
+// Bodies are saved gzipped, with the headers listed in `saved_headers`. A response too large to
+// be saved is saved without its body: sent again, the request is not run again but gets an
+// empty body.
 pub async fn save_response(
     mut transation: Transaction<'static, Postgres>,
     idempotency_key: &IdempotencyKey,
-    user_id: Uuid,
+    scope_id: Uuid,
     http_response: HttpResponse,
+    settings: &IdempotencySettings,
 ) -> Result<HttpResponse, anyhow::Error> {
     let (response_head, body) = http_response.into_parts();
-    let body = to_bytes(body).await.map_err(|e| anyhow::anyhow!("{}", e))?;
     let status_code = response_head.status().as_u16() as i16;
-    let headers = {
-        let mut h = Vec::with_capacity(response_head.headers().len());
-        for (name, value) in response_head.headers().iter() {
-            let name = name.as_str().to_owned();
-            let value = value.as_bytes().to_owned();
-            h.push(HeaderPairRecord { name, value });
-        }
-        h
+    let headers = headers_to_save(response_head.headers(), &settings.saved_headers);
+    let (saved_body, http_response) = if fits(body.size(), settings.max_saved_body_bytes) {
+        let body = to_bytes(body).await.map_err(|e| anyhow::anyhow!("{}", e))?;
+        // .map_into_boxed_body is needed to convert HttpResponse<Bytes> to HttpResponse<BoxBody>
+        (
+            body.clone(),
+            response_head.set_body(body).map_into_boxed_body(),
+        )
+    } else {
+        tracing::warn!(
+            body_size = ?body.size(),
+            "The response is too large to be saved, it is saved without its body"
+        );
+        (Bytes::new(), response_head.set_body(body))
     };
     sqlx::query_unchecked!(
         r#"
@@ -84,55 +131,207 @@ pub async fn save_response(
         SET
             response_status_code = $3,
             response_headers = $4,
-            response_body = $5
+            response_body = $5,
+            response_body_gzipped = true
         WHERE
-            user_id = $1 AND
+            scope_id = $1 AND
             idempotency_key = $2
         "#,
-        user_id,
+        scope_id,
         idempotency_key.as_ref(),
         status_code,
         headers,
-        body.as_ref()
+        gzip(&saved_body)?
     )
     .execute(&mut transation)
     .await?;
     transation.commit().await?;
-
-    // .map_into_boxed_body is needed to convert HttpResponse<Bytes> to HttpResponse<BoxBody>
-    let http_response = response_head.set_body(body).map_into_boxed_body();
     Ok(http_response)
 }
 
+// Bodies streamed without a known size are too large
+fn fits(body_size: BodySize, max_bytes: u64) -> bool {
+    match body_size {
+        BodySize::None => true,
+        BodySize::Sized(size) => size <= max_bytes,
+        BodySize::Stream => false,
+    }
+}
+
+// Cookies are never saved: they belong to the session of the first request, replaying them
+// would hand it to whoever sends the key again
+fn headers_to_save(headers: &HeaderMap, saved_headers: &[String]) -> Vec<HeaderPairRecord> {
+    headers
+        .iter()
+        .filter(|(name, _)| {
+            **name != SET_COOKIE
+                && saved_headers
+                    .iter()
+                    .any(|h| h.eq_ignore_ascii_case(name.as_str()))
+        })
+        .map(|(name, value)| HeaderPairRecord {
+            name: name.as_str().to_owned(),
+            value: value.as_bytes().to_owned(),
+        })
+        .collect()
+}
+
+fn gzip(body: &[u8]) -> Result<Vec<u8>, std::io::Error> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(body)?;
+    encoder.finish()
+}
+
+fn gunzip(body: &[u8]) -> Result<Vec<u8>, std::io::Error> {
+    let mut decoded = Vec::new();
+    GzDecoder::new(body).read_to_end(&mut decoded)?;
+    Ok(decoded)
+}
+
+// A key first used more than `key_ttl` ago is treated as new: its saved response is
+// replaced by the one to this request. A key used again for a request with a different
+// fingerprint is rejected.
+// The row for the key is locked until its response is saved: a request with the same key
+// waits for it, up to `in_flight_wait`, then replays the response.
 pub async fn try_processing(
     connection_pool: &PgPool,
     idempotency_key: &IdempotencyKey,
-    user_id: Uuid,
+    scope_id: Uuid,
+    fingerprint: &RequestFingerprint,
+    request_path: &str,
+    settings: &IdempotencySettings,
 ) -> Result<NextAction, anyhow::Error> {
     let mut transaction = connection_pool.begin().await?;
-    let n_inserted_row = sqlx::query!(
+    sqlx::query!(
+        "SELECT set_config('lock_timeout', $1, true)",
+        format!("{}ms", settings.in_flight_wait().as_millis())
+    )
+    .fetch_one(&mut transaction)
+    .await?;
+    let n_inserted_row = match sqlx::query!(
         r#"
         INSERT INTO idempotency (
-            user_id,
+            scope_id,
             idempotency_key,
+            request_fingerprint,
+            request_path,
             created_at
         )
-        VALUES ($1, $2, now())
-        ON CONFLICT DO NOTHING
+        VALUES ($1, $2, $4, $5, now())
+        ON CONFLICT (scope_id, idempotency_key) DO UPDATE
+        SET
+            created_at = now(),
+            request_fingerprint = $4,
+            request_path = $5,
+            response_status_code = NULL,
+            response_headers = NULL,
+            response_body = NULL
+        WHERE idempotency.created_at < $3
         "#,
-        user_id,
-        idempotency_key.as_ref()
+        scope_id,
+        idempotency_key.as_ref(),
+        chrono::Utc::now() - settings.key_ttl(),
+        fingerprint.as_ref(),
+        request_path
     )
     .execute(&mut transaction)
-    .await?
-    .rows_affected();
+    .await
+    {
+        Ok(result) => result.rows_affected(),
+        Err(sqlx::Error::Database(e)) if e.code().as_deref() == Some(LOCK_NOT_AVAILABLE) => {
+            return Ok(NextAction::RequestInProgress);
+        }
+        Err(e) => return Err(e.into()),
+    };
+    // Only the lock for the key should wait, not the rest of the request
+    sqlx::query!("SELECT set_config('lock_timeout', '0', true)")
+        .fetch_one(&mut transaction)
+        .await?;
     if n_inserted_row > 0 {
-        // Inserted a new row, no saved_response available
-        Ok(NextAction::StartProcessing(transaction))
+        // Inserted a new row or reset an expired one, no saved_response available
+        return Ok(NextAction::StartProcessing(transaction));
+    }
+    // Keys saved before fingerprints were recorded match any request
+    let saved_fingerprint = sqlx::query_scalar!(
+        "SELECT request_fingerprint FROM idempotency WHERE scope_id = $1 AND idempotency_key = $2",
+        scope_id,
+        idempotency_key.as_ref()
+    )
+    .fetch_optional(&mut transaction)
+    .await?
+    .flatten();
+    let next_action = if saved_fingerprint.is_some_and(|f| f != fingerprint.as_ref()) {
+        NextAction::KeyReused
     } else {
-        let saved_response = get_saved_response(connection_pool, idempotency_key, user_id)
-            .await?
-            .ok_or_else(|| anyhow::anyhow!("We expected a saved, we didn't find it"))?;
-        Ok(NextAction::ReturnSavedResponse(saved_response))
+        // The request holding the key may have failed without saving a response
+        match get_saved_response(&mut transaction, idempotency_key, scope_id).await? {
+            Some(saved_response) => NextAction::ReturnSavedResponse(saved_response),
+            None => NextAction::RequestInProgress,
+        }
+    };
+    // Releases the row lock taken by the upsert right away, a dropped transaction keeps it
+    // locked for a while
+    transaction.rollback().await?;
+    Ok(next_action)
+}
+
+// Forgets responses saved more than `max_age` ago, their keys can then be used again
+#[tracing::instrument(skip(connection_pool))]
+pub async fn delete_expired_responses(
+    connection_pool: &PgPool,
+    max_age: chrono::Duration,
+) -> Result<u64, sqlx::Error> {
+    let n_deleted = sqlx::query!(
+        "DELETE FROM idempotency WHERE created_at < $1",
+        chrono::Utc::now() - max_age
+    )
+    .execute(connection_pool)
+    .await?
+    .rows_affected();
+    Ok(n_deleted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{fits, gunzip, gzip, headers_to_save};
+    use actix_web::body::BodySize;
+    use actix_web::http::header::{HeaderMap, HeaderName, HeaderValue};
+
+    #[test]
+    fn only_allowed_headers_are_saved_and_never_cookies() {
+        let mut headers = HeaderMap::new();
+        for (name, value) in [
+            ("location", "/admin/newsletters"),
+            ("x-request-id", "42"),
+            ("set-cookie", "id=secret"),
+        ] {
+            headers.append(
+                HeaderName::from_static(name),
+                HeaderValue::from_static(value),
+            );
+        }
+        let allowed = ["Location".to_string(), "set-cookie".to_string()];
+
+        let saved = headers_to_save(&headers, &allowed);
+
+        assert_eq!(saved.len(), 1);
+        assert_eq!(saved[0].name, "location");
+        assert_eq!(saved[0].value, b"/admin/newsletters");
+    }
+
+    #[test]
+    fn bodies_above_the_limit_or_of_unknown_size_do_not_fit() {
+        assert!(fits(BodySize::None, 10));
+        assert!(fits(BodySize::Sized(10), 10));
+        assert!(!fits(BodySize::Sized(11), 10));
+        assert!(!fits(BodySize::Stream, 10));
+    }
+
+    #[test]
+    fn gzipped_bodies_are_restored() {
+        let body = br#"{"tags": ["beta"]}"#.repeat(100);
+        let compressed = gzip(&body).unwrap();
+        assert!(compressed.len() < body.len());
+        assert_eq!(gunzip(&compressed).unwrap(), body);
     }
 }
@@ -0,0 +1,126 @@
+use std::cell::RefCell;
+use std::future::Future;
+use std::ops::{Deref, DerefMut};
+use std::pin::Pin;
+use std::rc::Rc;
+
+use actix_web::dev::Payload;
+use actix_web::{web, FromRequest, HttpMessage, HttpRequest};
+use anyhow::Context;
+use sqlx::{PgPool, Postgres, Transaction};
+
+use crate::utils::err500;
+
+pub(super) enum KeyTransaction {
+    // Not taken by the handler, or handed back by `RequestTransaction::commit`
+    Ready(Transaction<'static, Postgres>),
+    // The handler dropped it without committing, its changes must be rolled back
+    Aborted(Transaction<'static, Postgres>),
+}
+
+// Where the transaction holding an idempotency key is kept while the handler does not use it,
+// shared by `save_idempotent_responses` and the handler's `RequestTransaction`
+#[derive(Clone)]
+pub(super) struct KeyTransactionSlot(Rc<RefCell<Option<KeyTransaction>>>);
+
+impl KeyTransactionSlot {
+    pub(super) fn new(transaction: Transaction<'static, Postgres>) -> Self {
+        Self(Rc::new(RefCell::new(Some(KeyTransaction::Ready(
+            transaction,
+        )))))
+    }
+
+    pub(super) fn take(&self) -> Option<KeyTransaction> {
+        self.0.borrow_mut().take()
+    }
+
+    fn put(&self, transaction: KeyTransaction) {
+        *self.0.borrow_mut() = Some(transaction);
+    }
+}
+
+// The transaction a handler makes its changes in. Behind an idempotency key it is the one
+// holding the key: `commit` hands it back to `save_idempotent_responses`, which commits the
+// changes along with the saved response, so they are saved together or not at all. Otherwise
+// it is a transaction of its own, committed by `commit`.
+// Dropped without `commit`, the changes are rolled back.
+pub struct RequestTransaction {
+    transaction: Option<Transaction<'static, Postgres>>,
+    key_slot: Option<KeyTransactionSlot>,
+}
+
+impl RequestTransaction {
+    pub async fn commit(mut self) -> Result<(), sqlx::Error> {
+        let transaction = self
+            .transaction
+            .take()
+            .expect("The transaction is only taken here");
+        match self.key_slot.take() {
+            Some(slot) => {
+                slot.put(KeyTransaction::Ready(transaction));
+                Ok(())
+            }
+            None => transaction.commit().await,
+        }
+    }
+}
+
+impl Drop for RequestTransaction {
+    fn drop(&mut self) {
+        if let (Some(transaction), Some(slot)) = (self.transaction.take(), self.key_slot.take()) {
+            slot.put(KeyTransaction::Aborted(transaction));
+        }
+    }
+}
+
+impl Deref for RequestTransaction {
+    type Target = Transaction<'static, Postgres>;
+
+    fn deref(&self) -> &Self::Target {
+        self.transaction
+            .as_ref()
+            .expect("The transaction is only taken when committed")
+    }
+}
+
+impl DerefMut for RequestTransaction {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.transaction
+            .as_mut()
+            .expect("The transaction is only taken when committed")
+    }
+}
+
+impl FromRequest for RequestTransaction {
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let key_slot = req.extensions().get::<KeyTransactionSlot>().cloned();
+        let connection_pool = req.app_data::<web::Data<PgPool>>().cloned();
+        Box::pin(async move {
+            if let Some(slot) = key_slot {
+                return match slot.take() {
+                    Some(KeyTransaction::Ready(transaction)) => Ok(Self {
+                        transaction: Some(transaction),
+                        key_slot: Some(slot),
+                    }),
+                    _ => Err(err500(anyhow::anyhow!(
+                        "The transaction of the idempotency key was already taken"
+                    ))),
+                };
+            }
+            let transaction = connection_pool
+                .context("The connection pool is not registered")
+                .map_err(err500)?
+                .begin()
+                .await
+                .context("Failed to acquire a Postgres connection from the pool")
+                .map_err(err500)?;
+            Ok(Self {
+                transaction: Some(transaction),
+                key_slot: None,
+            })
+        })
+    }
+}
@@ -0,0 +1,48 @@
+use sqlx::PgPool;
+
+use super::{enqueue_unless_queued, Job, JobContext, JobError};
+use crate::configuration::IdempotencySettings;
+use crate::idempotency::delete_expired_responses;
+
+// Deletes the responses saved for idempotency keys older than `max_age_seconds`
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct CleanUpIdempotencyKeys {
+    pub max_age_seconds: i64,
+}
+
+impl Job for CleanUpIdempotencyKeys {
+    const JOB_TYPE: &'static str = "clean_up_idempotency_keys";
+    const MAX_ATTEMPTS: i32 = 3;
+
+    #[tracing::instrument(skip(context))]
+    async fn run(&self, context: &JobContext) -> Result<(), JobError> {
+        let n_deleted = delete_expired_responses(
+            &context.connection_pool,
+            chrono::Duration::seconds(self.max_age_seconds),
+        )
+        .await?;
+        tracing::info!(n_deleted, "Deleted expired idempotency keys");
+        Ok(())
+    }
+}
+
+// Queues a clean up every `clean_up_interval`, starting right away. Runs alongside the worker.
+pub async fn clean_up_idempotency_keys_periodically(
+    connection_pool: PgPool,
+    settings: IdempotencySettings,
+) {
+    let job = CleanUpIdempotencyKeys {
+        max_age_seconds: settings.key_ttl().num_seconds(),
+    };
+    let mut interval = tokio::time::interval(settings.clean_up_interval());
+    loop {
+        interval.tick().await;
+        if let Err(e) = enqueue_unless_queued(&connection_pool, &job).await {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to schedule the clean up of idempotency keys"
+            );
+        }
+    }
+}
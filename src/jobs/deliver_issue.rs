@@ -0,0 +1,326 @@
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::{Job, JobContext, JobError, SubscriberLinks};
+use crate::domain::SubscriberEmail;
+use crate::email_client::{EmailClient, EmailClientError};
+use crate::email_templates::{EmailTemplate, Recipient, RenderedEmail};
+use crate::lists::manage_subscriptions_link;
+use crate::suppression::is_suppressed;
+use crate::tracking::{open_pixel, rewrite_links, IssueLink};
+
+// Sends an issue to one subscriber. Queued for every recipient when the issue is published.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct DeliverIssue {
+    pub newsletter_issue_id: Uuid,
+    pub subscriber_email: String,
+}
+
+impl Job for DeliverIssue {
+    const JOB_TYPE: &'static str = "deliver_issue";
+    // About a day of retries
+    const MAX_ATTEMPTS: i32 = 30;
+
+    #[tracing::instrument(
+        skip_all,
+        fields(
+            newsletter_issue_id=%self.newsletter_issue_id,
+            subscriber_email=%self.subscriber_email
+        )
+    )]
+    async fn run(&self, context: &JobContext) -> Result<(), JobError> {
+        match SubscriberEmail::parse(self.subscriber_email.clone()) {
+            Ok(email) => {
+                deliver_issue(
+                    &context.connection_pool,
+                    &context.email_client,
+                    &context.links,
+                    self.newsletter_issue_id,
+                    &email,
+                )
+                .await?
+            }
+            Err(e) => {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "Skipping a confirmed subscriber, stored contact details are invalid."
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+struct NewsletterIssue {
+    title: String,
+    text_content: String,
+    html_content: String,
+    track_opens: bool,
+    track_clicks: bool,
+}
+
+struct Subscriber {
+    id: Uuid,
+    name: String,
+    attributes: serde_json::Value,
+}
+
+// Sends the issue to the subscriber, unless it was already sent or the address is suppressed.
+// The send is logged before it happens: if the worker dies before the job is deleted, the
+// issue is not sent again. Fails if the email should be sent again later, i.e. on transient
+// or configuration errors that show it was not sent. If it may have been sent, e.g. after a
+// timeout, the delivery is left as `sending` to be checked by hand rather than sent twice.
+#[tracing::instrument(skip_all)]
+async fn deliver_issue(
+    connection_pool: &PgPool,
+    email_client: &EmailClient,
+    links: &SubscriberLinks,
+    issue_id: Uuid,
+    email: &SubscriberEmail,
+) -> Result<(), anyhow::Error> {
+    let Some(subscriber) = get_subscriber(connection_pool, email.as_ref()).await? else {
+        tracing::warn!(
+            "Skipping a subscriber that was erased or changed address since the issue was published."
+        );
+        return Ok(());
+    };
+    if is_suppressed(connection_pool, email.as_ref()).await? {
+        tracing::info!("Not sending the issue, the address is on the suppression list.");
+        log_delivery(connection_pool, issue_id, &subscriber, email, "suppressed").await?;
+        return Ok(());
+    }
+    let Some(content) =
+        render_issue_for(connection_pool, links, issue_id, &subscriber, email).await?
+    else {
+        return Ok(());
+    };
+    if !log_delivery(connection_pool, issue_id, &subscriber, email, "sending").await? {
+        tracing::warn!("Skipping a subscriber the issue was already sent to.");
+        return Ok(());
+    }
+    let idempotency_key = format!("{issue_id}:{}", subscriber.id);
+    match email_client
+        .send_email_once(
+            email,
+            &content.subject,
+            &content.html,
+            &content.text,
+            &idempotency_key,
+        )
+        .await
+    {
+        Ok(message_id) => {
+            complete_delivery(
+                connection_pool,
+                issue_id,
+                subscriber.id,
+                "delivered",
+                message_id,
+            )
+            .await?;
+        }
+        Err(e @ EmailClientError::Recipient(_)) => {
+            tracing::warn!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "The email provider refused the subscriber's address. Skipping."
+            );
+            complete_delivery(connection_pool, issue_id, subscriber.id, "failed", None).await?;
+        }
+        Err(e) if e.may_have_been_sent() => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                %idempotency_key,
+                "The issue may have been sent to the subscriber. Leaving the delivery as sending \
+                to be checked by hand."
+            );
+        }
+        // The job is retried
+        Err(e) => {
+            release_delivery(connection_pool, issue_id, subscriber.id).await?;
+            return Err(e.into());
+        }
+    }
+    Ok(())
+}
+
+// The issue as the subscriber will receive it, or `None` if it should not be sent to them
+#[tracing::instrument(skip_all)]
+async fn render_issue_for(
+    connection_pool: &PgPool,
+    links: &SubscriberLinks,
+    issue_id: Uuid,
+    subscriber: &Subscriber,
+    email: &SubscriberEmail,
+) -> Result<Option<RenderedEmail>, anyhow::Error> {
+    let issue = get_issue(connection_pool, issue_id).await?;
+    let unsubscribe_url =
+        manage_subscriptions_link(&links.base_url, &links.hmac_secret, subscriber.id);
+    let recipient = Recipient {
+        name: &subscriber.name,
+        email: email.as_ref(),
+        unsubscribe_url: &unsubscribe_url,
+        attributes: &subscriber.attributes,
+    };
+    let template = EmailTemplate {
+        subject: issue.title,
+        html: issue.html_content,
+        text: issue.text_content,
+    };
+    let mut content = match template.render_issue(&recipient) {
+        Ok(content) => content,
+        // Placeholders are validated on publish, this is an issue stored before
+        Err(e) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Skipping a subscriber, the issue could not be rendered."
+            );
+            return Ok(None);
+        }
+    };
+    if issue.track_clicks {
+        let issue_links = get_issue_links(connection_pool, issue_id).await?;
+        content.html = rewrite_links(
+            &content.html,
+            &issue_links,
+            &links.base_url,
+            &links.hmac_secret,
+            issue_id,
+            subscriber.id,
+        );
+    }
+    if issue.track_opens {
+        content.html.push_str(&open_pixel(
+            &links.base_url,
+            &links.hmac_secret,
+            issue_id,
+            subscriber.id,
+        ));
+    }
+    Ok(Some(content))
+}
+
+// Returns `false` if the issue was already logged for the subscriber
+#[tracing::instrument(skip_all)]
+async fn log_delivery(
+    connection_pool: &PgPool,
+    issue_id: Uuid,
+    subscriber: &Subscriber,
+    email: &SubscriberEmail,
+    outcome: &str,
+) -> Result<bool, anyhow::Error> {
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO deliveries (newsletter_issue_id, subscriber_id, subscriber_email, outcome)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT DO NOTHING
+        "#,
+        issue_id,
+        subscriber.id,
+        email.as_ref(),
+        outcome
+    )
+    .execute(connection_pool)
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+#[tracing::instrument(skip(connection_pool))]
+async fn complete_delivery(
+    connection_pool: &PgPool,
+    issue_id: Uuid,
+    subscriber_id: Uuid,
+    outcome: &str,
+    message_id: Option<String>,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE deliveries
+        SET
+            outcome = $3,
+            message_id = $4,
+            delivered_at = CASE WHEN $3 = 'delivered' THEN now() END
+        WHERE newsletter_issue_id = $1 AND subscriber_id = $2
+        "#,
+        issue_id,
+        subscriber_id,
+        outcome,
+        message_id
+    )
+    .execute(connection_pool)
+    .await?;
+    Ok(())
+}
+
+// Forgets a send that did not happen, so it can be attempted again
+#[tracing::instrument(skip(connection_pool))]
+async fn release_delivery(
+    connection_pool: &PgPool,
+    issue_id: Uuid,
+    subscriber_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        DELETE FROM deliveries
+        WHERE newsletter_issue_id = $1 AND subscriber_id = $2 AND outcome = 'sending'
+        "#,
+        issue_id,
+        subscriber_id
+    )
+    .execute(connection_pool)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_subscriber(
+    connection_pool: &PgPool,
+    email: &str,
+) -> Result<Option<Subscriber>, anyhow::Error> {
+    let subscriber = sqlx::query_as!(
+        Subscriber,
+        "SELECT id, name, attributes FROM subscriptions WHERE email = $1",
+        email
+    )
+    .fetch_optional(connection_pool)
+    .await?;
+    Ok(subscriber)
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_issue(
+    connection_pool: &PgPool,
+    issue_id: Uuid,
+) -> Result<NewsletterIssue, anyhow::Error> {
+    let issue = sqlx::query_as!(
+        NewsletterIssue,
+        r#"
+        SELECT title, text_content, html_content, track_opens, track_clicks
+        FROM newsletter_issues
+        WHERE
+            newsletter_issue_id = $1
+        "#,
+        issue_id
+    )
+    .fetch_one(connection_pool)
+    .await?;
+    Ok(issue)
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_issue_links(
+    connection_pool: &PgPool,
+    issue_id: Uuid,
+) -> Result<Vec<IssueLink>, anyhow::Error> {
+    let links = sqlx::query_as!(
+        IssueLink,
+        "SELECT link_id, href FROM issue_links WHERE newsletter_issue_id = $1",
+        issue_id
+    )
+    .fetch_all(connection_pool)
+    .await?;
+    Ok(links)
+}
@@ -0,0 +1,18 @@
+mod clean_up_idempotency_keys;
+mod deliver_issue;
+mod queue;
+mod send_confirmation_email;
+mod send_data_request_email;
+mod worker;
+
+pub use clean_up_idempotency_keys::{
+    clean_up_idempotency_keys_periodically, CleanUpIdempotencyKeys,
+};
+pub use deliver_issue::DeliverIssue;
+pub use queue::{
+    enqueue_all, enqueue_unless_queued, next_job_due_at, notify_workers, schedule, try_execute_job,
+    ExecutionOutcome, Job, JobContext, JobError, SubscriberLinks, NEW_JOBS_CHANNEL,
+};
+pub use send_confirmation_email::SendConfirmationEmail;
+pub use send_data_request_email::SendDataRequestEmail;
+pub use worker::{run_worker_until_stopped, worker_loop};
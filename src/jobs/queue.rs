@@ -0,0 +1,321 @@
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use secrecy::Secret;
+use serde::{de::DeserializeOwned, Serialize};
+use sqlx::{PgExecutor, PgPool, Postgres, Transaction};
+use std::future::Future;
+use tracing::{field::display, Span};
+use uuid::Uuid;
+
+use super::{CleanUpIdempotencyKeys, DeliverIssue, SendConfirmationEmail, SendDataRequestEmail};
+use crate::email_client::EmailClient;
+
+const MAX_RETRY_DELAY_SECONDS: i64 = 3600;
+// Workers listen on this channel to learn about new jobs without polling
+pub const NEW_JOBS_CHANNEL: &str = "new_jobs";
+
+pub enum ExecutionOutcome {
+    TaskCompleted,
+    EmptyQueue,
+}
+
+// What the worker needs to build links back to the application in the emails it sends
+#[derive(Clone)]
+pub struct SubscriberLinks {
+    pub base_url: String,
+    pub hmac_secret: Secret<String>,
+}
+
+// Everything job handlers may need, shared by all jobs the worker runs
+pub struct JobContext {
+    pub connection_pool: PgPool,
+    pub email_client: EmailClient,
+    pub links: SubscriberLinks,
+}
+
+// A type of background job. The job itself is its payload, stored as JSON until it runs.
+pub trait Job: Serialize + DeserializeOwned {
+    // Stored with each job to find its handler: it must not change while jobs are queued
+    const JOB_TYPE: &'static str;
+    const MAX_ATTEMPTS: i32;
+    // Jobs with a higher priority run first
+    const PRIORITY: i16 = 0;
+
+    // The job is deleted once this succeeds
+    fn run(&self, context: &JobContext) -> impl Future<Output = Result<(), JobError>> + Send;
+
+    // Called once, when the job failed for good
+    fn give_up(
+        &self,
+        _context: &JobContext,
+    ) -> impl Future<Output = Result<(), anyhow::Error>> + Send {
+        async { Ok(()) }
+    }
+}
+
+// Errors converted with `?` are retried
+pub enum JobError {
+    // Running the job again later may work
+    Retry(anyhow::Error),
+    // Running the job again would fail the same way
+    GiveUp(anyhow::Error),
+}
+
+impl<E: Into<anyhow::Error>> From<E> for JobError {
+    fn from(e: E) -> Self {
+        Self::Retry(e.into())
+    }
+}
+
+// Queues the jobs to run as soon as possible
+pub async fn enqueue_all<J: Job>(
+    executor: impl PgExecutor<'_>,
+    jobs: &[J],
+) -> Result<(), anyhow::Error> {
+    insert_jobs(executor, jobs, Utc::now()).await
+}
+
+// Queues the job to run at `run_at`, or as soon as possible after
+pub async fn schedule<J: Job>(
+    executor: impl PgExecutor<'_>,
+    job: &J,
+    run_at: DateTime<Utc>,
+) -> Result<(), anyhow::Error> {
+    insert_jobs(executor, std::slice::from_ref(job), run_at).await
+}
+
+// Queues the job to run as soon as possible, unless a job of the same type is already
+// waiting. For periodic jobs, which would otherwise pile up while the worker is down.
+#[tracing::instrument(skip_all, fields(job_type = J::JOB_TYPE))]
+pub async fn enqueue_unless_queued<J: Job>(
+    executor: impl PgExecutor<'_>,
+    job: &J,
+) -> Result<(), anyhow::Error> {
+    let payload = serde_json::to_value(job).context("Failed to serialize a job")?;
+    sqlx::query!(
+        r#"
+        WITH inserted AS (
+            INSERT INTO jobs (job_type, payload, priority, max_attempts)
+            SELECT $1, $2::jsonb, $3, $4
+            WHERE NOT EXISTS (SELECT 1 FROM jobs WHERE job_type = $1 AND status = 'queued')
+        )
+        SELECT pg_notify($5, $1)
+        "#,
+        J::JOB_TYPE,
+        payload,
+        J::PRIORITY,
+        J::MAX_ATTEMPTS,
+        NEW_JOBS_CHANNEL
+    )
+    .execute(executor)
+    .await
+    .context("Failed to queue a job")?;
+    Ok(())
+}
+
+#[tracing::instrument(skip_all, fields(job_type = J::JOB_TYPE, n_jobs = jobs.len()))]
+async fn insert_jobs<J: Job>(
+    executor: impl PgExecutor<'_>,
+    jobs: &[J],
+    run_at: DateTime<Utc>,
+) -> Result<(), anyhow::Error> {
+    if jobs.is_empty() {
+        return Ok(());
+    }
+    let payloads = jobs
+        .iter()
+        .map(serde_json::to_string)
+        .collect::<Result<Vec<_>, _>>()
+        .context("Failed to serialize a job")?;
+    // One statement, the executor can only be used once
+    sqlx::query!(
+        r#"
+        WITH inserted AS (
+            INSERT INTO jobs (job_type, payload, priority, max_attempts, run_at)
+            SELECT $1, payload::jsonb, $3, $4, $5
+            FROM UNNEST($2::text[]) AS t(payload)
+        )
+        SELECT pg_notify($6, $1)
+        "#,
+        J::JOB_TYPE,
+        &payloads[..],
+        J::PRIORITY,
+        J::MAX_ATTEMPTS,
+        run_at,
+        NEW_JOBS_CHANNEL
+    )
+    .execute(executor)
+    .await
+    .context("Failed to queue jobs")?;
+    Ok(())
+}
+
+// Wakes up the workers once the current transaction commits, or right away outside of one.
+// Jobs scheduled for later are found by the workers' periodic poll.
+pub async fn notify_workers(
+    executor: impl PgExecutor<'_>,
+    job_type: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!("SELECT pg_notify($1, $2)", NEW_JOBS_CHANNEL, job_type)
+        .execute(executor)
+        .await?;
+    Ok(())
+}
+
+// When the next queued job is due, if there is one
+pub async fn next_job_due_at(
+    connection_pool: &PgPool,
+) -> Result<Option<DateTime<Utc>>, sqlx::Error> {
+    sqlx::query_scalar!("SELECT MIN(run_at) FROM jobs WHERE status = 'queued'")
+        .fetch_one(connection_pool)
+        .await
+}
+
+struct QueuedJob {
+    id: Uuid,
+    job_type: String,
+    payload: serde_json::Value,
+    n_attempts: i32,
+    max_attempts: i32,
+}
+
+// Runs the next job that is due. A failed job is retried later with a growing delay, until it
+// runs out of attempts, and its error is returned.
+#[tracing::instrument(
+    skip_all,
+    fields(job_id=tracing::field::Empty, job_type=tracing::field::Empty),
+    err
+)]
+pub async fn try_execute_job(context: &JobContext) -> Result<ExecutionOutcome, anyhow::Error> {
+    let mut transaction = context
+        .connection_pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let Some(job) = dequeue_job(&mut transaction).await? else {
+        return Ok(ExecutionOutcome::EmptyQueue);
+    };
+    Span::current()
+        .record("job_id", display(job.id))
+        .record("job_type", display(&job.job_type));
+    let outcome = match job.job_type.as_str() {
+        DeliverIssue::JOB_TYPE => run::<DeliverIssue>(context, &job).await,
+        SendConfirmationEmail::JOB_TYPE => run::<SendConfirmationEmail>(context, &job).await,
+        SendDataRequestEmail::JOB_TYPE => run::<SendDataRequestEmail>(context, &job).await,
+        CleanUpIdempotencyKeys::JOB_TYPE => run::<CleanUpIdempotencyKeys>(context, &job).await,
+        other => Err(JobError::GiveUp(anyhow::anyhow!(
+            "There is no handler for {other} jobs"
+        ))),
+    };
+    let (error, give_up) = match outcome {
+        Ok(()) => {
+            delete_job(&mut transaction, job.id).await?;
+            transaction.commit().await?;
+            return Ok(ExecutionOutcome::TaskCompleted);
+        }
+        Err(JobError::Retry(e)) => (e, false),
+        Err(JobError::GiveUp(e)) => (e, true),
+    };
+    record_failure(&mut transaction, &job, &error, give_up).await?;
+    transaction.commit().await?;
+    Err(error)
+}
+
+// Failures on the last attempt are turned into `GiveUp`
+async fn run<J: Job>(context: &JobContext, job: &QueuedJob) -> Result<(), JobError> {
+    let payload: J = serde_json::from_value(job.payload.clone())
+        .map_err(|e| JobError::GiveUp(anyhow::Error::new(e).context("Invalid job payload")))?;
+    let outcome = match payload.run(context).await {
+        Err(JobError::Retry(e)) if job.n_attempts + 1 >= job.max_attempts => {
+            Err(JobError::GiveUp(e))
+        }
+        outcome => outcome,
+    };
+    if let Err(JobError::GiveUp(_)) = &outcome {
+        if let Err(e) = payload.give_up(context).await {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to clean up after a job that failed for good"
+            );
+        }
+    }
+    outcome
+}
+
+#[tracing::instrument(skip_all)]
+async fn dequeue_job(
+    transaction: &mut Transaction<'_, Postgres>,
+) -> Result<Option<QueuedJob>, sqlx::Error> {
+    sqlx::query_as!(
+        QueuedJob,
+        r#"
+        SELECT id, job_type, payload, n_attempts, max_attempts
+        FROM jobs
+        WHERE status = 'queued' AND run_at <= now()
+        ORDER BY priority DESC, run_at
+        FOR UPDATE
+        SKIP LOCKED
+        LIMIT 1
+        "#
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+}
+
+#[tracing::instrument(skip(transaction))]
+async fn delete_job(
+    transaction: &mut Transaction<'_, Postgres>,
+    job_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!("DELETE FROM jobs WHERE id = $1", job_id)
+        .execute(&mut *transaction)
+        .await?;
+    Ok(())
+}
+
+#[tracing::instrument(skip(transaction, job), fields(n_attempts = job.n_attempts + 1))]
+async fn record_failure(
+    transaction: &mut Transaction<'_, Postgres>,
+    job: &QueuedJob,
+    error: &anyhow::Error,
+    give_up: bool,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE jobs
+        SET status = CASE WHEN $2 THEN 'failed' ELSE 'queued' END,
+            n_attempts = n_attempts + 1,
+            run_at = now() + make_interval(secs => $3),
+            last_error = $4
+        WHERE id = $1
+        "#,
+        job.id,
+        give_up,
+        retry_delay_seconds(job.n_attempts + 1) as f64,
+        format!("{error:#}")
+    )
+    .execute(&mut *transaction)
+    .await?;
+    Ok(())
+}
+
+// 30s after the first failure, doubling up to an hour
+fn retry_delay_seconds(n_attempts: i32) -> i64 {
+    let exponent = n_attempts.clamp(1, 20) - 1;
+    (30_i64 << exponent).min(MAX_RETRY_DELAY_SECONDS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::retry_delay_seconds;
+
+    #[test]
+    fn retries_back_off_up_to_an_hour() {
+        assert_eq!(retry_delay_seconds(1), 30);
+        assert_eq!(retry_delay_seconds(2), 60);
+        assert_eq!(retry_delay_seconds(5), 480);
+        assert_eq!(retry_delay_seconds(8), 3600);
+        assert_eq!(retry_delay_seconds(30), 3600);
+    }
+}
@@ -0,0 +1,110 @@
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::{Job, JobContext, JobError};
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailClientError;
+use crate::routes::record_subscription_event;
+use crate::suppression::is_suppressed;
+
+// A rendered confirmation email. It is queued in the same transaction as the token it carries:
+// either both are saved or neither is.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct SendConfirmationEmail {
+    pub subscriber_id: Uuid,
+    pub recipient: String,
+    pub subscription_token: String,
+    pub subject: String,
+    pub html_body: String,
+    pub text_body: String,
+}
+
+impl Job for SendConfirmationEmail {
+    const JOB_TYPE: &'static str = "send_confirmation_email";
+    // About a day of retries
+    const MAX_ATTEMPTS: i32 = 30;
+    // Someone is waiting for it, it goes before issues
+    const PRIORITY: i16 = 10;
+
+    // Nothing is sent to a suppressed address, the attempt is only recorded in the
+    // subscriber's history. Nothing is sent either if the token is gone, e.g. the subscriber
+    // was erased since.
+    #[tracing::instrument(skip_all, fields(subscriber_id=%self.subscriber_id))]
+    async fn run(&self, context: &JobContext) -> Result<(), JobError> {
+        let connection_pool = &context.connection_pool;
+        if !token_exists(connection_pool, &self.subscription_token).await? {
+            tracing::info!("Not sending a confirmation email, the token no longer exists");
+            return Ok(());
+        }
+        if is_suppressed(connection_pool, &self.recipient).await? {
+            tracing::info!(
+                "Not sending a confirmation email, the address is on the suppression list"
+            );
+            record_subscription_event(
+                connection_pool,
+                self.subscriber_id,
+                None,
+                "confirmation_suppressed",
+            )
+            .await?;
+            return Ok(());
+        }
+        // Addresses are validated before subscribers are saved
+        let recipient = SubscriberEmail::parse(self.recipient.clone())
+            .map_err(|e| JobError::GiveUp(anyhow::anyhow!(e)))?;
+        match context
+            .email_client
+            .send_email(&recipient, &self.subject, &self.html_body, &self.text_body)
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(e @ EmailClientError::Recipient(_)) => Err(JobError::GiveUp(e.into())),
+            Err(e) => Err(JobError::Retry(e.into())),
+        }
+    }
+
+    // Forgets the unsent token, so subscribing again sends a new email right away
+    #[tracing::instrument(skip_all, fields(subscriber_id=%self.subscriber_id))]
+    async fn give_up(&self, context: &JobContext) -> Result<(), anyhow::Error> {
+        let mut transaction = context
+            .connection_pool
+            .begin()
+            .await
+            .context("Failed to acquire a Postgres connection from the pool")?;
+        sqlx::query!(
+            "DELETE FROM subscription_tokens WHERE subscription_token = $1",
+            self.subscription_token
+        )
+        .execute(&mut transaction)
+        .await
+        .context("Failed to delete the unsent token")?;
+        record_subscription_event(
+            &mut transaction,
+            self.subscriber_id,
+            None,
+            "confirmation_failed",
+        )
+        .await
+        .context("Failed to record the subscription event")?;
+        transaction.commit().await?;
+        Ok(())
+    }
+}
+
+async fn token_exists(
+    connection_pool: &PgPool,
+    subscription_token: &str,
+) -> Result<bool, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT EXISTS (
+            SELECT 1 FROM subscription_tokens WHERE subscription_token = $1
+        ) AS "exists!"
+        "#,
+        subscription_token
+    )
+    .fetch_one(connection_pool)
+    .await?;
+    Ok(row.exists)
+}
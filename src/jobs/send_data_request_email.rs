@@ -0,0 +1,47 @@
+use uuid::Uuid;
+
+use super::{Job, JobContext, JobError};
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailClientError;
+use crate::suppression::is_suppressed;
+
+// A rendered email with the links to download or erase the subscriber's data. Queued rather
+// than sent right away, so the response to the request does not depend on the email provider.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct SendDataRequestEmail {
+    pub subscriber_id: Uuid,
+    pub recipient: String,
+    pub subject: String,
+    pub html_body: String,
+    pub text_body: String,
+}
+
+impl Job for SendDataRequestEmail {
+    const JOB_TYPE: &'static str = "send_data_request_email";
+    // The links expire after a day
+    const MAX_ATTEMPTS: i32 = 30;
+    // Someone is waiting for it, it goes before issues
+    const PRIORITY: i16 = 10;
+
+    #[tracing::instrument(skip_all, fields(subscriber_id=%self.subscriber_id))]
+    async fn run(&self, context: &JobContext) -> Result<(), JobError> {
+        if is_suppressed(&context.connection_pool, &self.recipient).await? {
+            tracing::info!(
+                "Not sending a data request email, the address is on the suppression list"
+            );
+            return Ok(());
+        }
+        // Addresses are validated before subscribers are saved
+        let recipient = SubscriberEmail::parse(self.recipient.clone())
+            .map_err(|e| JobError::GiveUp(anyhow::anyhow!(e)))?;
+        match context
+            .email_client
+            .send_email(&recipient, &self.subject, &self.html_body, &self.text_body)
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(e @ EmailClientError::Recipient(_)) => Err(JobError::GiveUp(e.into())),
+            Err(e) => Err(JobError::Retry(e.into())),
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use sqlx::postgres::PgListener;
+use sqlx::PgPool;
+use std::time::Duration;
+
+use super::{
+    clean_up_idempotency_keys_periodically, next_job_due_at, try_execute_job, ExecutionOutcome,
+    JobContext, SubscriberLinks, NEW_JOBS_CHANNEL,
+};
+use crate::configuration::Settings;
+use crate::email_client::{EmailClient, EmailClientError};
+use crate::startup::get_connection_pool;
+
+// Notifications wake the worker as soon as jobs are queued: polling is a fallback for jobs
+// scheduled for later and for notifications missed while the listener was reconnecting
+const POLL_INTERVAL: Duration = Duration::from_secs(60);
+// Due jobs may be locked by another worker, they are not waited for in a busy loop
+const MIN_WAIT: Duration = Duration::from_secs(1);
+
+pub async fn run_worker_until_stopped(
+    configuration: Settings,
+    email_client: EmailClient,
+) -> Result<(), anyhow::Error> {
+    let connection_pool = get_connection_pool(&configuration.database);
+    let clean_up = tokio::spawn(clean_up_idempotency_keys_periodically(
+        connection_pool.clone(),
+        configuration.idempotency,
+    ));
+    let context = JobContext {
+        connection_pool,
+        email_client,
+        links: SubscriberLinks {
+            base_url: configuration.application.base_url,
+            hmac_secret: configuration.application.hmac_secret,
+        },
+    };
+    let outcome = worker_loop(context).await;
+    clean_up.abort();
+    outcome
+}
+
+pub async fn worker_loop(context: JobContext) -> Result<(), anyhow::Error> {
+    let mut listener = listen_for_new_jobs(&context.connection_pool).await;
+    loop {
+        // Most jobs send emails, which would fail right away: wait until Postmark can be
+        // tried again
+        if let Some(open_for) = context.email_client.circuit_open_for() {
+            tracing::info!(
+                circuit.state = "open",
+                "Pausing jobs for {}s",
+                open_for.as_secs()
+            );
+            tokio::time::sleep(open_for).await;
+            continue;
+        }
+        match try_execute_job(&context).await {
+            Ok(ExecutionOutcome::EmptyQueue) => {
+                wait_for_new_jobs(&context.connection_pool, listener.as_mut()).await;
+            }
+            // Nothing can be sent until the configuration is fixed, no need to keep trying
+            Err(e)
+                if matches!(
+                    e.downcast_ref::<EmailClientError>(),
+                    Some(EmailClientError::Configuration(_))
+                ) =>
+            {
+                tokio::time::sleep(Duration::from_secs(60)).await;
+            }
+            Err(_) => {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+            Ok(ExecutionOutcome::TaskCompleted) => {}
+        }
+    }
+}
+
+// Without a listener the worker still runs, it only polls
+async fn listen_for_new_jobs(connection_pool: &PgPool) -> Option<PgListener> {
+    let listener = async {
+        let mut listener = PgListener::connect_with(connection_pool).await?;
+        listener.listen(NEW_JOBS_CHANNEL).await?;
+        Ok::<_, sqlx::Error>(listener)
+    }
+    .await;
+    match listener {
+        Ok(listener) => Some(listener),
+        Err(e) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to listen for new jobs, falling back to polling"
+            );
+            None
+        }
+    }
+}
+
+// Returns when a job is queued, the next scheduled job is due or the poll interval is over
+async fn wait_for_new_jobs(connection_pool: &PgPool, listener: Option<&mut PgListener>) {
+    let timeout = match next_job_due_at(connection_pool).await {
+        Ok(Some(due_at)) => (due_at - chrono::Utc::now())
+            .to_std()
+            .unwrap_or(MIN_WAIT)
+            .clamp(MIN_WAIT, POLL_INTERVAL),
+        _ => POLL_INTERVAL,
+    };
+    let Some(listener) = listener else {
+        tokio::time::sleep(timeout).await;
+        return;
+    };
+    // The listener reconnects by itself, a failure is just an early poll
+    if let Ok(Err(e)) = tokio::time::timeout(timeout, listener.recv()).await {
+        tracing::warn!(
+            error.cause_chain = ?e,
+            error.message = %e,
+            "Lost the connection listening for new jobs"
+        );
+    }
+}
@@ -1,11 +1,19 @@
+pub mod audit;
 pub mod authentication;
+pub mod circuit_breaker;
 pub mod configuration;
 pub mod domain;
 pub mod email_client;
+pub mod email_templates;
 pub mod idempotency;
-pub mod issue_delivery_worker;
+pub mod jobs;
+pub mod lists;
 pub mod routes;
 pub mod session_state;
+pub mod signed_token;
 pub mod startup;
+pub mod suppression;
 pub mod telemetry;
+pub mod templates;
+pub mod tracking;
 pub mod utils;
@@ -0,0 +1,82 @@
+use chrono::{Duration, Utc};
+use secrecy::Secret;
+use sqlx::PgExecutor;
+use uuid::Uuid;
+
+use crate::signed_token;
+
+// The list used when a request doesn't name one, e.g. by clients written before there were several
+pub const DEFAULT_LIST: &str = "newsletter";
+
+pub const MANAGE_SUBSCRIPTIONS_PURPOSE: &str = "manage_subscriptions";
+// Links are in every issue we send, they should keep working for old issues too
+const MANAGE_SUBSCRIPTIONS_LINK_TTL_DAYS: i64 = 365;
+
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct MailingList {
+    pub id: Uuid,
+    pub slug: String,
+    pub name: String,
+}
+
+#[tracing::instrument(name = "Get all lists", skip(executor))]
+pub async fn get_lists(executor: impl PgExecutor<'_>) -> Result<Vec<MailingList>, sqlx::Error> {
+    sqlx::query_as!(
+        MailingList,
+        "SELECT id, slug, name FROM lists ORDER BY name"
+    )
+    .fetch_all(executor)
+    .await
+}
+
+#[tracing::instrument(name = "Get a list by slug", skip(executor))]
+pub async fn get_list_by_slug(
+    executor: impl PgExecutor<'_>,
+    slug: &str,
+) -> Result<Option<MailingList>, sqlx::Error> {
+    sqlx::query_as!(
+        MailingList,
+        "SELECT id, slug, name FROM lists WHERE slug = $1",
+        slug
+    )
+    .fetch_optional(executor)
+    .await
+}
+
+// Adds the subscriber to the list, or changes their status on it
+#[tracing::instrument(name = "Set the status of a list subscription", skip(executor))]
+pub async fn set_list_status(
+    executor: impl PgExecutor<'_>,
+    list_id: Uuid,
+    subscriber_id: Uuid,
+    status: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO list_subscriptions (list_id, subscriber_id, status, subscribed_at)
+        VALUES ($1, $2, $3, now())
+        ON CONFLICT (list_id, subscriber_id) DO UPDATE SET status = EXCLUDED.status
+        "#,
+        list_id,
+        subscriber_id,
+        status
+    )
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+// Link to the page where a subscriber can leave any of their lists
+pub fn manage_subscriptions_link(
+    base_url: &str,
+    hmac_secret: &Secret<String>,
+    subscriber_id: Uuid,
+) -> String {
+    let token = signed_token::sign(
+        hmac_secret,
+        MANAGE_SUBSCRIPTIONS_PURPOSE,
+        &subscriber_id.to_string(),
+        Utc::now() + Duration::days(MANAGE_SUBSCRIPTIONS_LINK_TTL_DAYS),
+    );
+    format!("{base_url}/subscriptions/unsubscribe?token={token}")
+}
@@ -1,7 +1,7 @@
 use std::fmt::{Debug, Display};
 use tokio::task::JoinError;
 use zero2prod::configuration::get_configuration;
-use zero2prod::issue_delivery_worker::run_worker_until_stopped;
+use zero2prod::jobs::run_worker_until_stopped;
 use zero2prod::startup::Application;
 use zero2prod::telemetry::{get_subscriber, init_subscriber};
 
@@ -12,8 +12,9 @@ async fn main() -> anyhow::Result<()> {
     init_subscriber(subscriber);
     let config = get_configuration().expect("Could not read configuration file");
     let application = Application::build(config.clone()).await?;
+    let email_client = application.email_client();
     let application_task = tokio::spawn(application.run_until_stopped());
-    let worker_task = tokio::spawn(run_worker_until_stopped(config));
+    let worker_task = tokio::spawn(run_worker_until_stopped(config, email_client));
     tokio::select! {
         o = application_task => report_exit("API", o),
         o = worker_task => report_exit("Background worker", o)
@@ -0,0 +1,29 @@
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use sqlx::PgPool;
+
+use super::render_api_tokens_page;
+use crate::authentication::UserId;
+use crate::utils::err500;
+
+#[utoipa::path(
+    get,
+    path = "/admin/api_tokens",
+    tag = "admin",
+    security(("session_cookie" = [])),
+    responses(
+        (status = 200, description = "The API tokens of the logged in user, and a form to create one", body = String, content_type = "text/html"),
+        (status = 303, description = "Not logged in, redirects to /login")
+    )
+)]
+#[tracing::instrument(name = "List API tokens", skip_all)]
+pub async fn list_api_tokens(
+    connection_pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let messages: Vec<&str> = flash_messages.iter().map(|m| m.content()).collect();
+    render_api_tokens_page(&connection_pool, *user_id.into_inner(), messages, None)
+        .await
+        .map_err(err500)
+}
@@ -0,0 +1,64 @@
+mod get;
+mod post;
+
+use actix_web::http::StatusCode;
+use actix_web::HttpResponse;
+use anyhow::Context;
+use askama::Template;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::templates::html_response;
+
+pub use get::{__path_list_api_tokens, list_api_tokens};
+pub use post::{
+    __path_create_api_token, __path_revoke_api_token, create_api_token, revoke_api_token,
+};
+
+struct ApiTokenRow {
+    id: Uuid,
+    name: String,
+    created_at: DateTime<Utc>,
+    last_used_at: Option<DateTime<Utc>>,
+    revoked_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Template)]
+#[template(path = "admin/api_tokens.html")]
+struct ApiTokensPage<'a> {
+    messages: Vec<&'a str>,
+    tokens: Vec<ApiTokenRow>,
+    // A token that was just created, it cannot be shown again afterwards
+    new_token: Option<String>,
+}
+
+// Lists the tokens of the logged in user
+async fn render_api_tokens_page(
+    connection_pool: &PgPool,
+    user_id: Uuid,
+    messages: Vec<&str>,
+    new_token: Option<String>,
+) -> Result<HttpResponse, anyhow::Error> {
+    let tokens = sqlx::query_as!(
+        ApiTokenRow,
+        r#"
+        SELECT id, name, created_at, last_used_at, revoked_at
+        FROM api_tokens
+        WHERE user_id = $1
+        ORDER BY created_at DESC
+        "#,
+        user_id
+    )
+    .fetch_all(connection_pool)
+    .await
+    .context("Failed to fetch the API tokens")?;
+    Ok(html_response(
+        StatusCode::OK,
+        &ApiTokensPage {
+            messages,
+            tokens,
+            new_token,
+        },
+    ))
+}
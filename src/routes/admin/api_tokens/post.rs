@@ -0,0 +1,142 @@
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::render_api_tokens_page;
+use crate::audit::record_audit_event;
+use crate::authentication::{generate_api_token, UserId};
+use crate::utils::{err404, err500, see_other};
+
+#[derive(Debug, serde::Deserialize, utoipa::ToSchema)]
+pub struct FormData {
+    /// What the token is used for
+    name: String,
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/api_tokens",
+    tag = "admin",
+    security(("session_cookie" = [])),
+    request_body(
+        content = inline(FormData),
+        content_type = "application/x-www-form-urlencoded"
+    ),
+    responses(
+        (status = 200, description = "The token list, showing the new token once", body = String, content_type = "text/html"),
+        (status = 303, description = "The name is missing, redirects to /admin/api_tokens")
+    )
+)]
+#[tracing::instrument(name = "Create an API token", skip(connection_pool, user_id))]
+pub async fn create_api_token(
+    form: web::Form<FormData>,
+    connection_pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let name = form.name.trim();
+    if name.is_empty() {
+        FlashMessage::error("The token needs a name").send();
+        return Ok(see_other("/admin/api_tokens"));
+    }
+    let user_id = *user_id.into_inner();
+    let (token, token_hash) = generate_api_token();
+    let token_id = Uuid::new_v4();
+    let mut transaction = connection_pool
+        .begin()
+        .await
+        .context("Failed to get a connection from the pool")
+        .map_err(err500)?;
+    sqlx::query!(
+        r#"
+        INSERT INTO api_tokens (id, user_id, name, token_hash)
+        VALUES ($1, $2, $3, $4)
+        "#,
+        token_id,
+        user_id,
+        name,
+        token_hash
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to store the API token")
+    .map_err(err500)?;
+    record_audit_event(
+        &mut transaction,
+        &format!("admin:{user_id}"),
+        "api_token_created",
+        serde_json::json!({ "token_id": token_id, "name": name }),
+    )
+    .await
+    .context("Failed to record the audit event")
+    .map_err(err500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the new API token")
+        .map_err(err500)?;
+    // Rendered directly rather than flashed, so the token never ends up in a cookie
+    render_api_tokens_page(&connection_pool, user_id, vec![], Some(token))
+        .await
+        .map_err(err500)
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/api_tokens/{token_id}/revoke",
+    tag = "admin",
+    security(("session_cookie" = [])),
+    params(("token_id" = Uuid, Path, description = "Id of the API token")),
+    responses(
+        (status = 303, description = "Token revoked, redirects to /admin/api_tokens"),
+        (status = 404, description = "The logged in user has no active token with this id")
+    )
+)]
+#[tracing::instrument(name = "Revoke an API token", skip(connection_pool, user_id))]
+pub async fn revoke_api_token(
+    token_id: web::Path<Uuid>,
+    connection_pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let token_id = token_id.into_inner();
+    let user_id = *user_id.into_inner();
+    let mut transaction = connection_pool
+        .begin()
+        .await
+        .context("Failed to get a connection from the pool")
+        .map_err(err500)?;
+    let n_revoked = sqlx::query!(
+        r#"
+        UPDATE api_tokens
+        SET revoked_at = now()
+        WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL
+        "#,
+        token_id,
+        user_id
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to revoke the API token")
+    .map_err(err500)?
+    .rows_affected();
+    if n_revoked == 0 {
+        return Err(err404("API token not found"));
+    }
+    record_audit_event(
+        &mut transaction,
+        &format!("admin:{user_id}"),
+        "api_token_revoked",
+        serde_json::json!({ "token_id": token_id }),
+    )
+    .await
+    .context("Failed to record the audit event")
+    .map_err(err500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the revocation")
+        .map_err(err500)?;
+    FlashMessage::info("The API token has been revoked").send();
+    Ok(see_other("/admin/api_tokens"))
+}
@@ -1,12 +1,31 @@
-use actix_web::{http::header::ContentType, web, HttpResponse};
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse};
 use anyhow::Context;
+use askama::Template;
 use reqwest::header::LOCATION;
 use sqlx::PgPool;
 use uuid::Uuid;
 
 use crate::session_state::TypedSession;
+use crate::templates::html_response;
 use crate::utils::err500;
 
+#[derive(Template)]
+#[template(path = "admin/dashboard.html")]
+struct DashboardTemplate<'a> {
+    username: &'a str,
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/dashboard",
+    tag = "admin",
+    security(("session_cookie" = [])),
+    responses(
+        (status = 200, description = "Admin dashboard", body = String, content_type = "text/html"),
+        (status = 303, description = "Not logged in, redirects to /login")
+    )
+)]
 pub async fn admin_dashboard(
     session: TypedSession,
     connection_pool: web::Data<PgPool>,
@@ -20,32 +39,12 @@ pub async fn admin_dashboard(
             .insert_header((LOCATION, "/login"))
             .finish());
     };
-    Ok(HttpResponse::Ok()
-        .content_type(ContentType::html())
-        .body(format!(
-            r#"
-<!DOCTYPE html>
-<html lang="en">
-<head>
-    <meta http-equiv="content-type" content="text/html"; charset=utf-8">
-    <title>Admin Dashboard</title>
-</head>
-<body>
-    <p>Welcome {username}!</p>
-    <p>Actions:</p>
-    <ol>
-        <li><a href="/admin/password">Change password</a></li>
-        <li><a href="/admin/newsletters">Publish newsletter</></li>
-        <li>
-            <form name="logoutForm" action="/admin/logout" method="post">
-                <input type="submit" value="Logout">
-            </form>
-        </li>
-    </ol>
-</body>
-</html>
-                "#
-        )))
+    Ok(html_response(
+        StatusCode::OK,
+        &DashboardTemplate {
+            username: &username,
+        },
+    ))
 }
 
 #[tracing::instrument(name = "Get username", skip(connection_pool))]
@@ -0,0 +1,45 @@
+use actix_web::{http::StatusCode, web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use sqlx::PgPool;
+
+use super::ConfirmationEmailPage;
+use crate::email_templates::{get_custom_email, EmailTemplates, CONFIRMATION_PLACEHOLDERS};
+use crate::templates::html_response;
+use crate::utils::err500;
+
+#[utoipa::path(
+    get,
+    path = "/admin/emails/confirmation",
+    tag = "admin",
+    security(("session_cookie" = [])),
+    responses(
+        (status = 200, description = "Form to edit, preview and test the confirmation email", body = String, content_type = "text/html"),
+        (status = 303, description = "Not logged in, redirects to /login")
+    )
+)]
+#[tracing::instrument(name = "Confirmation email form", skip_all)]
+pub async fn confirmation_email_form(
+    connection_pool: web::Data<PgPool>,
+    email_templates: web::Data<EmailTemplates>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let custom = get_custom_email("confirmation", &connection_pool)
+        .await
+        .map_err(err500)?;
+    let customized = custom.is_some();
+    let template = custom.unwrap_or_else(|| email_templates.default_confirmation().clone());
+    let messages: Vec<&str> = flash_messages.iter().map(|m| m.content()).collect();
+    Ok(html_response(
+        StatusCode::OK,
+        &ConfirmationEmailPage {
+            messages,
+            placeholders: CONFIRMATION_PLACEHOLDERS,
+            customized,
+            subject: &template.subject,
+            html: &template.html,
+            text: &template.text,
+            test_email: "",
+            preview: None,
+        },
+    ))
+}
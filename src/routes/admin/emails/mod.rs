@@ -0,0 +1,37 @@
+mod get;
+mod post;
+
+use askama::Template;
+
+use crate::email_templates::{render_confirmation, EmailTemplate, PlaceholderError, RenderedEmail};
+
+pub use get::{__path_confirmation_email_form, confirmation_email_form};
+pub use post::{
+    __path_preview_confirmation_email, __path_reset_confirmation_email,
+    __path_save_confirmation_email, __path_send_test_confirmation_email,
+    preview_confirmation_email, reset_confirmation_email, save_confirmation_email,
+    send_test_confirmation_email,
+};
+
+#[derive(Template)]
+#[template(path = "admin/emails/confirmation.html")]
+struct ConfirmationEmailPage<'a> {
+    messages: Vec<&'a str>,
+    placeholders: &'a [&'a str],
+    // Whether an admin edited the email, rather than it using the default content
+    customized: bool,
+    subject: &'a str,
+    html: &'a str,
+    text: &'a str,
+    test_email: &'a str,
+    preview: Option<RenderedEmail>,
+}
+
+// Renders the email as a subscriber called "Jane Doe" would receive it for the default list
+fn render_sample(
+    template: &EmailTemplate,
+    base_url: &str,
+) -> Result<RenderedEmail, PlaceholderError> {
+    let confirmation_link = format!("{base_url}/subscriptions/confirm?subscription_token=example");
+    render_confirmation(template, "Jane Doe", "Newsletter", &confirmation_link)
+}
@@ -0,0 +1,286 @@
+use actix_web::{http::StatusCode, web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use sqlx::PgPool;
+
+use super::{render_sample, ConfirmationEmailPage};
+use crate::audit::record_audit_event;
+use crate::authentication::UserId;
+use crate::domain::SubscriberEmail;
+use crate::email_client::{EmailClient, EmailClientError};
+use crate::email_templates::{
+    get_custom_email, EmailTemplate, RenderedEmail, CONFIRMATION_PLACEHOLDERS,
+};
+use crate::startup::ApplicationBaseUrl;
+use crate::templates::html_response;
+use crate::utils::{err500, see_other};
+
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+pub struct FormData {
+    subject: String,
+    html: String,
+    text: String,
+    // Only used when sending a test
+    #[serde(default)]
+    test_email: String,
+}
+
+impl FormData {
+    fn template(&self) -> EmailTemplate {
+        EmailTemplate {
+            subject: self.subject.clone(),
+            html: self.html.clone(),
+            text: self.text.clone(),
+        }
+    }
+
+    fn validate(&self) -> Result<EmailTemplate, String> {
+        if self.subject.trim().is_empty() {
+            return Err("The subject cannot be empty".into());
+        }
+        let template = self.template();
+        template
+            .validate(CONFIRMATION_PLACEHOLDERS)
+            .map_err(|e| e.to_string())?;
+        Ok(template)
+    }
+}
+
+// Shows the form again with the submitted (possibly unsaved) content
+async fn render_form(
+    status: StatusCode,
+    form: &FormData,
+    message: &str,
+    preview: Option<RenderedEmail>,
+    connection_pool: &PgPool,
+) -> Result<HttpResponse, actix_web::Error> {
+    let customized = get_custom_email("confirmation", connection_pool)
+        .await
+        .map_err(err500)?
+        .is_some();
+    Ok(html_response(
+        status,
+        &ConfirmationEmailPage {
+            messages: vec![message],
+            placeholders: CONFIRMATION_PLACEHOLDERS,
+            customized,
+            subject: &form.subject,
+            html: &form.html,
+            text: &form.text,
+            test_email: &form.test_email,
+            preview,
+        },
+    ))
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/emails/confirmation",
+    tag = "admin",
+    security(("session_cookie" = [])),
+    request_body(
+        content = inline(FormData),
+        content_type = "application/x-www-form-urlencoded"
+    ),
+    responses(
+        (status = 303, description = "The email was saved, redirects to /admin/emails/confirmation"),
+        (status = 400, description = "The subject is empty or the content uses an unknown placeholder", body = String, content_type = "text/html")
+    )
+)]
+#[tracing::instrument(name = "Save the confirmation email", skip(form, connection_pool))]
+pub async fn save_confirmation_email(
+    form: web::Form<FormData>,
+    connection_pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let template = match form.validate() {
+        Ok(template) => template,
+        Err(e) => {
+            return render_form(StatusCode::BAD_REQUEST, &form, &e, None, &connection_pool).await
+        }
+    };
+    let mut transaction = connection_pool
+        .begin()
+        .await
+        .context("Failed to get a connection from the pool")
+        .map_err(err500)?;
+    sqlx::query!(
+        r#"
+        INSERT INTO custom_emails (email, subject, html, text, updated_at, updated_by)
+        VALUES ('confirmation', $1, $2, $3, now(), $4)
+        ON CONFLICT (email) DO UPDATE
+        SET subject = EXCLUDED.subject,
+            html = EXCLUDED.html,
+            text = EXCLUDED.text,
+            updated_at = EXCLUDED.updated_at,
+            updated_by = EXCLUDED.updated_by
+        "#,
+        template.subject,
+        template.html,
+        template.text,
+        *user_id
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to save the confirmation email")
+    .map_err(err500)?;
+    record_audit_event(
+        &mut transaction,
+        &format!("admin:{user_id}"),
+        "confirmation_email_updated",
+        serde_json::json!({ "subject": template.subject }),
+    )
+    .await
+    .context("Failed to record the audit event")
+    .map_err(err500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the confirmation email")
+        .map_err(err500)?;
+    FlashMessage::info("The confirmation email has been saved").send();
+    Ok(see_other("/admin/emails/confirmation"))
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/emails/confirmation/preview",
+    tag = "admin",
+    security(("session_cookie" = [])),
+    request_body(
+        content = inline(FormData),
+        content_type = "application/x-www-form-urlencoded"
+    ),
+    responses(
+        (status = 200, description = "The form, with the submitted content rendered for a sample subscriber", body = String, content_type = "text/html"),
+        (status = 400, description = "The subject is empty or the content uses an unknown placeholder", body = String, content_type = "text/html")
+    )
+)]
+#[tracing::instrument(name = "Preview the confirmation email", skip_all)]
+pub async fn preview_confirmation_email(
+    form: web::Form<FormData>,
+    connection_pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let rendered = form
+        .validate()
+        .and_then(|template| render_sample(&template, &base_url.0).map_err(|e| e.to_string()));
+    match rendered {
+        Ok(preview) => {
+            render_form(
+                StatusCode::OK,
+                &form,
+                "Preview of the unsaved content",
+                Some(preview),
+                &connection_pool,
+            )
+            .await
+        }
+        Err(e) => render_form(StatusCode::BAD_REQUEST, &form, &e, None, &connection_pool).await,
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/emails/confirmation/test",
+    tag = "admin",
+    security(("session_cookie" = [])),
+    request_body(
+        content = inline(FormData),
+        content_type = "application/x-www-form-urlencoded"
+    ),
+    responses(
+        (status = 200, description = "The submitted content was sent to `test_email` for a sample subscriber", body = String, content_type = "text/html"),
+        (status = 400, description = "The content or the test address is invalid", body = String, content_type = "text/html"),
+        (status = 500, description = "The test email could not be sent", body = String, content_type = "text/html")
+    )
+)]
+#[tracing::instrument(
+    name = "Send a test confirmation email",
+    skip(form, connection_pool, email_client, base_url),
+    fields(test_email = %form.test_email)
+)]
+pub async fn send_test_confirmation_email(
+    form: web::Form<FormData>,
+    connection_pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let prepared = form.validate().and_then(|template| {
+        let email = render_sample(&template, &base_url.0).map_err(|e| e.to_string())?;
+        let recipient = SubscriberEmail::parse(form.test_email.clone())?;
+        Ok((recipient, email))
+    });
+    let (recipient, email) = match prepared {
+        Ok(prepared) => prepared,
+        Err(e) => {
+            return render_form(StatusCode::BAD_REQUEST, &form, &e, None, &connection_pool).await
+        }
+    };
+    let (status, message) = match email_client
+        .send_email(&recipient, &email.subject, &email.html, &email.text)
+        .await
+    {
+        Ok(_) => (
+            StatusCode::OK,
+            format!("A test email has been sent to {recipient}"),
+        ),
+        Err(e @ EmailClientError::Recipient(_)) => {
+            tracing::warn!(error.cause_chain = ?e, "The test email address was refused");
+            (
+                StatusCode::BAD_REQUEST,
+                format!("The email provider refused to send to {recipient}"),
+            )
+        }
+        Err(e) => {
+            tracing::error!(error.cause_chain = ?e, "Failed to send a test confirmation email");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "The test email could not be sent".to_string(),
+            )
+        }
+    };
+    render_form(status, &form, &message, None, &connection_pool).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/emails/confirmation/reset",
+    tag = "admin",
+    security(("session_cookie" = [])),
+    responses((status = 303, description = "The default content is used again, redirects to /admin/emails/confirmation"))
+)]
+#[tracing::instrument(name = "Reset the confirmation email", skip(connection_pool))]
+pub async fn reset_confirmation_email(
+    connection_pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let mut transaction = connection_pool
+        .begin()
+        .await
+        .context("Failed to get a connection from the pool")
+        .map_err(err500)?;
+    sqlx::query!("DELETE FROM custom_emails WHERE email = 'confirmation'")
+        .execute(&mut transaction)
+        .await
+        .context("Failed to delete the confirmation email")
+        .map_err(err500)?;
+    record_audit_event(
+        &mut transaction,
+        &format!("admin:{user_id}"),
+        "confirmation_email_reset",
+        serde_json::json!({}),
+    )
+    .await
+    .context("Failed to record the audit event")
+    .map_err(err500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the reset")
+        .map_err(err500)?;
+    FlashMessage::info("The confirmation email is back to its default content").send();
+    Ok(see_other("/admin/emails/confirmation"))
+}
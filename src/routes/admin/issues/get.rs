@@ -0,0 +1,236 @@
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use askama::Template;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::templates::html_response;
+use crate::utils::{err404, err500};
+
+struct IssueRow {
+    id: Uuid,
+    title: String,
+    published_at: String,
+    delivered: i64,
+}
+
+struct IssueDetails {
+    title: String,
+    published_at: String,
+    segment: Option<serde_json::Value>,
+    track_opens: bool,
+    track_clicks: bool,
+    delivered: i64,
+    queued: i64,
+    suppressed: i64,
+    unique_opens: i64,
+}
+
+struct LinkStats {
+    url: String,
+    clicks: i64,
+    unique_clicks: i64,
+}
+
+#[derive(Template)]
+#[template(path = "admin/issues/list.html")]
+struct ListTemplate {
+    issues: Vec<IssueRow>,
+}
+
+#[derive(Template)]
+#[template(path = "admin/issues/details.html")]
+struct DetailsTemplate {
+    issue: IssueDetails,
+    lists: Vec<String>,
+    // Pretty-printed JSON, if the issue was sent to a segment of its lists
+    segment: Option<String>,
+    links: Vec<LinkStats>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/issues",
+    tag = "admin",
+    security(("session_cookie" = [])),
+    responses(
+        (status = 200, description = "The published issues, most recent first", body = String, content_type = "text/html"),
+        (status = 303, description = "Not logged in, redirects to /login")
+    )
+)]
+#[tracing::instrument(name = "List issues", skip_all)]
+pub async fn list_issues(
+    connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issues = sqlx::query_as!(
+        IssueRow,
+        r#"
+        SELECT
+            n.newsletter_issue_id AS id,
+            n.title,
+            n.published_at,
+            (
+                SELECT COUNT(*)
+                FROM deliveries d
+                WHERE d.newsletter_issue_id = n.newsletter_issue_id AND d.outcome = 'delivered'
+            ) AS "delivered!"
+        FROM newsletter_issues n
+        ORDER BY n.published_at DESC
+        "#
+    )
+    .fetch_all(connection_pool.as_ref())
+    .await
+    .context("Failed to fetch the issues")
+    .map_err(err500)?;
+    Ok(html_response(StatusCode::OK, &ListTemplate { issues }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/issues/{issue_id}",
+    tag = "admin",
+    security(("session_cookie" = [])),
+    params(("issue_id" = Uuid, Path, description = "Id of the newsletter issue")),
+    responses(
+        (status = 200, description = "Who the issue was sent to, with its open and click stats", body = String, content_type = "text/html"),
+        (status = 303, description = "Not logged in, redirects to /login"),
+        (status = 404, description = "No issue with this id")
+    )
+)]
+#[tracing::instrument(name = "Show issue details", skip(connection_pool))]
+pub async fn issue_details(
+    issue_id: web::Path<Uuid>,
+    connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issue_id = issue_id.into_inner();
+    let issue = get_issue(&connection_pool, issue_id)
+        .await
+        .map_err(err500)?
+        .ok_or_else(|| err404("Issue not found"))?;
+    let lists = get_issue_lists(&connection_pool, issue_id)
+        .await
+        .map_err(err500)?;
+    let links = get_link_stats(&connection_pool, issue_id)
+        .await
+        .map_err(err500)?;
+    let segment = issue
+        .segment
+        .as_ref()
+        .map(serde_json::to_string_pretty)
+        .transpose()
+        .map_err(err500)?;
+    Ok(html_response(
+        StatusCode::OK,
+        &DetailsTemplate {
+            issue,
+            lists,
+            segment,
+            links,
+        },
+    ))
+}
+
+#[tracing::instrument(skip(connection_pool))]
+async fn get_issue(
+    connection_pool: &PgPool,
+    issue_id: Uuid,
+) -> Result<Option<IssueDetails>, anyhow::Error> {
+    let issue = sqlx::query_as!(
+        IssueDetails,
+        r#"
+        SELECT
+            title,
+            published_at,
+            segment,
+            track_opens,
+            track_clicks,
+            (
+                SELECT COUNT(*)
+                FROM deliveries
+                WHERE newsletter_issue_id = $1 AND outcome = 'delivered'
+            ) AS "delivered!",
+            (
+                SELECT COUNT(*)
+                FROM deliveries
+                WHERE newsletter_issue_id = $1 AND outcome = 'suppressed'
+            ) AS "suppressed!",
+            (
+                SELECT COUNT(*)
+                FROM jobs
+                WHERE job_type = 'deliver_issue'
+                    AND status = 'queued'
+                    AND payload ->> 'newsletter_issue_id' = $1::text
+            ) AS "queued!",
+            (
+                SELECT COUNT(DISTINCT subscriber_id)
+                FROM email_events
+                WHERE newsletter_issue_id = $1 AND event = 'open'
+            ) AS "unique_opens!"
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1
+        "#,
+        issue_id
+    )
+    .fetch_optional(connection_pool)
+    .await
+    .context("Failed to fetch the issue")?;
+    Ok(issue)
+}
+
+#[tracing::instrument(skip(connection_pool))]
+async fn get_issue_lists(
+    connection_pool: &PgPool,
+    issue_id: Uuid,
+) -> Result<Vec<String>, anyhow::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT l.name
+        FROM newsletter_issue_lists il
+        JOIN lists l ON l.id = il.list_id
+        WHERE il.newsletter_issue_id = $1
+        ORDER BY l.name
+        "#,
+        issue_id
+    )
+    .fetch_all(connection_pool)
+    .await
+    .context("Failed to fetch the lists of the issue")?;
+    Ok(rows.into_iter().map(|r| r.name).collect())
+}
+
+#[tracing::instrument(skip(connection_pool))]
+async fn get_link_stats(
+    connection_pool: &PgPool,
+    issue_id: Uuid,
+) -> Result<Vec<LinkStats>, anyhow::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT
+            l.href,
+            COUNT(e.id) AS "clicks!",
+            COUNT(DISTINCT e.subscriber_id) AS "unique_clicks!"
+        FROM issue_links l
+        LEFT JOIN email_events e
+            ON e.newsletter_issue_id = l.newsletter_issue_id
+            AND e.link_id = l.link_id
+            AND e.event = 'click'
+        WHERE l.newsletter_issue_id = $1
+        GROUP BY l.link_id, l.href
+        ORDER BY l.link_id
+        "#,
+        issue_id
+    )
+    .fetch_all(connection_pool)
+    .await
+    .context("Failed to fetch the link stats")?;
+    Ok(rows
+        .into_iter()
+        .map(|r| LinkStats {
+            // Stored as written in the HTML content
+            url: htmlescape::decode_html(&r.href).unwrap_or(r.href),
+            clicks: r.clicks,
+            unique_clicks: r.unique_clicks,
+        })
+        .collect())
+}
@@ -0,0 +1,3 @@
+mod get;
+
+pub use get::{__path_issue_details, __path_list_issues, issue_details, list_issues};
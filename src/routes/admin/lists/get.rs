@@ -0,0 +1,63 @@
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use askama::Template;
+use sqlx::PgPool;
+
+use crate::templates::html_response;
+use crate::utils::err500;
+
+struct ListRow {
+    slug: String,
+    name: String,
+    confirmed: i64,
+    pending: i64,
+}
+
+#[derive(Template)]
+#[template(path = "admin/lists.html")]
+struct ListsTemplate<'a> {
+    messages: Vec<&'a str>,
+    lists: Vec<ListRow>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/lists",
+    tag = "admin",
+    security(("session_cookie" = [])),
+    responses(
+        (status = 200, description = "The lists with their subscriber counts, and a form to create one", body = String, content_type = "text/html"),
+        (status = 303, description = "Not logged in, redirects to /login")
+    )
+)]
+#[tracing::instrument(name = "List the lists", skip_all)]
+pub async fn list_lists(
+    connection_pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let lists = sqlx::query_as!(
+        ListRow,
+        r#"
+        SELECT
+            l.slug,
+            l.name,
+            COUNT(*) FILTER (WHERE s.status = 'confirmed') AS "confirmed!",
+            COUNT(*) FILTER (WHERE s.status = 'pending_confirmation') AS "pending!"
+        FROM lists l
+        LEFT JOIN list_subscriptions s ON s.list_id = l.id
+        GROUP BY l.id
+        ORDER BY l.name
+        "#
+    )
+    .fetch_all(connection_pool.as_ref())
+    .await
+    .context("Failed to fetch the lists")
+    .map_err(err500)?;
+    let messages: Vec<&str> = flash_messages.iter().map(|m| m.content()).collect();
+    Ok(html_response(
+        StatusCode::OK,
+        &ListsTemplate { messages, lists },
+    ))
+}
@@ -0,0 +1,5 @@
+mod get;
+mod post;
+
+pub use get::{__path_list_lists, list_lists};
+pub use post::{__path_create_list, create_list};
@@ -0,0 +1,96 @@
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::audit::record_audit_event;
+use crate::authentication::UserId;
+use crate::utils::{err500, see_other};
+
+#[derive(Debug, serde::Deserialize, utoipa::ToSchema)]
+pub struct FormData {
+    /// Identifier used by `POST /subscriptions`: lowercase letters, digits and dashes
+    slug: String,
+    name: String,
+}
+
+fn validate(form: &FormData) -> Result<(), &'static str> {
+    let slug_is_valid = !form.slug.is_empty()
+        && form.slug.len() <= 64
+        && form
+            .slug
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+    if !slug_is_valid {
+        return Err("The identifier can only contain lowercase letters, digits and dashes");
+    }
+    if form.name.trim().is_empty() {
+        return Err("The name cannot be empty");
+    }
+    Ok(())
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/lists",
+    tag = "admin",
+    security(("session_cookie" = [])),
+    request_body(
+        content = inline(FormData),
+        content_type = "application/x-www-form-urlencoded"
+    ),
+    responses((status = 303, description = "Outcome is flashed, redirects to /admin/lists"))
+)]
+#[tracing::instrument(name = "Create a list", skip(connection_pool, user_id))]
+pub async fn create_list(
+    form: web::Form<FormData>,
+    connection_pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if let Err(e) = validate(&form) {
+        FlashMessage::error(e).send();
+        return Ok(see_other("/admin/lists"));
+    }
+    let mut transaction = connection_pool
+        .begin()
+        .await
+        .context("Failed to get a connection from the pool")
+        .map_err(err500)?;
+    let created = sqlx::query!(
+        r#"
+        INSERT INTO lists (id, slug, name)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (slug) DO NOTHING
+        "#,
+        Uuid::new_v4(),
+        form.slug,
+        form.name.trim()
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to create the list")
+    .map_err(err500)?
+    .rows_affected()
+        == 1;
+    if !created {
+        FlashMessage::error(format!("There already is a list {}", form.slug)).send();
+        return Ok(see_other("/admin/lists"));
+    }
+    record_audit_event(
+        &mut transaction,
+        &format!("admin:{}", *user_id.into_inner()),
+        "list_created",
+        serde_json::json!({ "slug": form.slug, "name": form.name.trim() }),
+    )
+    .await
+    .context("Failed to record the audit event")
+    .map_err(err500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the new list")
+        .map_err(err500)?;
+    FlashMessage::info(format!("The list {} has been created", form.name.trim())).send();
+    Ok(see_other("/admin/lists"))
+}
@@ -6,6 +6,13 @@ use crate::{
     utils::{err500, see_other},
 };
 
+#[utoipa::path(
+    post,
+    path = "/admin/logout",
+    tag = "admin",
+    security(("session_cookie" = [])),
+    responses((status = 303, description = "Session cleared, redirects to /login"))
+)]
 pub async fn log_out(session: TypedSession) -> Result<HttpResponse, actix_web::Error> {
     if session.get_user_id().map_err(err500)?.is_none() {
         Ok(see_other("/login"))
@@ -1,9 +1,21 @@
+mod api_tokens;
 mod dashboard;
+mod emails;
+mod issues;
+mod lists;
 mod logout;
 mod newsletter;
 mod password;
+mod subscribers;
+mod suppressions;
 
-pub use dashboard::admin_dashboard;
+pub use api_tokens::*;
+pub use dashboard::{__path_admin_dashboard, admin_dashboard};
+pub use emails::*;
+pub use issues::*;
+pub use lists::*;
 pub use logout::*;
 pub use newsletter::*;
 pub use password::*;
+pub use subscribers::*;
+pub use suppressions::*;
@@ -1,60 +1,33 @@
-use actix_web::http::header::ContentType;
-use actix_web::HttpResponse;
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse};
 use actix_web_flash_messages::IncomingFlashMessages;
-use std::fmt::Write;
+use sqlx::PgPool;
 
+use super::{render_publish_form, NewsletterFormData};
+use crate::lists::DEFAULT_LIST;
+use crate::utils::err500;
+
+#[utoipa::path(
+    get,
+    path = "/admin/newsletters",
+    tag = "admin",
+    security(("session_cookie" = [])),
+    responses(
+        (status = 200, description = "Newsletter publishing form", body = String, content_type = "text/html"),
+        (status = 303, description = "Not logged in, redirects to /login")
+    )
+)]
 pub async fn publish_newsletter_form(
+    connection_pool: web::Data<PgPool>,
     flash_messages: IncomingFlashMessages,
 ) -> Result<HttpResponse, actix_web::Error> {
-    let mut msg_html = String::new();
-    for m in flash_messages.iter() {
-        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
-    }
-
-    let idempotency_key = uuid::Uuid::new_v4().to_string();
-    Ok(HttpResponse::Ok()
-        .content_type(ContentType::html())
-        .body(format!(
-            r#"<!DOCTYPE html>
-<html lang="en">
-<head>
-    <meta http-equiv="content-type" content="text/html; charset=utf-8">
-    <title>Publish Newsletter Issue</title>
-</head>
-<body>
-    {msg_html}
-    <form action="/admin/newsletters" method="post">
-        <label>Title:<br>
-            <input
-                type="text"
-                placeholder="Enter the issue title"
-                name="title"
-            >
-        </label>
-        <br>
-        <label>Plain text content:<br>
-            <textarea
-                placeholder="Enter the content in plain text"
-                name="text_content"
-                rows="20"
-                cols="50"
-            ></textarea>
-        </label>
-        <br>
-        <label>HTML content:<br>
-            <textarea
-                placeholder="Enter the content in HTML format"
-                name="html_content"
-                rows="20"
-                cols="50"
-            ></textarea>
-        </label>
-        <br>
-        <input hidden type="text" name="idempotency_key" value="{idempotency_key}">
-        <button type="submit">Publish</button>
-    </form>
-    <p><a href="/admin/dashboard">&lt;- Back</a></p>
-</body>
-</html>"#,
-        )))
+    let messages: Vec<&str> = flash_messages.iter().map(|m| m.content()).collect();
+    let form = NewsletterFormData {
+        idempotency_key: uuid::Uuid::new_v4().to_string(),
+        list: vec![DEFAULT_LIST.to_string()],
+        ..Default::default()
+    };
+    render_publish_form(&connection_pool, StatusCode::OK, messages, &form)
+        .await
+        .map_err(err500)
 }
@@ -1,5 +1,94 @@
 mod get;
 mod post;
 
-pub use get::publish_newsletter_form;
-pub use post::publish_newsletter;
+use actix_web::http::StatusCode;
+use actix_web::HttpResponse;
+use askama::Template;
+use sqlx::PgPool;
+
+use crate::email_templates::ISSUE_PLACEHOLDERS;
+use crate::lists::{get_lists, MailingList};
+use crate::templates::html_response;
+use crate::tracking::TrackingSettings;
+
+pub use get::{__path_publish_newsletter_form, publish_newsletter_form};
+pub use post::{
+    __path_count_newsletter_recipients, __path_publish_newsletter, count_newsletter_recipients,
+    publish_newsletter, replay_newsletter_submissions,
+};
+
+#[derive(Default, serde::Deserialize, utoipa::ToSchema)]
+pub struct NewsletterFormData {
+    title: String,
+    text_content: String,
+    html_content: String,
+    idempotency_key: String,
+    /// Slugs of the lists to send the issue to, repeated for each list. `newsletter` if not given
+    #[serde(default)]
+    list: Vec<String>,
+    /// Only send to subscribers with one of these comma separated tags
+    #[serde(default)]
+    tags: String,
+    /// Only send to subscribers with this attribute
+    #[serde(default)]
+    attribute_name: String,
+    /// Only send to subscribers whose attribute has this value, compared as text
+    #[serde(default)]
+    attribute_value: String,
+    /// Only send to subscribers who subscribed before this date (YYYY-MM-DD)
+    #[serde(default)]
+    subscribed_before: String,
+    /// Only send to subscribers who subscribed on or after this date (YYYY-MM-DD)
+    #[serde(default)]
+    subscribed_after: String,
+    /// Record when recipients open the issue if set (to any value)
+    track_opens: Option<String>,
+    /// Record which links recipients click if set (to any value)
+    track_clicks: Option<String>,
+}
+
+impl NewsletterFormData {
+    // Checkboxes are only submitted when checked
+    fn tracking(&self) -> TrackingSettings {
+        TrackingSettings {
+            opens: self.track_opens.is_some(),
+            clicks: self.track_clicks.is_some(),
+        }
+    }
+}
+
+#[derive(Template)]
+#[template(path = "admin/newsletter.html")]
+struct PublishNewsletterTemplate<'a> {
+    messages: Vec<&'a str>,
+    form: &'a NewsletterFormData,
+    placeholders: &'a [&'a str],
+    // (list, whether it is checked)
+    lists: Vec<(MailingList, bool)>,
+}
+
+// Renders the publish form filled with `form`
+async fn render_publish_form(
+    connection_pool: &PgPool,
+    status: StatusCode,
+    messages: Vec<&str>,
+    form: &NewsletterFormData,
+) -> Result<HttpResponse, sqlx::Error> {
+    let lists = get_lists(connection_pool)
+        .await?
+        .into_iter()
+        .map(|list| {
+            let checked = form.list.contains(&list.slug);
+            (list, checked)
+        })
+        .collect();
+    Ok(html_response(
+        status,
+        &PublishNewsletterTemplate {
+            messages,
+            form,
+            placeholders: ISSUE_PLACEHOLDERS,
+            lists,
+        },
+    ))
+}
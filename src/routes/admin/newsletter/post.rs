@@ -1,67 +1,185 @@
+use super::{render_publish_form, NewsletterFormData};
 use crate::authentication::UserId;
-use crate::idempotency::{save_response, try_processing, IdempotencyKey, NextAction};
-use crate::utils::{err400, err500, see_other};
+use crate::domain::Segment;
+use crate::email_templates::EmailTemplate;
+use crate::idempotency::{is_replayed, save_idempotent_responses, RequestTransaction};
+use crate::jobs::{notify_workers, DeliverIssue, Job};
+use crate::lists::{get_list_by_slug, DEFAULT_LIST};
+use crate::tracking::{trackable_links, TrackingSettings};
+use crate::utils::{err500, see_other};
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::StatusCode;
 use actix_web::web::ReqData;
 use actix_web::{web, HttpResponse};
 use actix_web_flash_messages::FlashMessage;
+use actix_web_lab::extract::UrlEncodedForm;
+use actix_web_lab::middleware::Next;
 use anyhow::Context;
-use sqlx::{PgPool, Postgres, Transaction};
+use sqlx::{PgExecutor, PgPool, Postgres, Transaction};
 use uuid::Uuid;
 
-#[derive(serde::Deserialize)]
-pub struct FormData {
-    title: String,
-    text_content: String,
-    html_content: String,
-    idempotency_key: String,
-}
-
+#[utoipa::path(
+    post,
+    path = "/admin/newsletters",
+    tag = "admin",
+    security(("session_cookie" = [])),
+    request_body(
+        content = inline(NewsletterFormData),
+        content_type = "application/x-www-form-urlencoded"
+    ),
+    responses(
+        (status = 303, description = "Issue accepted for delivery, or the form is invalid. Redirects to the form"),
+        (status = 400, description = "The idempotency key is invalid"),
+        (status = 409, description = "The same form is still being submitted. Retry after the `Retry-After` delay"),
+        (status = 422, description = "The idempotency key was already used to submit a different form"),
+        (status = 500, description = "The issue could not be stored")
+    )
+)]
 #[tracing::instrument(
     name = "Publish a newsletter issue",
     skip_all,
     fields(user_id=%*user_id)
 )]
 pub async fn publish_newsletter(
-    form: web::Form<FormData>,
+    form: UrlEncodedForm<NewsletterFormData>,
     user_id: ReqData<UserId>,
     connection_pool: web::Data<PgPool>,
+    mut transaction: RequestTransaction,
 ) -> Result<HttpResponse, actix_web::Error> {
-    let user_id = user_id.into_inner();
-    let FormData {
-        title,
-        text_content,
-        html_content,
-        idempotency_key,
-    } = form.0;
-    // First try to see if a saved_response is available
-    let idempotency_key: IdempotencyKey = idempotency_key.try_into().map_err(err400)?;
-    let mut transaction = match try_processing(&connection_pool, &idempotency_key, *user_id)
+    let (list_ids, segment) = match validate_form(&connection_pool, &form)
         .await
         .map_err(err500)?
     {
-        NextAction::StartProcessing(t) => t,
-        NextAction::ReturnSavedResponse(saved_response) => {
-            success_message().send();
-            return Ok(saved_response);
+        Ok(recipients) => recipients,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other("/admin/newsletters"));
         }
     };
+    let tracking = form.tracking();
+    let NewsletterFormData {
+        title,
+        text_content,
+        html_content,
+        ..
+    } = form.0;
+    // Submitting the form again replays the response, see `save_idempotent_responses`: the
+    // issue is saved in the same transaction as the response
+    let issue_id = insert_newsletter_issue(
+        &mut transaction,
+        &title,
+        &text_content,
+        &html_content,
+        &list_ids,
+        &segment,
+        tracking,
+    )
+    .await
+    .context("Failed to store newletter issue details")
+    .map_err(err500)?;
 
-    let issue_id = insert_newsletter_issue(&mut transaction, &title, &text_content, &html_content)
-        .await
-        .context("Failed to store newletter issue details")
-        .map_err(err500)?;
-
-    enqueue_delivery_tasks(&mut transaction, issue_id)
+    enqueue_delivery_tasks(&mut transaction, issue_id, &list_ids, &segment)
         .await
         .context("Failed to enqueue the delivery tasks")
         .map_err(err500)?;
 
-    let response = see_other("/admin/newsletters");
-    let response = save_response(transaction, &idempotency_key, *user_id, response)
+    transaction
+        .commit()
         .await
+        .context("Failed to commit the newsletter issue")
         .map_err(err500)?;
     success_message().send();
-    Ok(response)
+    Ok(see_other("/admin/newsletters"))
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/newsletters/recipients",
+    tag = "admin",
+    security(("session_cookie" = [])),
+    request_body(
+        content = inline(NewsletterFormData),
+        content_type = "application/x-www-form-urlencoded"
+    ),
+    responses(
+        (status = 200, description = "The publish form, filled in and showing how many subscribers the issue would go to", body = String, content_type = "text/html"),
+        (status = 400, description = "The publish form, showing why the placeholders, lists or segment are invalid", body = String, content_type = "text/html")
+    )
+)]
+#[tracing::instrument(name = "Count the recipients of an issue", skip_all)]
+pub async fn count_newsletter_recipients(
+    form: UrlEncodedForm<NewsletterFormData>,
+    connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let (status, message) = match validate_form(&connection_pool, &form)
+        .await
+        .map_err(err500)?
+    {
+        Ok((list_ids, segment)) => {
+            let count = count_recipients(connection_pool.as_ref(), &list_ids, &segment)
+                .await
+                .context("Failed to count the recipients")
+                .map_err(err500)?;
+            (
+                StatusCode::OK,
+                format!("This issue would go to {count} subscriber(s)"),
+            )
+        }
+        Err(e) => (StatusCode::BAD_REQUEST, e),
+    };
+    render_publish_form(&connection_pool, status, vec![&message], &form)
+        .await
+        .map_err(err500)
+}
+
+// Resolves the lists and segment of the form, or explains why the form is invalid
+async fn validate_form(
+    connection_pool: &PgPool,
+    form: &NewsletterFormData,
+) -> Result<Result<(Vec<Uuid>, Segment), String>, sqlx::Error> {
+    let content = EmailTemplate {
+        subject: form.title.clone(),
+        html: form.html_content.clone(),
+        text: form.text_content.clone(),
+    };
+    if let Err(e) = content.validate_issue() {
+        return Ok(Err(e.to_string()));
+    }
+    let list_ids = match get_list_ids(connection_pool, &form.list).await? {
+        Ok(list_ids) => list_ids,
+        Err(unknown) => return Ok(Err(format!("There is no list {unknown}"))),
+    };
+    let segment = Segment::parse(
+        &form.tags,
+        &form.attribute_name,
+        &form.attribute_value,
+        &form.subscribed_before,
+        &form.subscribed_after,
+    );
+    Ok(segment.map(|segment| (list_ids, segment)))
+}
+
+// Resolves list slugs to ids, or returns the first slug that is not a list
+#[tracing::instrument(skip(connection_pool))]
+async fn get_list_ids(
+    connection_pool: &PgPool,
+    slugs: &[String],
+) -> Result<Result<Vec<Uuid>, String>, sqlx::Error> {
+    let default_list = [DEFAULT_LIST.to_string()];
+    let slugs = if slugs.is_empty() {
+        &default_list
+    } else {
+        slugs
+    };
+    let mut list_ids = Vec::with_capacity(slugs.len());
+    for slug in slugs {
+        match get_list_by_slug(connection_pool, slug).await? {
+            Some(list) => list_ids.push(list.id),
+            None => return Ok(Err(slug.clone())),
+        }
+    }
+    Ok(Ok(list_ids))
 }
 
 #[tracing::instrument(skip_all)]
@@ -70,8 +188,16 @@ async fn insert_newsletter_issue(
     title: &str,
     text_content: &str,
     html_content: &str,
+    list_ids: &[Uuid],
+    segment: &Segment,
+    tracking: TrackingSettings,
 ) -> Result<Uuid, sqlx::Error> {
     let newsletter_issue_id = uuid::Uuid::new_v4();
+    let segment = if segment.is_empty() {
+        None
+    } else {
+        Some(serde_json::to_value(segment).expect("A segment is always valid JSON"))
+    };
     sqlx::query!(
         r#"
         INSERT INTO newsletter_issues (
@@ -79,40 +205,141 @@ async fn insert_newsletter_issue(
             title,
             text_content,
             html_content,
+            segment,
+            track_opens,
+            track_clicks,
             published_at
         )
-        VALUES ($1, $2, $3, $4, now())
+        VALUES ($1, $2, $3, $4, $5, $6, $7, now())
         "#,
         newsletter_issue_id,
         title,
         text_content,
-        html_content
+        html_content,
+        segment,
+        tracking.opens,
+        tracking.clicks
     )
-    .execute(transaction)
+    .execute(&mut *transaction)
+    .await?;
+    if tracking.clicks {
+        let links = trackable_links(html_content);
+        sqlx::query!(
+            r#"
+            INSERT INTO issue_links (newsletter_issue_id, link_id, href)
+            SELECT $1, t.link_id, t.href
+            FROM UNNEST($2::text[]) WITH ORDINALITY AS t(href, link_id)
+            "#,
+            newsletter_issue_id,
+            &links
+        )
+        .execute(&mut *transaction)
+        .await?;
+    }
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issue_lists (newsletter_issue_id, list_id)
+        SELECT $1, list_id
+        FROM UNNEST($2::uuid[]) AS t(list_id)
+        ON CONFLICT DO NOTHING
+        "#,
+        newsletter_issue_id,
+        list_ids
+    )
+    .execute(&mut *transaction)
     .await?;
     Ok(newsletter_issue_id)
 }
 
 #[tracing::instrument(skip_all)]
+// Subscribers on several of the lists get the issue once. Only confirmed subscribers are
+// enqueued, which leaves out addresses that bounced or complained.
+// The conditions must be kept in sync with `count_recipients`.
 async fn enqueue_delivery_tasks(
     transaction: &mut Transaction<'_, Postgres>,
     newsletter_issue_id: Uuid,
+    list_ids: &[Uuid],
+    segment: &Segment,
 ) -> Result<(), sqlx::Error> {
+    // `DeliverIssue` jobs are built in SQL, there can be many of them
     sqlx::query!(
         r#"
-        INSERT INTO issue_delivery_queue (
-            newsletter_issue_id,
-            subscriber_email
-        )
-        SELECT $1, email
-        FROM subscriptions
-        WHERE status = 'confirmed'
+        INSERT INTO jobs (job_type, payload, max_attempts)
+        SELECT
+            $8,
+            jsonb_build_object('newsletter_issue_id', $1::uuid, 'subscriber_email', email),
+            $9
+        FROM (
+            SELECT DISTINCT s.email
+            FROM subscriptions s
+            JOIN list_subscriptions l ON l.subscriber_id = s.id
+            WHERE s.status = 'confirmed'
+                AND l.status = 'confirmed'
+                AND l.list_id = ANY($2)
+                AND (cardinality($3::text[]) = 0 OR s.tags && $3)
+                AND ($4::text IS NULL OR s.attributes ? $4)
+                AND ($5::text IS NULL OR s.attributes ->> $4 = $5)
+                AND ($6::timestamptz IS NULL OR s.subscribed_at < $6)
+                AND ($7::timestamptz IS NULL OR s.subscribed_at >= $7)
+        ) AS recipients
+        "#,
+        newsletter_issue_id,
+        list_ids,
+        &segment.tags,
+        segment.attribute_name(),
+        segment.attribute_value(),
+        segment.subscribed_before(),
+        segment.subscribed_after(),
+        DeliverIssue::JOB_TYPE,
+        DeliverIssue::MAX_ATTEMPTS
+    )
+    .execute(&mut *transaction)
+    .await?;
+    notify_workers(transaction, DeliverIssue::JOB_TYPE).await
+}
+
+#[tracing::instrument(skip(executor))]
+async fn count_recipients(
+    executor: impl PgExecutor<'_>,
+    list_ids: &[Uuid],
+    segment: &Segment,
+) -> Result<i64, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT COUNT(DISTINCT s.id) AS "count!"
+        FROM subscriptions s
+        JOIN list_subscriptions l ON l.subscriber_id = s.id
+        WHERE s.status = 'confirmed'
+            AND l.status = 'confirmed'
+            AND l.list_id = ANY($1)
+            AND (cardinality($2::text[]) = 0 OR s.tags && $2)
+            AND ($3::text IS NULL OR s.attributes ? $3)
+            AND ($4::text IS NULL OR s.attributes ->> $3 = $4)
+            AND ($5::timestamptz IS NULL OR s.subscribed_at < $5)
+            AND ($6::timestamptz IS NULL OR s.subscribed_at >= $6)
         "#,
-        newsletter_issue_id
+        list_ids,
+        &segment.tags,
+        segment.attribute_name(),
+        segment.attribute_value(),
+        segment.subscribed_before(),
+        segment.subscribed_after()
     )
-    .execute(transaction)
+    .fetch_one(executor)
     .await?;
-    Ok(())
+    Ok(row.count)
+}
+
+// Wraps `publish_newsletter`: a form submitted again shows the same confirmation as the first time
+pub async fn replay_newsletter_submissions(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, actix_web::Error> {
+    let response = save_idempotent_responses(req, next).await?;
+    if is_replayed(&response) {
+        success_message().send();
+    }
+    Ok(response)
 }
 
 fn success_message() -> FlashMessage {
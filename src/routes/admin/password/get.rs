@@ -1,12 +1,29 @@
-use actix_web::{http::header::ContentType, HttpResponse};
+use actix_web::{http::StatusCode, HttpResponse};
 use actix_web_flash_messages::IncomingFlashMessages;
-use std::fmt::Write;
+use askama::Template;
 
 use crate::{
     session_state::TypedSession,
+    templates::html_response,
     utils::{err500, see_other},
 };
 
+#[derive(Template)]
+#[template(path = "admin/password.html")]
+struct ChangePasswordTemplate<'a> {
+    messages: Vec<&'a str>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/password",
+    tag = "admin",
+    security(("session_cookie" = [])),
+    responses(
+        (status = 200, description = "Change password form", body = String, content_type = "text/html"),
+        (status = 303, description = "Not logged in, redirects to /login")
+    )
+)]
 pub async fn change_password_form(
     session: TypedSession,
     flash_messages: IncomingFlashMessages,
@@ -14,55 +31,9 @@ pub async fn change_password_form(
     if session.get_user_id().map_err(err500)?.is_none() {
         return Ok(see_other("/login"));
     };
-    let mut msg_html = String::new();
-    for msg in flash_messages.iter() {
-        writeln!(msg_html, "<p><i>{}</i></p>", msg.content()).unwrap();
-    }
-    Ok(HttpResponse::Ok()
-        .content_type(ContentType::html())
-        .body(format!(
-            r#"
-<!DOCTYPE html>
-<html lang="en">
-<head>
-    <meta http-equiv="content-type" content-type="text/html"; charset="utf-8">
-    <title>Change Password</title>
-</head>
-<body>
-    {msg_html}
-    <form action="/admin/password" method="post">
-        <label>
-            Current Password
-            <input
-                type="password"
-                placeholder="Enter current password"
-                name="current_password"
-            >
-        </label>
-        <br>
-        <label>
-            New Password
-            <input
-                type="password"
-                placeholder="Enter new password"
-                name="new_password"
-            >
-        </label>
-        <br>
-        <label>
-            Confirm New Password
-            <input
-                type="password"
-                placeholder="Enter new password again"
-                name="confirm_new_password"
-            >
-        </label>
-        <br>
-        <button type="submit">Change password</button>
-    </form>
-    <p><a href="/admin/dashboard">&lt;- Back</a></p>
-</body>
-</html>
-            "#
-        )))
+    let messages: Vec<&str> = flash_messages.iter().map(|m| m.content()).collect();
+    Ok(html_response(
+        StatusCode::OK,
+        &ChangePasswordTemplate { messages },
+    ))
 }
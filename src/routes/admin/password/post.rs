@@ -9,13 +9,27 @@ use crate::{
     utils::{err500, see_other},
 };
 
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, utoipa::ToSchema)]
 pub struct FormData {
+    #[schema(value_type = String, format = Password)]
     current_password: Secret<String>,
+    #[schema(value_type = String, format = Password)]
     new_password: Secret<String>,
+    #[schema(value_type = String, format = Password)]
     confirm_new_password: Secret<String>,
 }
 
+#[utoipa::path(
+    post,
+    path = "/admin/password",
+    tag = "admin",
+    security(("session_cookie" = [])),
+    request_body(
+        content = inline(FormData),
+        content_type = "application/x-www-form-urlencoded"
+    ),
+    responses((status = 303, description = "Outcome is flashed, redirects to /admin/password"))
+)]
 pub async fn change_password(
     form: web::Form<FormData>,
     connection_pool: web::Data<PgPool>,
@@ -0,0 +1,97 @@
+use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType};
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, SecondsFormat, Utc};
+use sqlx::PgPool;
+use std::collections::BTreeSet;
+
+use super::get::ListParameters;
+use crate::utils::err500;
+
+struct ExportRow {
+    email: String,
+    name: String,
+    status: String,
+    subscribed_at: DateTime<Utc>,
+    tags: Vec<String>,
+    attributes: serde_json::Value,
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/subscribers/export",
+    tag = "admin",
+    security(("session_cookie" = [])),
+    params(ListParameters),
+    responses(
+        (status = 200, description = "Subscribers matching the filters as CSV", body = String, content_type = "text/csv"),
+        (status = 303, description = "Not logged in, redirects to /login")
+    )
+)]
+#[tracing::instrument(name = "Export subscribers as CSV", skip(connection_pool))]
+pub async fn export_subscribers(
+    parameters: web::Query<ListParameters>,
+    connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let rows = sqlx::query_as!(
+        ExportRow,
+        r#"
+        SELECT email, name, status, subscribed_at, tags, attributes
+        FROM subscriptions
+        WHERE
+            ($1::text IS NULL OR email ILIKE '%' || $1 || '%' OR name ILIKE '%' || $1 || '%') AND
+            ($2::text IS NULL OR status = $2)
+        ORDER BY subscribed_at, email
+        "#,
+        parameters.search(),
+        parameters.status()
+    )
+    .fetch_all(connection_pool.as_ref())
+    .await
+    .context("Failed to fetch subscribers to export")
+    .map_err(err500)?;
+
+    // Written the way the import reads them back: tags separated by semicolons and a column per
+    // attribute. Attributes that are not strings are written as JSON, and imported as strings.
+    let attribute_names: BTreeSet<&String> = rows
+        .iter()
+        .filter_map(|row| row.attributes.as_object())
+        .flat_map(|attributes| attributes.keys())
+        .collect();
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    let headers = ["email", "name", "status", "subscribed_at", "tags"];
+    writer
+        .write_record(
+            headers
+                .iter()
+                .copied()
+                .chain(attribute_names.iter().map(|n| n.as_str())),
+        )
+        .map_err(err500)?;
+    for row in &rows {
+        let mut record = vec![
+            row.email.clone(),
+            row.name.clone(),
+            row.status.clone(),
+            row.subscribed_at
+                .to_rfc3339_opts(SecondsFormat::AutoSi, true),
+            row.tags.join(";"),
+        ];
+        for name in &attribute_names {
+            record.push(match row.attributes.get(name.as_str()) {
+                None | Some(serde_json::Value::Null) => String::new(),
+                Some(serde_json::Value::String(value)) => value.clone(),
+                Some(value) => value.to_string(),
+            });
+        }
+        writer.write_record(&record).map_err(err500)?;
+    }
+    let body = writer.into_inner().map_err(err500)?;
+    Ok(HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename("subscribers.csv".into())],
+        })
+        .body(body))
+}
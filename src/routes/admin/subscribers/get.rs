@@ -0,0 +1,376 @@
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use askama::Template;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::templates::html_response;
+use crate::utils::{err404, err500};
+
+const PAGE_SIZE: i64 = 25;
+const STATUSES: [&str; 5] = [
+    "pending_confirmation",
+    "confirmed",
+    "unsubscribed",
+    "bounced",
+    "complained",
+];
+
+#[derive(Debug, serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListParameters {
+    /// Page to show, starting from 1
+    page: Option<i64>,
+    /// Case-insensitive match against the subscriber email or name
+    search: Option<String>,
+    /// Only show subscribers with this status
+    status: Option<String>,
+}
+
+impl ListParameters {
+    // Empty form fields are submitted as empty strings, treat them as "no filter"
+    pub fn search(&self) -> Option<&str> {
+        self.search.as_deref().filter(|s| !s.trim().is_empty())
+    }
+    pub fn status(&self) -> Option<&str> {
+        self.status.as_deref().filter(|s| !s.is_empty())
+    }
+}
+
+struct SubscriberRow {
+    id: Uuid,
+    email: String,
+    name: String,
+    status: String,
+    subscribed_at: DateTime<Utc>,
+}
+
+#[derive(Template)]
+#[template(path = "admin/subscribers/list.html")]
+struct ListTemplate<'a> {
+    search: &'a str,
+    // (status, whether it is the current filter)
+    status_options: Vec<(&'static str, bool)>,
+    total: i64,
+    filter_query: String,
+    subscribers: Vec<SubscriberRow>,
+    page: i64,
+    page_count: i64,
+}
+
+#[derive(Template)]
+#[template(path = "admin/subscribers/details.html")]
+struct DetailsTemplate<'a> {
+    messages: Vec<&'a str>,
+    subscriber: SubscriberRow,
+    token_status: String,
+    // (list name, status on the list)
+    lists: Vec<(String, String)>,
+    // Comma separated
+    tags: String,
+    // Pretty-printed JSON object
+    attributes: String,
+    history: Vec<(String, DateTime<Utc>)>,
+    deliveries: Vec<(String, DateTime<Utc>)>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/subscribers",
+    tag = "admin",
+    security(("session_cookie" = [])),
+    params(ListParameters),
+    responses(
+        (status = 200, description = "A page of subscribers", body = String, content_type = "text/html"),
+        (status = 303, description = "Not logged in, redirects to /login")
+    )
+)]
+#[tracing::instrument(name = "List subscribers", skip(connection_pool))]
+pub async fn list_subscribers(
+    parameters: web::Query<ListParameters>,
+    connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let search = parameters.search();
+    let status = parameters.status();
+
+    let total = count_subscribers(&connection_pool, search, status)
+        .await
+        .map_err(err500)?;
+    let page_count = ((total + PAGE_SIZE - 1) / PAGE_SIZE).max(1);
+    // Out of range pages show the closest one, the offset of a page past the last one could
+    // overflow
+    let page = parameters.page.unwrap_or(1).clamp(1, page_count);
+    let subscribers = get_subscribers_page(&connection_pool, search, status, page)
+        .await
+        .map_err(err500)?;
+
+    let filter_query = format!(
+        "search={}&status={}",
+        urlencoding::encode(search.unwrap_or_default()),
+        urlencoding::encode(status.unwrap_or_default())
+    );
+    let status_options = STATUSES
+        .iter()
+        .map(|&option| (option, Some(option) == status))
+        .collect();
+    Ok(html_response(
+        StatusCode::OK,
+        &ListTemplate {
+            search: search.unwrap_or_default(),
+            status_options,
+            total,
+            filter_query,
+            subscribers,
+            page,
+            page_count,
+        },
+    ))
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/subscribers/{subscriber_id}",
+    tag = "admin",
+    security(("session_cookie" = [])),
+    params(("subscriber_id" = Uuid, Path, description = "Id of the subscriber")),
+    responses(
+        (status = 200, description = "Subscriber details", body = String, content_type = "text/html"),
+        (status = 303, description = "Not logged in, redirects to /login"),
+        (status = 404, description = "No subscriber with this id")
+    )
+)]
+#[tracing::instrument(
+    name = "Show subscriber details",
+    skip(connection_pool, flash_messages)
+)]
+pub async fn subscriber_details(
+    subscriber_id: web::Path<Uuid>,
+    connection_pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber_id = subscriber_id.into_inner();
+    let subscriber = get_subscriber(&connection_pool, subscriber_id)
+        .await
+        .map_err(err500)?
+        .ok_or_else(|| err404("Subscriber not found"))?;
+
+    let messages: Vec<&str> = flash_messages.iter().map(|m| m.content()).collect();
+
+    let (n_issued, n_usable) = count_tokens(&connection_pool, subscriber_id)
+        .await
+        .map_err(err500)?;
+    let token_status = match n_issued {
+        0 => "No confirmation token issued".to_string(),
+        n => format!("{n} confirmation token(s) issued, {n_usable} unused and not expired"),
+    };
+    let lists = get_list_subscriptions(&connection_pool, subscriber_id)
+        .await
+        .map_err(err500)?;
+    let (tags, attributes) = get_tags_and_attributes(&connection_pool, subscriber_id)
+        .await
+        .map_err(err500)?;
+    let history = get_subscription_events(&connection_pool, subscriber_id)
+        .await
+        .map_err(err500)?;
+    let deliveries = get_delivered_issues(&connection_pool, &subscriber.email)
+        .await
+        .map_err(err500)?;
+    Ok(html_response(
+        StatusCode::OK,
+        &DetailsTemplate {
+            messages,
+            subscriber,
+            token_status,
+            lists,
+            tags: tags.join(", "),
+            attributes: serde_json::to_string_pretty(&attributes).map_err(err500)?,
+            history,
+            deliveries,
+        },
+    ))
+}
+
+#[tracing::instrument(skip(connection_pool))]
+async fn count_subscribers(
+    connection_pool: &PgPool,
+    search: Option<&str>,
+    status: Option<&str>,
+) -> Result<i64, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT COUNT(*) as "count!"
+        FROM subscriptions
+        WHERE
+            ($1::text IS NULL OR email ILIKE '%' || $1 || '%' OR name ILIKE '%' || $1 || '%') AND
+            ($2::text IS NULL OR status = $2)
+        "#,
+        search,
+        status
+    )
+    .fetch_one(connection_pool)
+    .await
+    .context("Failed to count subscribers")?;
+    Ok(row.count)
+}
+
+#[tracing::instrument(skip(connection_pool))]
+async fn get_subscribers_page(
+    connection_pool: &PgPool,
+    search: Option<&str>,
+    status: Option<&str>,
+    page: i64,
+) -> Result<Vec<SubscriberRow>, anyhow::Error> {
+    let rows = sqlx::query_as!(
+        SubscriberRow,
+        r#"
+        SELECT id, email, name, status, subscribed_at
+        FROM subscriptions
+        WHERE
+            ($1::text IS NULL OR email ILIKE '%' || $1 || '%' OR name ILIKE '%' || $1 || '%') AND
+            ($2::text IS NULL OR status = $2)
+        ORDER BY subscribed_at DESC, email
+        LIMIT $3
+        OFFSET $4
+        "#,
+        search,
+        status,
+        PAGE_SIZE,
+        (page - 1) * PAGE_SIZE
+    )
+    .fetch_all(connection_pool)
+    .await
+    .context("Failed to fetch a page of subscribers")?;
+    Ok(rows)
+}
+
+#[tracing::instrument(skip(connection_pool))]
+async fn get_subscriber(
+    connection_pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Option<SubscriberRow>, anyhow::Error> {
+    let row = sqlx::query_as!(
+        SubscriberRow,
+        r#"
+        SELECT id, email, name, status, subscribed_at
+        FROM subscriptions
+        WHERE id = $1
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(connection_pool)
+    .await
+    .context("Failed to fetch subscriber")?;
+    Ok(row)
+}
+
+#[tracing::instrument(skip(connection_pool))]
+async fn count_tokens(
+    connection_pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<(i64, i64), anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT
+            COUNT(*) as "issued!",
+            COUNT(*) FILTER (WHERE consumed_at IS NULL AND expires_at > now()) as "usable!"
+        FROM subscription_tokens
+        WHERE subscriber_id = $1
+        "#,
+        subscriber_id
+    )
+    .fetch_one(connection_pool)
+    .await
+    .context("Failed to count subscription tokens")?;
+    Ok((row.issued, row.usable))
+}
+
+#[tracing::instrument(skip(connection_pool))]
+async fn get_tags_and_attributes(
+    connection_pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<(Vec<String>, serde_json::Value), anyhow::Error> {
+    let row = sqlx::query!(
+        "SELECT tags, attributes FROM subscriptions WHERE id = $1",
+        subscriber_id
+    )
+    .fetch_one(connection_pool)
+    .await
+    .context("Failed to fetch tags and attributes")?;
+    Ok((row.tags, row.attributes))
+}
+
+#[tracing::instrument(skip(connection_pool))]
+async fn get_list_subscriptions(
+    connection_pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Vec<(String, String)>, anyhow::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT l.name, s.status
+        FROM list_subscriptions s
+        JOIN lists l ON l.id = s.list_id
+        WHERE s.subscriber_id = $1
+        ORDER BY l.name
+        "#,
+        subscriber_id
+    )
+    .fetch_all(connection_pool)
+    .await
+    .context("Failed to fetch list subscriptions")?;
+    Ok(rows.into_iter().map(|r| (r.name, r.status)).collect())
+}
+
+// Events about a single list mention its name
+#[tracing::instrument(skip(connection_pool))]
+async fn get_subscription_events(
+    connection_pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Vec<(String, DateTime<Utc>)>, anyhow::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT e.event, l.name AS "list_name?", e.occurred_at
+        FROM subscription_events e
+        LEFT JOIN lists l ON l.id = e.list_id
+        WHERE e.subscriber_id = $1
+        ORDER BY e.occurred_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(connection_pool)
+    .await
+    .context("Failed to fetch subscription events")?;
+    Ok(rows
+        .into_iter()
+        .map(|r| match r.list_name {
+            Some(list_name) => (format!("{} ({list_name})", r.event), r.occurred_at),
+            None => (r.event, r.occurred_at),
+        })
+        .collect())
+}
+
+#[tracing::instrument(skip(connection_pool))]
+async fn get_delivered_issues(
+    connection_pool: &PgPool,
+    email: &str,
+) -> Result<Vec<(String, DateTime<Utc>)>, anyhow::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT n.title, d.delivered_at AS "delivered_at!"
+        FROM deliveries d
+        JOIN newsletter_issues n ON n.newsletter_issue_id = d.newsletter_issue_id
+        WHERE d.subscriber_email = $1 AND d.outcome = 'delivered'
+        ORDER BY d.delivered_at DESC
+        "#,
+        email
+    )
+    .fetch_all(connection_pool)
+    .await
+    .context("Failed to fetch delivered issues")?;
+    Ok(rows
+        .into_iter()
+        .map(|r| (r.title, r.delivered_at))
+        .collect())
+}
@@ -0,0 +1,414 @@
+use actix_multipart::form::bytes::Bytes;
+use actix_multipart::form::text::Text;
+use actix_multipart::form::MultipartForm;
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use anyhow::Context;
+use askama::Template;
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, Postgres, Transaction};
+use std::collections::{HashMap, HashSet};
+use uuid::Uuid;
+
+use crate::configuration::SubscriptionSettings;
+use crate::domain::{
+    NewSubscriber, SubscriberAttributes, SubscriberEmail, SubscriberName, SubscriberTags,
+};
+use crate::email_templates::{confirmation_template, EmailTemplates};
+use crate::jobs::enqueue_all;
+use crate::lists::{get_list_by_slug, get_lists, MailingList, DEFAULT_LIST};
+use crate::routes::{confirmation_email, generate_subscribe_token};
+use crate::startup::ApplicationBaseUrl;
+use crate::templates::html_response;
+use crate::utils::{err500, see_other};
+
+#[derive(serde::Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum InitialStatus {
+    Confirmed,
+    PendingConfirmation,
+}
+
+impl InitialStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            InitialStatus::Confirmed => "confirmed",
+            InitialStatus::PendingConfirmation => "pending_confirmation",
+        }
+    }
+}
+
+#[derive(MultipartForm, utoipa::ToSchema)]
+pub struct ImportForm {
+    /// CSV file with a header row containing `email` and `name` columns. An optional `tags`
+    /// column holds tags separated by semicolons. The `status` and `subscribed_at` columns of
+    /// an export are ignored, any other column is stored as an attribute of the same name
+    #[multipart(limit = "10MiB")]
+    #[schema(value_type = String, format = Binary)]
+    file: Bytes,
+    /// `confirmed` or `pending_confirmation`
+    #[schema(value_type = String, example = "pending_confirmation")]
+    initial_status: Text<InitialStatus>,
+    /// Slug of the list to add the subscribers to, `newsletter` if not given
+    #[schema(value_type = Option<String>, example = "newsletter")]
+    list: Option<Text<String>>,
+}
+
+#[derive(Template)]
+#[template(path = "admin/subscribers/import.html")]
+struct ImportFormTemplate<'a> {
+    messages: Vec<&'a str>,
+    lists: Vec<MailingList>,
+}
+
+#[derive(Template)]
+#[template(path = "admin/subscribers/import_report.html")]
+struct ImportReportTemplate {
+    report: ImportReport,
+    initial_status: &'static str,
+    list: MailingList,
+}
+
+#[derive(Default)]
+struct ImportReport {
+    imported: usize,
+    duplicates: usize,
+    // (line number, error message)
+    errors: Vec<(u64, String)>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/subscribers/import",
+    tag = "admin",
+    security(("session_cookie" = [])),
+    responses(
+        (status = 200, description = "CSV import form", body = String, content_type = "text/html"),
+        (status = 303, description = "Not logged in, redirects to /login")
+    )
+)]
+pub async fn import_subscribers_form(
+    connection_pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let lists = get_lists(connection_pool.get_ref()).await.map_err(err500)?;
+    let messages: Vec<&str> = flash_messages.iter().map(|m| m.content()).collect();
+    Ok(html_response(
+        StatusCode::OK,
+        &ImportFormTemplate { messages, lists },
+    ))
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/subscribers/import",
+    tag = "admin",
+    security(("session_cookie" = [])),
+    request_body(content = inline(ImportForm), content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "Import report with per-row errors", body = String, content_type = "text/html"),
+        (status = 303, description = "The file is not a usable CSV, redirects to the import form")
+    )
+)]
+#[tracing::instrument(
+    name = "Import subscribers from CSV",
+    skip_all,
+    fields(initial_status = form.initial_status.as_str())
+)]
+pub async fn import_subscribers(
+    form: MultipartForm<ImportForm>,
+    connection_pool: web::Data<PgPool>,
+    email_templates: web::Data<EmailTemplates>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    settings: web::Data<SubscriptionSettings>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let ImportForm {
+        file,
+        initial_status,
+        list,
+    } = form.into_inner();
+    let initial_status = initial_status.into_inner();
+    let list_slug = list.map(Text::into_inner);
+    let list_slug = list_slug.as_deref().unwrap_or(DEFAULT_LIST);
+    let list = match get_list_by_slug(connection_pool.get_ref(), list_slug)
+        .await
+        .map_err(err500)?
+    {
+        Some(list) => list,
+        None => {
+            FlashMessage::error(format!("There is no list {list_slug}")).send();
+            return Ok(see_other("/admin/subscribers/import"));
+        }
+    };
+    let (subscribers, mut report) = match parse_csv(&file.data) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            FlashMessage::error(e.to_string()).send();
+            return Ok(see_other("/admin/subscribers/import"));
+        }
+    };
+
+    let mut transaction = connection_pool
+        .begin()
+        .await
+        .context("Failed to get a connection from the pool")
+        .map_err(err500)?;
+    let n_subscribers = subscribers.len();
+    let added = insert_subscribers(&mut transaction, subscribers, list.id, initial_status)
+        .await
+        .map_err(err500)?;
+    report.duplicates += n_subscribers - added.len();
+    report.imported = added.len();
+    if initial_status == InitialStatus::PendingConfirmation && !added.is_empty() {
+        let expires_at = Utc::now() + settings.confirmation_token_ttl();
+        let tokens = save_tokens(&mut transaction, added, list.id, expires_at)
+            .await
+            .map_err(err500)?;
+        let template = confirmation_template(&connection_pool, &email_templates)
+            .await
+            .context("Failed to get the confirmation email template")
+            .map_err(err500)?;
+        let emails = tokens
+            .into_iter()
+            .map(|(subscriber_id, subscriber, token)| {
+                confirmation_email(
+                    &template,
+                    subscriber_id,
+                    &subscriber,
+                    &list.name,
+                    &base_url.0,
+                    token,
+                )
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .context("Failed to render the confirmation emails")
+            .map_err(err500)?;
+        enqueue_all(&mut transaction, &emails)
+            .await
+            .context("Failed to queue the confirmation emails")
+            .map_err(err500)?;
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the imported subscribers")
+        .map_err(err500)?;
+    Ok(html_response(
+        StatusCode::OK,
+        &ImportReportTemplate {
+            report,
+            initial_status: initial_status.as_str(),
+            list,
+        },
+    ))
+}
+
+struct ImportedSubscriber {
+    subscriber: NewSubscriber,
+    tags: SubscriberTags,
+    attributes: SubscriberAttributes,
+}
+
+fn parse_csv(data: &[u8]) -> Result<(Vec<ImportedSubscriber>, ImportReport), anyhow::Error> {
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(data);
+    let headers = reader
+        .headers()
+        .context("Could not read the CSV header row")?;
+    let column = |name: &str| headers.iter().position(|h| h.eq_ignore_ascii_case(name));
+    let (email_column, name_column) = match (column("email"), column("name")) {
+        (Some(e), Some(n)) => (e, n),
+        _ => anyhow::bail!("The CSV file must have a header row with `email` and `name` columns"),
+    };
+    let tags_column = column("tags");
+    // Written by the export, but not imported: subscribers get the status picked in the form
+    let ignored_columns = [column("status"), column("subscribed_at")];
+    let attribute_columns: Vec<(usize, String)> = headers
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| {
+            ![Some(email_column), Some(name_column), tags_column]
+                .iter()
+                .chain(&ignored_columns)
+                .any(|c| *c == Some(*i))
+        })
+        .map(|(i, h)| (i, h.to_string()))
+        .collect();
+
+    let mut report = ImportReport::default();
+    let mut subscribers = Vec::new();
+    let mut seen = HashSet::new();
+    for record in reader.records() {
+        let record = match record {
+            Ok(r) => r,
+            Err(e) => {
+                let line = e.position().map(|p| p.line()).unwrap_or_default();
+                report.errors.push((line, e.to_string()));
+                continue;
+            }
+        };
+        let line = record.position().map(|p| p.line()).unwrap_or_default();
+        let email = record.get(email_column).unwrap_or_default().to_string();
+        let name = record.get(name_column).unwrap_or_default().to_string();
+        let subscriber = match (SubscriberEmail::parse(email), SubscriberName::parse(name)) {
+            (Ok(email), Ok(name)) => NewSubscriber { email, name },
+            (Err(e), _) | (_, Err(e)) => {
+                report.errors.push((line, e));
+                continue;
+            }
+        };
+        let tags = tags_column.and_then(|i| record.get(i)).unwrap_or_default();
+        let tags = match SubscriberTags::parse(tags) {
+            Ok(tags) => tags,
+            Err(e) => {
+                report.errors.push((line, e));
+                continue;
+            }
+        };
+        let mut attributes = SubscriberAttributes::default();
+        for (i, name) in &attribute_columns {
+            // Empty cells are missing values, not empty strings
+            match record.get(*i) {
+                Some(value) if !value.is_empty() => attributes.insert(name.clone(), value.into()),
+                _ => {}
+            }
+        }
+        if seen.insert(subscriber.email.as_ref().to_owned()) {
+            subscribers.push(ImportedSubscriber {
+                subscriber,
+                tags,
+                attributes,
+            });
+        } else {
+            report.duplicates += 1;
+        }
+    }
+    Ok((subscribers, report))
+}
+
+// Inserts all subscribers in one statement, keeping the addresses that are already subscribed as
+// they are, then adds all of them to the list unless they are already on it.
+// Returns the subscribers that were actually added to the list along with their ids.
+#[tracing::instrument(skip_all, fields(n_subscribers = subscribers.len()))]
+async fn insert_subscribers(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscribers: Vec<ImportedSubscriber>,
+    list_id: Uuid,
+    initial_status: InitialStatus,
+) -> Result<Vec<(Uuid, NewSubscriber)>, anyhow::Error> {
+    let ids: Vec<Uuid> = subscribers.iter().map(|_| Uuid::new_v4()).collect();
+    // Postgres arrays can't be ragged, so each subscriber's tags travel as a JSON array
+    let mut tags: Vec<serde_json::Value> = Vec::with_capacity(subscribers.len());
+    let mut attributes: Vec<serde_json::Value> = Vec::with_capacity(subscribers.len());
+    let subscribers: Vec<NewSubscriber> = subscribers
+        .into_iter()
+        .map(|s| {
+            tags.push(s.tags.inner().into());
+            attributes.push(s.attributes.into_value());
+            s.subscriber
+        })
+        .collect();
+    let emails: Vec<&str> = subscribers.iter().map(|s| s.email.as_ref()).collect();
+    let names: Vec<&str> = subscribers.iter().map(|s| s.name.as_ref()).collect();
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, name, subscribed_at, status, tags, attributes)
+        SELECT
+            id,
+            email,
+            name,
+            now(),
+            $4,
+            ARRAY(SELECT jsonb_array_elements_text(tags)),
+            attributes
+        FROM UNNEST($1::uuid[], $2::text[], $3::text[], $5::jsonb[], $6::jsonb[])
+            AS t(id, email, name, tags, attributes)
+        ON CONFLICT (email) DO NOTHING
+        "#,
+        &ids[..],
+        &emails as &[&str],
+        &names as &[&str],
+        initial_status.as_str(),
+        &tags[..],
+        &attributes[..]
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to insert imported subscribers")?;
+    let rows = sqlx::query!(
+        r#"
+        WITH added AS (
+            INSERT INTO list_subscriptions (list_id, subscriber_id, status, subscribed_at)
+            SELECT $2, id, $3, now()
+            FROM subscriptions
+            WHERE email = ANY($1)
+            ON CONFLICT (list_id, subscriber_id) DO NOTHING
+            RETURNING subscriber_id
+        )
+        SELECT subscriptions.id, subscriptions.email
+        FROM added
+        JOIN subscriptions ON subscriptions.id = added.subscriber_id
+        "#,
+        &emails as &[&str],
+        list_id,
+        initial_status.as_str()
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to add imported subscribers to the list")?;
+    let added_ids: HashMap<String, Uuid> = rows.into_iter().map(|r| (r.email, r.id)).collect();
+    let added_id_list: Vec<Uuid> = added_ids.values().copied().collect();
+    sqlx::query!(
+        r#"
+        INSERT INTO subscription_events (subscriber_id, list_id, event, occurred_at)
+        SELECT id, $2, 'imported', now()
+        FROM UNNEST($1::uuid[]) AS t(id)
+        "#,
+        &added_id_list[..],
+        list_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to record the import events")?;
+    let added = subscribers
+        .into_iter()
+        .filter_map(|s| Some((*added_ids.get(s.email.as_ref())?, s)))
+        .collect();
+    Ok(added)
+}
+
+#[tracing::instrument(skip_all)]
+async fn save_tokens(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscribers: Vec<(Uuid, NewSubscriber)>,
+    list_id: Uuid,
+    expires_at: DateTime<Utc>,
+) -> Result<Vec<(Uuid, NewSubscriber, String)>, anyhow::Error> {
+    let ids: Vec<Uuid> = subscribers.iter().map(|(id, _)| *id).collect();
+    let tokens: Vec<String> = subscribers
+        .iter()
+        .map(|_| generate_subscribe_token())
+        .collect();
+    sqlx::query!(
+        r#"
+        INSERT INTO subscription_tokens (subscription_token, subscriber_id, list_id, expires_at)
+        SELECT token, id, $3, $4
+        FROM UNNEST($1::text[], $2::uuid[]) AS t(token, id)
+        "#,
+        &tokens[..],
+        &ids[..],
+        list_id,
+        expires_at
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to save tokens for imported subscribers")?;
+    Ok(subscribers
+        .into_iter()
+        .zip(tokens)
+        .map(|((id, subscriber), token)| (id, subscriber, token))
+        .collect())
+}
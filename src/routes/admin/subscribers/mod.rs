@@ -0,0 +1,18 @@
+mod export;
+mod get;
+mod import;
+mod post;
+
+pub use export::{__path_export_subscribers, export_subscribers};
+pub use get::{
+    __path_list_subscribers, __path_subscriber_details, list_subscribers, subscriber_details,
+};
+pub use import::{
+    __path_import_subscribers, __path_import_subscribers_form, import_subscribers,
+    import_subscribers_form,
+};
+pub use post::{
+    __path_confirm_subscriber, __path_delete_subscriber, __path_edit_subscriber,
+    __path_edit_subscriber_attributes, __path_unsubscribe_subscriber, confirm_subscriber,
+    delete_subscriber, edit_subscriber, edit_subscriber_attributes, unsubscribe_subscriber,
+};
@@ -0,0 +1,315 @@
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::authentication::UserId;
+use crate::domain::{SubscriberAttributes, SubscriberEmail, SubscriberName, SubscriberTags};
+use crate::routes::{erase_subscriber, record_subscription_event};
+use crate::utils::{err404, err500, see_other};
+
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+pub struct FormData {
+    email: String,
+    name: String,
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/subscribers/{subscriber_id}",
+    tag = "admin",
+    security(("session_cookie" = [])),
+    params(("subscriber_id" = Uuid, Path, description = "Id of the subscriber")),
+    request_body(
+        content = inline(FormData),
+        content_type = "application/x-www-form-urlencoded"
+    ),
+    responses(
+        (status = 303, description = "Outcome is flashed, redirects to the subscriber details"),
+        (status = 404, description = "No subscriber with this id")
+    )
+)]
+#[tracing::instrument(name = "Edit a subscriber", skip(form, connection_pool))]
+pub async fn edit_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    form: web::Form<FormData>,
+    connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber_id = subscriber_id.into_inner();
+    let details_page = format!("/admin/subscribers/{subscriber_id}");
+    let FormData { email, name } = form.0;
+    let (email, name) = match (SubscriberEmail::parse(email), SubscriberName::parse(name)) {
+        (Ok(email), Ok(name)) => (email, name),
+        (Err(e), _) | (_, Err(e)) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other(&details_page));
+        }
+    };
+    let mut transaction = connection_pool
+        .begin()
+        .await
+        .context("Failed to get a connection from the pool")
+        .map_err(err500)?;
+    let result = sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET email = $2, name = $3
+        WHERE id = $1
+        "#,
+        subscriber_id,
+        email.as_ref(),
+        name.as_ref()
+    )
+    .execute(&mut transaction)
+    .await;
+    match result {
+        Ok(r) if r.rows_affected() == 0 => return Err(err404("Subscriber not found")),
+        Ok(_) => {}
+        Err(sqlx::Error::Database(e)) if e.constraint() == Some("subscriptions_email_key") => {
+            FlashMessage::error(format!("{email} is already subscribed")).send();
+            return Ok(see_other(&details_page));
+        }
+        Err(e) => return Err(err500(e)),
+    }
+    record_subscription_event(&mut transaction, subscriber_id, None, "edited_by_admin")
+        .await
+        .map_err(err500)?;
+    transaction.commit().await.map_err(err500)?;
+    FlashMessage::info("Subscriber details updated").send();
+    Ok(see_other(&details_page))
+}
+
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+pub struct AttributesFormData {
+    /// Comma separated
+    tags: String,
+    /// JSON object, replaces the current attributes
+    attributes: String,
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/subscribers/{subscriber_id}/attributes",
+    tag = "admin",
+    security(("session_cookie" = [])),
+    params(("subscriber_id" = Uuid, Path, description = "Id of the subscriber")),
+    request_body(
+        content = inline(AttributesFormData),
+        content_type = "application/x-www-form-urlencoded"
+    ),
+    responses(
+        (status = 303, description = "Outcome is flashed, redirects to the subscriber details"),
+        (status = 404, description = "No subscriber with this id")
+    )
+)]
+#[tracing::instrument(
+    name = "Edit the tags and attributes of a subscriber",
+    skip(form, connection_pool)
+)]
+pub async fn edit_subscriber_attributes(
+    subscriber_id: web::Path<Uuid>,
+    form: web::Form<AttributesFormData>,
+    connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber_id = subscriber_id.into_inner();
+    let details_page = format!("/admin/subscribers/{subscriber_id}");
+    let parsed = SubscriberTags::parse(&form.tags)
+        .and_then(|tags| Ok((tags, SubscriberAttributes::parse_str(&form.attributes)?)));
+    let (tags, attributes) = match parsed {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other(&details_page));
+        }
+    };
+    let mut transaction = connection_pool
+        .begin()
+        .await
+        .context("Failed to get a connection from the pool")
+        .map_err(err500)?;
+    let n_updated = sqlx::query!(
+        "UPDATE subscriptions SET tags = $2, attributes = $3 WHERE id = $1",
+        subscriber_id,
+        tags.as_ref(),
+        attributes.into_value()
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to update tags and attributes")
+    .map_err(err500)?
+    .rows_affected();
+    if n_updated == 0 {
+        return Err(err404("Subscriber not found"));
+    }
+    record_subscription_event(&mut transaction, subscriber_id, None, "edited_by_admin")
+        .await
+        .map_err(err500)?;
+    transaction.commit().await.map_err(err500)?;
+    FlashMessage::info("Tags and attributes updated").send();
+    Ok(see_other(&details_page))
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/subscribers/{subscriber_id}/confirm",
+    tag = "admin",
+    security(("session_cookie" = [])),
+    params(("subscriber_id" = Uuid, Path, description = "Id of the subscriber")),
+    responses(
+        (status = 303, description = "Outcome is flashed, redirects to the subscriber details"),
+        (status = 404, description = "No subscriber with this id")
+    )
+)]
+#[tracing::instrument(name = "Manually confirm a subscriber", skip(connection_pool))]
+pub async fn confirm_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber_id = subscriber_id.into_inner();
+    if set_status(
+        &connection_pool,
+        subscriber_id,
+        "confirmed",
+        "confirmed_by_admin",
+    )
+    .await?
+    {
+        FlashMessage::info("Subscriber confirmed").send();
+    }
+    Ok(see_other(&format!("/admin/subscribers/{subscriber_id}")))
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/subscribers/{subscriber_id}/unsubscribe",
+    tag = "admin",
+    security(("session_cookie" = [])),
+    params(("subscriber_id" = Uuid, Path, description = "Id of the subscriber")),
+    responses(
+        (status = 303, description = "Subscriber unsubscribed, redirects to the subscriber details"),
+        (status = 404, description = "No subscriber with this id")
+    )
+)]
+#[tracing::instrument(name = "Manually unsubscribe a subscriber", skip(connection_pool))]
+pub async fn unsubscribe_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    connection_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber_id = subscriber_id.into_inner();
+    if set_status(
+        &connection_pool,
+        subscriber_id,
+        "unsubscribed",
+        "unsubscribed_by_admin",
+    )
+    .await?
+    {
+        FlashMessage::info("Subscriber unsubscribed").send();
+    }
+    Ok(see_other(&format!("/admin/subscribers/{subscriber_id}")))
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/subscribers/{subscriber_id}/delete",
+    tag = "admin",
+    security(("session_cookie" = [])),
+    params(("subscriber_id" = Uuid, Path, description = "Id of the subscriber")),
+    responses(
+        (status = 303, description = "Subscriber deleted, redirects to the subscriber list"),
+        (status = 404, description = "No subscriber with this id")
+    )
+)]
+#[tracing::instrument(
+    name = "Permanently delete a subscriber",
+    skip(connection_pool, user_id),
+    fields(user_id=%*user_id)
+)]
+pub async fn delete_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    connection_pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut transaction = connection_pool
+        .begin()
+        .await
+        .context("Failed to get a connection from the pool")
+        .map_err(err500)?;
+    let email = erase_subscriber(
+        &mut transaction,
+        subscriber_id.into_inner(),
+        &format!("admin:{}", *user_id.into_inner()),
+    )
+    .await
+    .map_err(err500)?
+    .ok_or_else(|| err404("Subscriber not found"))?;
+    transaction.commit().await.map_err(err500)?;
+    FlashMessage::info(format!("{email} has been deleted")).send();
+    Ok(see_other("/admin/subscribers"))
+}
+
+// Returns whether the status was changed, the reason it was not is flashed
+async fn set_status(
+    connection_pool: &PgPool,
+    subscriber_id: Uuid,
+    status: &str,
+    event: &str,
+) -> Result<bool, actix_web::Error> {
+    let mut transaction = connection_pool
+        .begin()
+        .await
+        .context("Failed to get a connection from the pool")
+        .map_err(err500)?;
+    let current_status = sqlx::query!(
+        "SELECT status FROM subscriptions WHERE id = $1 FOR UPDATE",
+        subscriber_id
+    )
+    .fetch_optional(&mut transaction)
+    .await
+    .context("Failed to look up the subscriber status")
+    .map_err(err500)?
+    .ok_or_else(|| err404("Subscriber not found"))?
+    .status;
+    // Emails to the address bounced or were reported as spam, it must not receive issues again
+    if status == "confirmed" && (current_status == "bounced" || current_status == "complained") {
+        FlashMessage::error(format!(
+            "The subscriber is {current_status}, they cannot be confirmed"
+        ))
+        .send();
+        return Ok(false);
+    }
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET status = $2
+        WHERE id = $1
+        "#,
+        subscriber_id,
+        status
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to update subscriber status")
+    .map_err(err500)?;
+    if status == "confirmed" {
+        // Confirming the address also confirms the lists it is waiting to join
+        sqlx::query!(
+            r#"
+            UPDATE list_subscriptions
+            SET status = 'confirmed'
+            WHERE subscriber_id = $1 AND status = 'pending_confirmation'
+            "#,
+            subscriber_id
+        )
+        .execute(&mut transaction)
+        .await
+        .context("Failed to confirm the list subscriptions")
+        .map_err(err500)?;
+    }
+    record_subscription_event(&mut transaction, subscriber_id, None, event)
+        .await
+        .map_err(err500)?;
+    transaction.commit().await.map_err(err500)?;
+    Ok(true)
+}
@@ -0,0 +1,62 @@
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use askama::Template;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::templates::html_response;
+use crate::utils::err500;
+
+struct SuppressionRow {
+    id: Uuid,
+    entry: String,
+    reason: String,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(Template)]
+#[template(path = "admin/suppressions.html")]
+struct SuppressionsTemplate<'a> {
+    messages: Vec<&'a str>,
+    suppressions: Vec<SuppressionRow>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/suppressions",
+    tag = "admin",
+    security(("session_cookie" = [])),
+    responses(
+        (status = 200, description = "The suppressed addresses and domains, and a form to add one", body = String, content_type = "text/html"),
+        (status = 303, description = "Not logged in, redirects to /login")
+    )
+)]
+#[tracing::instrument(name = "List suppressions", skip_all)]
+pub async fn list_suppressions(
+    connection_pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let suppressions = sqlx::query_as!(
+        SuppressionRow,
+        r#"
+        SELECT id, entry, reason, created_at
+        FROM suppressions
+        ORDER BY created_at DESC
+        "#
+    )
+    .fetch_all(connection_pool.as_ref())
+    .await
+    .context("Failed to fetch the suppressions")
+    .map_err(err500)?;
+    let messages: Vec<&str> = flash_messages.iter().map(|m| m.content()).collect();
+    Ok(html_response(
+        StatusCode::OK,
+        &SuppressionsTemplate {
+            messages,
+            suppressions,
+        },
+    ))
+}
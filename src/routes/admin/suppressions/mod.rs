@@ -0,0 +1,7 @@
+mod get;
+mod post;
+
+pub use get::{__path_list_suppressions, list_suppressions};
+pub use post::{
+    __path_add_suppression, __path_remove_suppression, add_suppression, remove_suppression,
+};
@@ -0,0 +1,127 @@
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::audit::record_audit_event;
+use crate::authentication::UserId;
+use crate::domain::SuppressionEntry;
+use crate::suppression::suppress;
+use crate::utils::{err404, err500, see_other};
+
+#[derive(Debug, serde::Deserialize, utoipa::ToSchema)]
+pub struct SuppressionForm {
+    /// An email address, or a domain to suppress every address at
+    entry: String,
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/suppressions",
+    tag = "admin",
+    security(("session_cookie" = [])),
+    request_body(
+        content = inline(SuppressionForm),
+        content_type = "application/x-www-form-urlencoded"
+    ),
+    responses((status = 303, description = "Outcome is flashed, redirects to /admin/suppressions"))
+)]
+#[tracing::instrument(name = "Add a suppression", skip(connection_pool, user_id))]
+pub async fn add_suppression(
+    form: web::Form<SuppressionForm>,
+    connection_pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let entry = match SuppressionEntry::parse(&form.entry) {
+        Ok(entry) => entry,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other("/admin/suppressions"));
+        }
+    };
+    let mut transaction = connection_pool
+        .begin()
+        .await
+        .context("Failed to get a connection from the pool")
+        .map_err(err500)?;
+    let added = suppress(&mut transaction, &entry, "manual")
+        .await
+        .context("Failed to add the suppression")
+        .map_err(err500)?;
+    if !added {
+        FlashMessage::error(format!("{} is already suppressed", entry.as_ref())).send();
+        return Ok(see_other("/admin/suppressions"));
+    }
+    record_audit_event(
+        &mut transaction,
+        &format!("admin:{}", *user_id.into_inner()),
+        "suppression_added",
+        serde_json::json!({ "entry": entry.as_ref() }),
+    )
+    .await
+    .context("Failed to record the audit event")
+    .map_err(err500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the suppression")
+        .map_err(err500)?;
+    let message = if entry.is_domain() {
+        format!("Nothing will be sent to addresses at {}", entry.as_ref())
+    } else {
+        format!("Nothing will be sent to {}", entry.as_ref())
+    };
+    FlashMessage::info(message).send();
+    Ok(see_other("/admin/suppressions"))
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/suppressions/{suppression_id}/delete",
+    tag = "admin",
+    security(("session_cookie" = [])),
+    params(("suppression_id" = Uuid, Path, description = "Id of the suppression")),
+    responses(
+        (status = 303, description = "Suppression removed, redirects to /admin/suppressions"),
+        (status = 404, description = "No suppression with this id")
+    )
+)]
+#[tracing::instrument(name = "Remove a suppression", skip(connection_pool, user_id))]
+pub async fn remove_suppression(
+    suppression_id: web::Path<Uuid>,
+    connection_pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut transaction = connection_pool
+        .begin()
+        .await
+        .context("Failed to get a connection from the pool")
+        .map_err(err500)?;
+    let removed = sqlx::query!(
+        "DELETE FROM suppressions WHERE id = $1 RETURNING entry, reason",
+        suppression_id.into_inner()
+    )
+    .fetch_optional(&mut transaction)
+    .await
+    .context("Failed to remove the suppression")
+    .map_err(err500)?
+    .ok_or_else(|| err404("Suppression not found"))?;
+    // Removing a complaint or a bounce is worth knowing about later on
+    record_audit_event(
+        &mut transaction,
+        &format!("admin:{}", *user_id.into_inner()),
+        "suppression_removed",
+        serde_json::json!({ "entry": removed.entry, "reason": removed.reason }),
+    )
+    .await
+    .context("Failed to record the audit event")
+    .map_err(err500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the removal")
+        .map_err(err500)?;
+    FlashMessage::info(format!("{} is no longer suppressed", removed.entry)).send();
+    Ok(see_other("/admin/suppressions"))
+}
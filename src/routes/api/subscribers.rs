@@ -0,0 +1,167 @@
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{PgExecutor, PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use super::ApiError;
+use crate::authentication::ApiToken;
+use crate::domain::{SubscriberAttributes, SubscriberTags};
+use crate::idempotency::RequestTransaction;
+use crate::routes::record_subscription_event;
+
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct Subscriber {
+    id: Uuid,
+    email: String,
+    name: String,
+    status: String,
+    #[schema(value_type = String, format = DateTime)]
+    subscribed_at: DateTime<Utc>,
+    tags: Vec<String>,
+    #[schema(value_type = Object)]
+    attributes: serde_json::Value,
+}
+
+#[derive(Debug, serde::Deserialize, utoipa::ToSchema)]
+pub struct SubscriberPatch {
+    /// Replaces all the tags of the subscriber
+    tags: Option<Vec<String>>,
+    /// Merged into the attributes of the subscriber, a `null` value removes the attribute
+    #[schema(value_type = Option<Object>)]
+    attributes: Option<serde_json::Value>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/subscribers/{subscriber_id}",
+    tag = "api",
+    security(("api_token" = [])),
+    params(("subscriber_id" = Uuid, Path, description = "Id of the subscriber")),
+    responses(
+        (status = 200, description = "The subscriber", body = Subscriber),
+        (status = 401, description = "Missing, unknown or revoked API token"),
+        (status = 404, description = "No subscriber with this id")
+    )
+)]
+#[tracing::instrument(name = "Get a subscriber through the API", skip(connection_pool))]
+pub async fn api_get_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    connection_pool: web::Data<PgPool>,
+    api_token: web::ReqData<ApiToken>,
+) -> Result<HttpResponse, ApiError> {
+    let subscriber = get_subscriber(connection_pool.get_ref(), subscriber_id.into_inner())
+        .await?
+        .ok_or_else(|| ApiError::NotFound("Subscriber not found".into()))?;
+    Ok(HttpResponse::Ok().json(subscriber))
+}
+
+#[utoipa::path(
+    patch,
+    path = "/api/subscribers/{subscriber_id}",
+    tag = "api",
+    security(("api_token" = [])),
+    params(
+        ("subscriber_id" = Uuid, Path, description = "Id of the subscriber"),
+        ("Idempotency-Key" = Option<String>, Header, description = "Sending the same update again with this key replays the first response")
+    ),
+    request_body = SubscriberPatch,
+    responses(
+        (status = 200, description = "The updated subscriber", body = Subscriber),
+        (status = 400, description = "Invalid tags, attributes or idempotency key"),
+        (status = 401, description = "Missing, unknown or revoked API token"),
+        (status = 404, description = "No subscriber with this id"),
+        (status = 409, description = "A request with the same idempotency key is still being processed. Retry after the `Retry-After` delay"),
+        (status = 422, description = "The idempotency key was already used for a different request")
+    )
+)]
+#[tracing::instrument(name = "Update tags and attributes through the API", skip(transaction))]
+pub async fn api_update_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    patch: web::Json<SubscriberPatch>,
+    mut transaction: RequestTransaction,
+    api_token: web::ReqData<ApiToken>,
+) -> Result<HttpResponse, ApiError> {
+    let subscriber_id = subscriber_id.into_inner();
+    let SubscriberPatch { tags, attributes } = patch.into_inner();
+    let tags = tags
+        .map(SubscriberTags::from_list)
+        .transpose()
+        .map_err(ApiError::ValidationError)?;
+    let attributes = attributes
+        .map(SubscriberAttributes::parse)
+        .transpose()
+        .map_err(ApiError::ValidationError)?;
+
+    let current = lock_subscriber(&mut transaction, subscriber_id)
+        .await?
+        .ok_or_else(|| ApiError::NotFound("Subscriber not found".into()))?;
+    let tags = tags.map(SubscriberTags::inner).unwrap_or(current.tags);
+    let attributes = match attributes {
+        Some(patch) => SubscriberAttributes::parse(current.attributes)
+            .and_then(|current| current.merge(patch))
+            .map_err(ApiError::ValidationError)?
+            .into_value(),
+        None => current.attributes,
+    };
+    sqlx::query!(
+        "UPDATE subscriptions SET tags = $2, attributes = $3 WHERE id = $1",
+        subscriber_id,
+        &tags,
+        attributes
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to update tags and attributes")?;
+    record_subscription_event(&mut *transaction, subscriber_id, None, "edited_through_api")
+        .await
+        .context("Failed to record the subscription event")?;
+    let subscriber = get_subscriber(&mut *transaction, subscriber_id)
+        .await?
+        .context("The subscriber disappeared during the update")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the update")?;
+    Ok(HttpResponse::Ok().json(subscriber))
+}
+
+async fn get_subscriber(
+    executor: impl PgExecutor<'_>,
+    subscriber_id: Uuid,
+) -> Result<Option<Subscriber>, anyhow::Error> {
+    let subscriber = sqlx::query_as!(
+        Subscriber,
+        r#"
+        SELECT id, email, name, status, subscribed_at, tags, attributes
+        FROM subscriptions
+        WHERE id = $1
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(executor)
+    .await
+    .context("Failed to fetch the subscriber")?;
+    Ok(subscriber)
+}
+
+// Locks the row so concurrent updates don't lose each other's attributes
+async fn lock_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<Option<Subscriber>, anyhow::Error> {
+    let subscriber = sqlx::query_as!(
+        Subscriber,
+        r#"
+        SELECT id, email, name, status, subscribed_at, tags, attributes
+        FROM subscriptions
+        WHERE id = $1
+        FOR UPDATE
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(transaction)
+    .await
+    .context("Failed to fetch the subscriber")?;
+    Ok(subscriber)
+}
@@ -1,5 +1,44 @@
-use actix_web::HttpResponse;
+use actix_web::{web, HttpResponse};
 
+use crate::circuit_breaker::CircuitState;
+use crate::email_client::EmailClient;
+
+#[utoipa::path(
+    get,
+    path = "/health_check",
+    tag = "health",
+    responses((status = 200, description = "The application is up and running"))
+)]
 pub async fn health_check() -> HttpResponse {
     HttpResponse::Ok().finish()
 }
+
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct EmailProviderHealth {
+    // `closed` when Postmark is called as usual, `open` while calls fail fast, `half_open`
+    // while a single call checks whether Postmark recovered
+    circuit: &'static str,
+    // Seconds until Postmark is called again, only set while the circuit is open
+    retry_in_seconds: Option<u64>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/health_check/email_provider",
+    tag = "health",
+    responses(
+        (status = 200, description = "Emails are sent", body = EmailProviderHealth),
+        (status = 503, description = "Postmark kept failing, emails are not sent for now", body = EmailProviderHealth)
+    )
+)]
+pub async fn email_provider_health(email_client: web::Data<EmailClient>) -> HttpResponse {
+    let state = email_client.circuit_state();
+    let body = EmailProviderHealth {
+        circuit: state.as_str(),
+        retry_in_seconds: email_client.circuit_open_for().map(|d| d.as_secs()),
+    };
+    match state {
+        CircuitState::Open => HttpResponse::ServiceUnavailable().json(body),
+        CircuitState::Closed | CircuitState::HalfOpen => HttpResponse::Ok().json(body),
+    }
+}
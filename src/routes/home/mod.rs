@@ -1,8 +1,28 @@
-use actix_web::http::header::ContentType;
-use actix_web::HttpResponse;
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse};
+use sqlx::PgPool;
 
-pub async fn home() -> HttpResponse {
-    HttpResponse::Ok()
-        .content_type(ContentType::html())
-        .body(include_str!("home.html"))
+use super::SubscribeFormTemplate;
+use crate::lists::{get_lists, DEFAULT_LIST};
+use crate::templates::html_response;
+use crate::utils::err500;
+
+#[utoipa::path(
+    get,
+    path = "/",
+    tag = "pages",
+    responses((status = 200, description = "Home page with the subscription form", body = String, content_type = "text/html"))
+)]
+pub async fn home(connection_pool: web::Data<PgPool>) -> Result<HttpResponse, actix_web::Error> {
+    let lists = get_lists(connection_pool.get_ref()).await.map_err(err500)?;
+    Ok(html_response(
+        StatusCode::OK,
+        &SubscribeFormTemplate {
+            messages: Vec::new(),
+            name: "",
+            email: "",
+            lists,
+            list: DEFAULT_LIST,
+        },
+    ))
 }
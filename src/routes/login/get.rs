@@ -1,42 +1,23 @@
-use actix_web::http::header::ContentType;
+use actix_web::http::StatusCode;
 use actix_web::HttpResponse;
 use actix_web_flash_messages::IncomingFlashMessages;
-use std::fmt::Write;
+use askama::Template;
 
+use crate::templates::html_response;
+
+#[derive(Template)]
+#[template(path = "login.html")]
+struct LoginTemplate<'a> {
+    messages: Vec<&'a str>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/login",
+    tag = "pages",
+    responses((status = 200, description = "Login form", body = String, content_type = "text/html"))
+)]
 pub async fn login_form(flash_messages: IncomingFlashMessages) -> HttpResponse {
-    let mut error_html = String::new();
-    for msg in flash_messages.iter() {
-        writeln!(error_html, "<p><i>{}</i></p>", msg.content()).unwrap();
-    }
-    HttpResponse::Ok()
-        .content_type(ContentType::html())
-        .body(format!(
-            r#"<!DOCTYPE html>
-<html lang="en">
-<head>
-    <meta http-equiv="content-type" content="text/html; charset=utf-8">
-    <title>Login</title>
-</head>
-<body>
-    {error_html}
-    <form action="/login" method="post">
-        <label>Username
-            <input
-                type="text"
-                placeholder="Enter Username"
-                name="username"
-            >
-        </label>
-        <label>Password
-            <input
-                type="password"
-                placeholder="Enter Password"
-                name="password"
-            >
-        </label>
-        <button type="submit">Login</button>
-    </form>
-</body>
-</html>"#,
-        ))
+    let messages: Vec<&str> = flash_messages.iter().map(|m| m.content()).collect();
+    html_response(StatusCode::OK, &LoginTemplate { messages })
 }
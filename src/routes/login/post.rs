@@ -11,9 +11,10 @@ use sqlx::PgPool;
 
 use crate::authentication::{validate_credentials, Credentials};
 
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, utoipa::ToSchema)]
 pub struct FormData {
     username: String,
+    #[schema(value_type = String, format = Password)]
     password: Secret<String>,
 }
 
@@ -31,6 +32,18 @@ impl std::fmt::Debug for LoginError {
     }
 }
 
+#[utoipa::path(
+    post,
+    path = "/login",
+    tag = "pages",
+    request_body(
+        content = inline(FormData),
+        content_type = "application/x-www-form-urlencoded"
+    ),
+    responses(
+        (status = 303, description = "Redirects to /admin/dashboard on success, /login otherwise")
+    )
+)]
 #[tracing::instrument(
     skip(form, connection_pool, session)
     fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
@@ -44,10 +57,10 @@ pub async fn login(
         username: form.0.username,
         password: form.0.password,
     };
-    tracing::Span::current().record("username", &tracing::field::display(&credentials.username));
+    tracing::Span::current().record("username", tracing::field::display(&credentials.username));
     match validate_credentials(credentials, &connection_pool).await {
         Ok(user_id) => {
-            tracing::Span::current().record("user_id", &tracing::field::display(&user_id));
+            tracing::Span::current().record("user_id", tracing::field::display(&user_id));
             session.renew();
             session
                 .insert_user(user_id)
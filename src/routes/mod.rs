@@ -1,13 +1,25 @@
 mod admin;
+mod api;
 mod health_check;
 mod home;
 mod login;
+mod openapi;
+mod subscriber_data;
 mod subscriptions;
 mod subscriptions_confirm;
+mod subscriptions_unsubscribe;
+mod tracking;
+mod webhooks;
 
 pub use admin::*;
+pub use api::*;
 pub use health_check::*;
 pub use home::*;
 pub use login::*;
+pub use openapi::*;
+pub use subscriber_data::*;
 pub use subscriptions::*;
 pub use subscriptions_confirm::*;
+pub use subscriptions_unsubscribe::*;
+pub use tracking::*;
+pub use webhooks::*;
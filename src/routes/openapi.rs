@@ -0,0 +1,133 @@
+use actix_web::HttpResponse;
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::openapi::Components;
+use utoipa::{Modify, OpenApi};
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "zero2prod", description = "Newsletter delivery service"),
+    paths(
+        super::health_check,
+        super::email_provider_health,
+        super::home,
+        super::login_form,
+        super::login,
+        super::subscribe,
+        super::confirm,
+        super::manage_subscriptions,
+        super::unsubscribe,
+        super::request_subscriber_data,
+        super::download_subscriber_data,
+        super::erase_subscriber_data_form,
+        super::erase_subscriber_data,
+        super::track_open,
+        super::track_click,
+        super::postmark_webhook,
+        super::admin_dashboard,
+        super::log_out,
+        super::publish_newsletter_form,
+        super::publish_newsletter,
+        super::count_newsletter_recipients,
+        super::list_issues,
+        super::issue_details,
+        super::change_password_form,
+        super::change_password,
+        super::confirmation_email_form,
+        super::save_confirmation_email,
+        super::preview_confirmation_email,
+        super::send_test_confirmation_email,
+        super::reset_confirmation_email,
+        super::list_api_tokens,
+        super::create_api_token,
+        super::revoke_api_token,
+        super::list_lists,
+        super::create_list,
+        super::list_suppressions,
+        super::add_suppression,
+        super::remove_suppression,
+        super::list_subscribers,
+        super::import_subscribers_form,
+        super::import_subscribers,
+        super::export_subscribers,
+        super::subscriber_details,
+        super::edit_subscriber,
+        super::edit_subscriber_attributes,
+        super::confirm_subscriber,
+        super::unsubscribe_subscriber,
+        super::delete_subscriber,
+        super::api_get_subscriber,
+        super::api_update_subscriber,
+        openapi_json,
+    ),
+    components(schemas(
+        super::Subscriber,
+        super::SubscriberPatch,
+        super::EmailProviderHealth
+    )),
+    modifiers(&SessionCookie, &BearerToken, &WebhookBasicAuth),
+    tags(
+        (name = "health", description = "Service health"),
+        (name = "pages", description = "Public HTML pages and login"),
+        (name = "subscriptions", description = "Public subscription flow"),
+        (name = "tracking", description = "Open and click tracking in sent issues"),
+        (name = "webhooks", description = "Notifications from the email provider"),
+        (name = "admin", description = "Endpoints that require a logged in admin session"),
+        (name = "api", description = "JSON endpoints that require an API token"),
+        (name = "docs", description = "API documentation")
+    )
+)]
+pub struct ApiDoc;
+
+// Admin endpoints are authenticated by the session cookie set on successful login
+struct SessionCookie;
+
+impl Modify for SessionCookie {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Components::new);
+        components.add_security_scheme(
+            "session_cookie",
+            SecurityScheme::ApiKey(ApiKey::Cookie(ApiKeyValue::new("id"))),
+        )
+    }
+}
+
+// API endpoints are authenticated by a token created on /admin/api_tokens
+struct BearerToken;
+
+impl Modify for BearerToken {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Components::new);
+        components.add_security_scheme(
+            "api_token",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+        )
+    }
+}
+
+// Webhooks are authenticated by the credentials set in `webhooks` in the configuration
+struct WebhookBasicAuth;
+
+impl Modify for WebhookBasicAuth {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Components::new);
+        components.add_security_scheme(
+            "webhook_basic_auth",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Basic).build()),
+        )
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/openapi.json",
+    tag = "docs",
+    responses((status = 200, description = "This OpenAPI document", content_type = "application/json"))
+)]
+pub async fn openapi_json() -> HttpResponse {
+    HttpResponse::Ok().json(ApiDoc::openapi())
+}
+
+#[cfg(feature = "swagger-ui")]
+pub fn swagger_ui() -> utoipa_swagger_ui::SwaggerUi {
+    utoipa_swagger_ui::SwaggerUi::new("/swagger-ui/{_:.*}").url("/openapi.json", ApiDoc::openapi())
+}
@@ -0,0 +1,460 @@
+use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType};
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use askama::Template;
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use super::SubscribeError;
+use crate::audit::record_audit_event;
+use crate::domain::SubscriberEmail;
+use crate::email_templates::EmailTemplates;
+use crate::jobs::{enqueue_all, SendDataRequestEmail};
+use crate::signed_token;
+use crate::startup::{ApplicationBaseUrl, HmacSecret};
+use crate::templates::html_response;
+use crate::utils::{err401, err404, err500};
+
+const DATA_REQUEST_PURPOSE: &str = "subscriber_data";
+const DATA_REQUEST_LINK_TTL_HOURS: i64 = 24;
+
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+pub struct DataRequestForm {
+    email: String,
+}
+
+#[derive(serde::Deserialize, utoipa::IntoParams, utoipa::ToSchema)]
+#[into_params(parameter_in = Query)]
+pub struct TokenParameters {
+    /// Signed token from the link emailed to the subscriber
+    token: String,
+}
+
+#[derive(Template)]
+#[template(path = "subscriptions/erase.html")]
+struct EraseTemplate<'a> {
+    token: &'a str,
+}
+
+#[derive(serde::Serialize)]
+struct SubscriberData {
+    subscription: SubscriptionRecord,
+    lists: Vec<ListSubscriptionRecord>,
+    confirmation_tokens: Vec<TokenRecord>,
+    history: Vec<HistoryRecord>,
+    delivered_issues: Vec<DeliveryRecord>,
+    pending_issues: Vec<Uuid>,
+    tracking_events: Vec<TrackingEventRecord>,
+}
+
+#[derive(serde::Serialize)]
+struct SubscriptionRecord {
+    id: Uuid,
+    email: String,
+    name: String,
+    status: String,
+    subscribed_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize)]
+struct ListSubscriptionRecord {
+    list: String,
+    status: String,
+    subscribed_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize)]
+struct TokenRecord {
+    subscription_token: String,
+    list: String,
+    created_at: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+    consumed_at: Option<DateTime<Utc>>,
+}
+
+#[derive(serde::Serialize)]
+struct HistoryRecord {
+    event: String,
+    list: Option<String>,
+    occurred_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize)]
+struct DeliveryRecord {
+    newsletter_issue_id: Uuid,
+    title: String,
+    delivered_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize)]
+struct TrackingEventRecord {
+    newsletter_issue_id: Uuid,
+    event: String,
+    // The link that was clicked
+    url: Option<String>,
+    occurred_at: DateTime<Utc>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/subscriptions/data_request",
+    tag = "subscriptions",
+    request_body(
+        content = inline(DataRequestForm),
+        content_type = "application/x-www-form-urlencoded"
+    ),
+    responses(
+        (status = 200, description = "A link to download or erase the subscriber's data is emailed if the address is subscribed"),
+        (status = 400, description = "The email is not valid", body = String),
+        (status = 500, description = "The request could not be processed")
+    )
+)]
+#[tracing::instrument(
+    name = "Request a copy of subscriber data",
+    skip_all,
+    fields(subscriber_email = %form.email)
+)]
+pub async fn request_subscriber_data(
+    form: web::Form<DataRequestForm>,
+    connection_pool: web::Data<PgPool>,
+    email_templates: web::Data<EmailTemplates>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, SubscribeError> {
+    let email = SubscriberEmail::parse(form.0.email)?;
+    let subscriber = sqlx::query!(
+        "SELECT id FROM subscriptions WHERE email = $1",
+        email.as_ref()
+    )
+    .fetch_optional(connection_pool.as_ref())
+    .await
+    .context("Failed to look up the subscriber")?;
+    // Respond the same way whether or not the address is subscribed, so the endpoint can't
+    // be used to find out who is on the list. The email is sent by the worker, which checks
+    // the suppression list.
+    if let Some(subscriber) = subscriber {
+        let expires_at = Utc::now() + chrono::Duration::hours(DATA_REQUEST_LINK_TTL_HOURS);
+        let token = signed_token::sign(
+            &hmac_secret.0,
+            DATA_REQUEST_PURPOSE,
+            email.as_ref(),
+            expires_at,
+        );
+        let job = data_request_email(&email_templates, subscriber.id, &email, &base_url.0, &token)
+            .context("Failed to render the data request email")?;
+        enqueue_all(connection_pool.as_ref(), &[job])
+            .await
+            .context("Failed to queue the data request email")?;
+    }
+    Ok(HttpResponse::Ok().body(
+        "If this address is subscribed, we have emailed you a link to download or erase your data.",
+    ))
+}
+
+fn data_request_email(
+    email_templates: &EmailTemplates,
+    subscriber_id: Uuid,
+    email: &SubscriberEmail,
+    base_url: &str,
+    token: &str,
+) -> Result<SendDataRequestEmail, anyhow::Error> {
+    let download_link = format!("{base_url}/subscriptions/data?token={token}");
+    let erase_link = format!("{base_url}/subscriptions/erase?token={token}");
+    let content =
+        email_templates.data_request(&download_link, &erase_link, DATA_REQUEST_LINK_TTL_HOURS)?;
+    Ok(SendDataRequestEmail {
+        subscriber_id,
+        recipient: email.as_ref().to_owned(),
+        subject: content.subject,
+        html_body: content.html,
+        text_body: content.text,
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/subscriptions/data",
+    tag = "subscriptions",
+    params(TokenParameters),
+    responses(
+        (status = 200, description = "JSON document with all the data held about the subscriber", content_type = "application/json"),
+        (status = 401, description = "The token is invalid or has expired"),
+        (status = 404, description = "The subscriber no longer exists")
+    )
+)]
+#[tracing::instrument(name = "Download subscriber data", skip_all)]
+pub async fn download_subscriber_data(
+    parameters: web::Query<TokenParameters>,
+    connection_pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let email = signed_token::verify(&hmac_secret.0, DATA_REQUEST_PURPOSE, &parameters.token)
+        .map_err(err401)?;
+    let data = get_subscriber_data(&connection_pool, &email)
+        .await
+        .map_err(err500)?
+        .ok_or_else(|| err404("Subscriber not found"))?;
+    record_audit_event(
+        connection_pool.as_ref(),
+        &format!("subscriber:{}", data.subscription.id),
+        "subscriber_data_exported",
+        serde_json::json!({ "subscriber_id": data.subscription.id }),
+    )
+    .await
+    .map_err(err500)?;
+    Ok(HttpResponse::Ok()
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename("subscriber-data.json".into())],
+        })
+        .json(data))
+}
+
+#[utoipa::path(
+    get,
+    path = "/subscriptions/erase",
+    tag = "subscriptions",
+    params(TokenParameters),
+    responses(
+        (status = 200, description = "Page asking the subscriber to confirm the erasure", body = String, content_type = "text/html"),
+        (status = 401, description = "The token is invalid or has expired")
+    )
+)]
+pub async fn erase_subscriber_data_form(
+    parameters: web::Query<TokenParameters>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, actix_web::Error> {
+    // Only render the form for tokens that would be accepted
+    signed_token::verify(&hmac_secret.0, DATA_REQUEST_PURPOSE, &parameters.token)
+        .map_err(err401)?;
+    Ok(html_response(
+        StatusCode::OK,
+        &EraseTemplate {
+            token: &parameters.token,
+        },
+    ))
+}
+
+#[utoipa::path(
+    post,
+    path = "/subscriptions/erase",
+    tag = "subscriptions",
+    request_body(
+        content = inline(TokenParameters),
+        content_type = "application/x-www-form-urlencoded"
+    ),
+    responses(
+        (status = 200, description = "All the data held about the subscriber has been erased"),
+        (status = 401, description = "The token is invalid or has expired")
+    )
+)]
+#[tracing::instrument(name = "Erase subscriber data", skip_all)]
+pub async fn erase_subscriber_data(
+    form: web::Form<TokenParameters>,
+    connection_pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let email =
+        signed_token::verify(&hmac_secret.0, DATA_REQUEST_PURPOSE, &form.token).map_err(err401)?;
+    let mut transaction = connection_pool
+        .begin()
+        .await
+        .context("Failed to get a connection from the pool")
+        .map_err(err500)?;
+    let subscriber_id = sqlx::query!("SELECT id FROM subscriptions WHERE email = $1", email)
+        .fetch_optional(&mut transaction)
+        .await
+        .context("Failed to look up the subscriber")
+        .map_err(err500)?
+        .map(|r| r.id);
+    // Erasing twice is not an error, the data is gone either way
+    if let Some(subscriber_id) = subscriber_id {
+        erase_subscriber(
+            &mut transaction,
+            subscriber_id,
+            &format!("subscriber:{subscriber_id}"),
+        )
+        .await
+        .map_err(err500)?;
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the erasure")
+        .map_err(err500)?;
+    Ok(HttpResponse::Ok().body("All the data we held about you has been erased."))
+}
+
+// Remove every trace of a subscriber: the subscription itself (cascading to tokens, history and
+// the delivery log), their jobs, queued or failed, which hold their address and emails
+// rendered for them, and the responses saved for idempotent requests about them, e.g. to
+// `/api/subscribers/{subscriber_id}`. Only the subscriber id is kept, in the audit trail.
+// Suppressions are kept too: they are what stops emails to an address that complained.
+// Returns the erased email address, if the subscriber existed.
+#[tracing::instrument(skip(transaction))]
+pub async fn erase_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    actor: &str,
+) -> Result<Option<String>, anyhow::Error> {
+    let email = match sqlx::query!(
+        "DELETE FROM subscriptions WHERE id = $1 RETURNING email",
+        subscriber_id
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to delete the subscriber")?
+    {
+        Some(r) => r.email,
+        None => return Ok(None),
+    };
+    sqlx::query!(
+        r#"
+        DELETE FROM jobs
+        WHERE (job_type = 'deliver_issue' AND payload ->> 'subscriber_email' = $1)
+            OR payload ->> 'subscriber_id' = $2
+        "#,
+        email,
+        subscriber_id.to_string()
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete the subscriber's jobs")?;
+    sqlx::query!(
+        "DELETE FROM idempotency WHERE request_path LIKE '%' || $1 || '%'",
+        subscriber_id.to_string()
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete the responses saved for the subscriber")?;
+    record_audit_event(
+        &mut *transaction,
+        actor,
+        "subscriber_erased",
+        serde_json::json!({ "subscriber_id": subscriber_id }),
+    )
+    .await
+    .context("Failed to record the erasure in the audit trail")?;
+    Ok(Some(email))
+}
+
+#[tracing::instrument(skip(connection_pool))]
+async fn get_subscriber_data(
+    connection_pool: &PgPool,
+    email: &str,
+) -> Result<Option<SubscriberData>, anyhow::Error> {
+    let subscription = match sqlx::query_as!(
+        SubscriptionRecord,
+        r#"
+        SELECT id, email, name, status, subscribed_at
+        FROM subscriptions
+        WHERE email = $1
+        "#,
+        email
+    )
+    .fetch_optional(connection_pool)
+    .await
+    .context("Failed to fetch the subscription")?
+    {
+        Some(s) => s,
+        None => return Ok(None),
+    };
+    let lists = sqlx::query_as!(
+        ListSubscriptionRecord,
+        r#"
+        SELECT l.slug AS list, s.status, s.subscribed_at
+        FROM list_subscriptions s
+        JOIN lists l ON l.id = s.list_id
+        WHERE s.subscriber_id = $1
+        ORDER BY s.subscribed_at
+        "#,
+        subscription.id
+    )
+    .fetch_all(connection_pool)
+    .await
+    .context("Failed to fetch the list subscriptions")?;
+    let confirmation_tokens = sqlx::query_as!(
+        TokenRecord,
+        r#"
+        SELECT t.subscription_token, l.slug AS list, t.created_at, t.expires_at, t.consumed_at
+        FROM subscription_tokens t
+        JOIN lists l ON l.id = t.list_id
+        WHERE t.subscriber_id = $1
+        ORDER BY t.created_at
+        "#,
+        subscription.id
+    )
+    .fetch_all(connection_pool)
+    .await
+    .context("Failed to fetch the subscription tokens")?;
+    let history = sqlx::query_as!(
+        HistoryRecord,
+        r#"
+        SELECT e.event, l.slug AS "list?", e.occurred_at
+        FROM subscription_events e
+        LEFT JOIN lists l ON l.id = e.list_id
+        WHERE e.subscriber_id = $1
+        ORDER BY e.occurred_at
+        "#,
+        subscription.id
+    )
+    .fetch_all(connection_pool)
+    .await
+    .context("Failed to fetch the subscription history")?;
+    let delivered_issues = sqlx::query_as!(
+        DeliveryRecord,
+        r#"
+        SELECT d.newsletter_issue_id, n.title, d.delivered_at AS "delivered_at!"
+        FROM deliveries d
+        JOIN newsletter_issues n ON n.newsletter_issue_id = d.newsletter_issue_id
+        WHERE d.subscriber_email = $1 AND d.outcome = 'delivered'
+        ORDER BY d.delivered_at
+        "#,
+        email
+    )
+    .fetch_all(connection_pool)
+    .await
+    .context("Failed to fetch the delivery log")?;
+    let pending_issues = sqlx::query!(
+        r#"
+        SELECT (payload ->> 'newsletter_issue_id')::uuid AS "newsletter_issue_id!"
+        FROM jobs
+        WHERE job_type = 'deliver_issue'
+            AND status = 'queued'
+            AND payload ->> 'subscriber_email' = $1
+        "#,
+        email
+    )
+    .fetch_all(connection_pool)
+    .await
+    .context("Failed to fetch queued deliveries")?
+    .into_iter()
+    .map(|r| r.newsletter_issue_id)
+    .collect();
+    let tracking_events = sqlx::query_as!(
+        TrackingEventRecord,
+        r#"
+        SELECT e.newsletter_issue_id, e.event, l.href AS "url?", e.occurred_at
+        FROM email_events e
+        LEFT JOIN issue_links l
+            ON l.newsletter_issue_id = e.newsletter_issue_id AND l.link_id = e.link_id
+        WHERE e.subscriber_id = $1
+        ORDER BY e.occurred_at
+        "#,
+        subscription.id
+    )
+    .fetch_all(connection_pool)
+    .await
+    .context("Failed to fetch the tracking events")?;
+    Ok(Some(SubscriberData {
+        subscription,
+        lists,
+        confirmation_tokens,
+        history,
+        delivered_issues,
+        pending_issues,
+        tracking_events,
+    }))
+}
@@ -1,21 +1,33 @@
+use actix_web::error::InternalError;
 use actix_web::http::StatusCode;
-use actix_web::{web, HttpResponse, ResponseError};
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
 use anyhow::Context;
-use chrono::Utc;
+use askama::Template;
+use chrono::{DateTime, Utc};
 use rand::{distributions::Alphanumeric, thread_rng, Rng};
-use sqlx::{PgPool, Postgres, Transaction};
+use sqlx::{PgExecutor, PgPool, Postgres, Transaction};
 use uuid::Uuid;
 
 use crate::{
+    configuration::SubscriptionSettings,
     domain::{NewSubscriber, SubscriberEmail, SubscriberName},
-    email_client::EmailClient,
+    email_templates::{
+        confirmation_template, render_confirmation, EmailTemplate, EmailTemplates, PlaceholderError,
+    },
+    jobs::{enqueue_all, SendConfirmationEmail},
+    lists::{get_list_by_slug, get_lists, set_list_status, MailingList, DEFAULT_LIST},
     startup::ApplicationBaseUrl,
+    templates::{html_response, MessagePage},
+    utils::prefers_html,
 };
 
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, utoipa::ToSchema)]
 pub struct FormData {
     email: String,
     name: String,
+    /// Slug of the list to subscribe to, `newsletter` if not given
+    #[serde(default)]
+    list: Option<String>,
 }
 
 // the impl of TryFrom is a built in way to peform the following manual failable conversion
@@ -33,71 +45,318 @@ impl TryFrom<FormData> for NewSubscriber {
     }
 }
 
+#[utoipa::path(
+    post,
+    path = "/subscriptions",
+    tag = "subscriptions",
+    request_body(
+        content = inline(FormData),
+        content_type = "application/x-www-form-urlencoded"
+    ),
+    responses(
+        (status = 200, description = "Subscriber saved and a confirmation email queued, it is sent in the background. Pending subscribers get a fresh confirmation email, at most once per resend interval. HTML page for browsers, JSON otherwise"),
+        (status = 400, description = "The name or email failed validation or the list does not exist. Browsers get the form back with the error, other clients `{\"error\": \"...\"}`"),
+        (status = 500, description = "The subscriber could not be saved")
+    )
+)]
 #[tracing::instrument(
     name = "Adding a new subscriber",
-    skip(form, connection_pool, base_url),
+    skip(request, form, connection_pool, email_templates, base_url, settings),
     fields(
         subscriber_email = %form.email,
         subscriber_name = %form.name
     )
 )]
 pub async fn subscribe(
+    request: HttpRequest,
     form: web::Form<FormData>,
     connection_pool: web::Data<PgPool>,
-    email_client: web::Data<EmailClient>,
+    email_templates: web::Data<EmailTemplates>,
     base_url: web::Data<ApplicationBaseUrl>,
-) -> Result<HttpResponse, SubscribeError> {
-    // alternate way to parse would be form.0.try_into(), since any type that
+    settings: web::Data<SubscriptionSettings>,
+) -> Result<HttpResponse, InternalError<SubscribeError>> {
+    let html = prefers_html(&request);
+    // Keep what was submitted to fill the form back in if it is rejected
+    let (name, email) = (form.name.clone(), form.email.clone());
+    let list = form
+        .list
+        .clone()
+        .unwrap_or_else(|| DEFAULT_LIST.to_string());
+    let outcome = try_subscribe(
+        form.0,
+        &connection_pool,
+        &email_templates,
+        &base_url.0,
+        &settings,
+    )
+    .await;
+    match outcome {
+        Ok(()) if html => Ok(html_response(
+            StatusCode::OK,
+            &MessagePage {
+                title: "Check your inbox",
+                paragraphs: &[
+                    "Thanks for subscribing! Check your inbox for a link to confirm your subscription.",
+                ],
+            },
+        )),
+        Ok(()) => Ok(HttpResponse::Ok().json(serde_json::json!({
+            "message": "Check your inbox for a link to confirm your subscription"
+        }))),
+        Err(e) => {
+            let status = e.status_code();
+            let response = match &e {
+                SubscribeError::ValidationError(message) if html => {
+                    match get_lists(connection_pool.get_ref()).await {
+                        Ok(lists) => html_response(
+                            status,
+                            &SubscribeFormTemplate {
+                                messages: vec![message],
+                                name: &name,
+                                email: &email,
+                                lists,
+                                list: &list,
+                            },
+                        ),
+                        Err(e) => {
+                            tracing::error!(error.cause_chain = ?e, "Failed to get the lists");
+                            HttpResponse::InternalServerError().finish()
+                        }
+                    }
+                }
+                SubscribeError::ValidationError(message) => {
+                    HttpResponse::build(status).json(serde_json::json!({ "error": message }))
+                }
+                SubscribeError::UnexpectedError(_) if html => html_response(
+                    status,
+                    &MessagePage {
+                        title: "Something went wrong",
+                        paragraphs: &["Something went wrong on our side. Please try again later."],
+                    },
+                ),
+                SubscribeError::UnexpectedError(_) => HttpResponse::build(status)
+                    .json(serde_json::json!({ "error": "Internal server error" })),
+            };
+            Err(InternalError::from_response(e, response))
+        }
+    }
+}
+
+// The subscription form, filled in with what was submitted and the reason it was rejected, if any
+#[derive(Template)]
+#[template(path = "subscriptions/form.html")]
+pub struct SubscribeFormTemplate<'a> {
+    pub messages: Vec<&'a str>,
+    pub name: &'a str,
+    pub email: &'a str,
+    // The list picker is only shown when there is more than one
+    pub lists: Vec<MailingList>,
+    // Slug of the selected list
+    pub list: &'a str,
+}
+
+async fn try_subscribe(
+    form: FormData,
+    connection_pool: &PgPool,
+    email_templates: &EmailTemplates,
+    base_url: &str,
+    settings: &SubscriptionSettings,
+) -> Result<(), SubscribeError> {
+    let list_slug = form.list.clone();
+    let list_slug = list_slug.as_deref().unwrap_or(DEFAULT_LIST);
+    // alternate way to parse would be form.try_into(), since any type that
     // implements TryFrom gets an impl TryInto for free
-    let new_subscriber =
-        NewSubscriber::try_from(form.0).map_err(SubscribeError::ValidationError)?;
+    let new_subscriber = NewSubscriber::try_from(form).map_err(SubscribeError::ValidationError)?;
     // Cnstruct new DB Transaction instance to pass into db method instead of the pool itself
     let mut transaction = connection_pool
         .begin()
         .await
         .context("Failed to get a connection from the pool")?;
-    // Save subscriber to db with pending_confirm status
-    let subscriber_id = save_subscriber(&new_subscriber, &mut transaction)
+    let list = get_list_by_slug(&mut transaction, list_slug)
+        .await
+        .context("Failed to look up the list")?
+        .ok_or_else(|| SubscribeError::ValidationError(format!("There is no list {list_slug}")))?;
+    let subscriber_id = match save_subscriber(&new_subscriber, &mut transaction)
         .await
-        .context("Failed to save new subscriber to the subscribers table")?;
+        .context("Failed to save new subscriber to the subscribers table")?
+    {
+        SavedSubscriber::New(subscriber_id) => {
+            set_list_status(
+                &mut transaction,
+                list.id,
+                subscriber_id,
+                "pending_confirmation",
+            )
+            .await
+            .context("Failed to add the subscriber to the list")?;
+            record_subscription_event(&mut transaction, subscriber_id, Some(list.id), "subscribed")
+                .await
+                .context("Failed to record the subscription event")?;
+            subscriber_id
+        }
+        SavedSubscriber::Existing(subscriber_id, status) => {
+            // Emails to the address bounced or were reported as spam, subscribing again must
+            // not make it look deliverable. Same response, so the status is not given away.
+            if status == "bounced" || status == "complained" {
+                tracing::info!("The address is {status}, not subscribing it again");
+                return Ok(());
+            }
+            let list_status = get_list_status(subscriber_id, list.id, &mut transaction)
+                .await
+                .context("Failed to look up the list subscription")?;
+            // Nothing to do, but don't tell the caller who is already on the list
+            if status == "confirmed" && list_status.as_deref() == Some("confirmed") {
+                return Ok(());
+            }
+            let last_sent = get_last_token_created_at(subscriber_id, list.id, &mut transaction)
+                .await
+                .context("Failed to look up the last confirmation token")?;
+            if last_sent.is_some_and(|t| Utc::now() - t < settings.resend_confirmation_interval()) {
+                tracing::info!("A confirmation email was sent recently, not sending another");
+                return Ok(());
+            }
+            // A confirmed address stays confirmed, it only needs to confirm the new list
+            if status != "confirmed" {
+                mark_pending_confirmation(subscriber_id, &mut transaction)
+                    .await
+                    .context("Failed to mark the subscriber as pending confirmation")?;
+            }
+            set_list_status(
+                &mut transaction,
+                list.id,
+                subscriber_id,
+                "pending_confirmation",
+            )
+            .await
+            .context("Failed to add the subscriber to the list")?;
+            let event = match list_status {
+                None => "subscribed",
+                Some(_) => "confirmation_resent",
+            };
+            record_subscription_event(&mut transaction, subscriber_id, Some(list.id), event)
+                .await
+                .context("Failed to record the subscription event")?;
+            subscriber_id
+        }
+    };
     // Generate and save token to send back in confirm email
     let subscribe_token = generate_subscribe_token();
-    save_token(subscriber_id, &subscribe_token, &mut transaction)
+    let expires_at = Utc::now() + settings.confirmation_token_ttl();
+    save_token(
+        subscriber_id,
+        list.id,
+        &subscribe_token,
+        expires_at,
+        &mut transaction,
+    )
+    .await
+    .context("Failed to save token for new subscriber")?;
+    let template = confirmation_template(connection_pool, email_templates)
+        .await
+        .context("Failed to get the confirmation email template")?;
+    let email = confirmation_email(
+        &template,
+        subscriber_id,
+        &new_subscriber,
+        &list.name,
+        base_url,
+        subscribe_token,
+    )
+    .context("Failed to render the confirmation email")?;
+    enqueue_all(&mut transaction, &[email])
         .await
-        .context("Failed to save token for new subscriber")?;
+        .context("Failed to queue the confirmation email")?;
     transaction
         .commit()
         .await
         .context("Failed to save SQL transaction to save new subscriber details")?;
-    send_confirmation_email(&email_client, new_subscriber, &base_url.0, &subscribe_token)
-        .await
-        .context("Failed to send a confirmation email")?;
-    Ok(HttpResponse::Ok().finish())
+    Ok(())
 }
 
-#[tracing::instrument(
-    name = "Send confirmation email to new subscriber",
-    skip(email_client, subscriber)
-)]
-pub async fn send_confirmation_email(
-    email_client: &EmailClient,
-    subscriber: NewSubscriber,
+#[tracing::instrument(name = "Looking up a list subscription", skip(transaction))]
+async fn get_list_status(
+    subscriber_id: Uuid,
+    list_id: Uuid,
+    transaction: &mut Transaction<'_, Postgres>,
+) -> Result<Option<String>, sqlx::Error> {
+    let row = sqlx::query!(
+        "SELECT status FROM list_subscriptions WHERE subscriber_id = $1 AND list_id = $2",
+        subscriber_id,
+        list_id
+    )
+    .fetch_optional(transaction)
+    .await?;
+    Ok(row.map(|r| r.status))
+}
+
+#[tracing::instrument(name = "Looking up the last confirmation token", skip(transaction))]
+async fn get_last_token_created_at(
+    subscriber_id: Uuid,
+    list_id: Uuid,
+    transaction: &mut Transaction<'_, Postgres>,
+) -> Result<Option<DateTime<Utc>>, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT MAX(created_at) as last_sent
+        FROM subscription_tokens
+        WHERE subscriber_id = $1 AND list_id = $2
+        "#,
+        subscriber_id,
+        list_id
+    )
+    .fetch_one(transaction)
+    .await?;
+    Ok(row.last_sent)
+}
+
+#[tracing::instrument(skip(transaction))]
+async fn mark_pending_confirmation(
+    subscriber_id: Uuid,
+    transaction: &mut Transaction<'_, Postgres>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "UPDATE subscriptions SET status = 'pending_confirmation' WHERE id = $1",
+        subscriber_id
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+// The confirmation email carrying the subscriber's token, ready to be queued in the same
+// transaction as the token
+pub fn confirmation_email(
+    template: &EmailTemplate,
+    subscriber_id: Uuid,
+    subscriber: &NewSubscriber,
+    list_name: &str,
     base_url: &str,
-    subscribe_token: &str,
-) -> Result<(), reqwest::Error> {
+    subscribe_token: String,
+) -> Result<SendConfirmationEmail, PlaceholderError> {
     let confirmation_link =
         format!("{base_url}/subscriptions/confirm?subscription_token={subscribe_token}");
-    let subject = "Welcome!";
-    let html_content = &format!(
-        "Welcome to my newsletter! <br>\
-        Click <a href=\"{confirmation_link}\">here</a> to confirm your subscription."
-    );
-    let text_content = &format!(
-        "Welcome to my newsletter!\nVisit {confirmation_link} to confirm your subscription."
-    );
-    email_client
-        .send_email(&subscriber.email, subject, html_content, text_content)
-        .await
+    let content = render_confirmation(
+        template,
+        subscriber.name.as_ref(),
+        list_name,
+        &confirmation_link,
+    )?;
+    Ok(SendConfirmationEmail {
+        subscriber_id,
+        recipient: subscriber.email.to_string(),
+        subscription_token: subscribe_token,
+        subject: content.subject,
+        html_body: content.html,
+        text_body: content.text,
+    })
+}
+
+// Either way the subscriber row ends up locked, so concurrent requests for the same email wait
+// for each other instead of both inserting it or both sending an email
+pub enum SavedSubscriber {
+    New(Uuid),
+    // With its current status, left unchanged
+    Existing(Uuid, String),
 }
 
 #[tracing::instrument(
@@ -107,21 +366,27 @@ pub async fn send_confirmation_email(
 pub async fn save_subscriber(
     new_subscriber: &NewSubscriber,
     transaction: &mut Transaction<'_, Postgres>,
-) -> Result<Uuid, sqlx::Error> {
-    let subscriber_id = Uuid::new_v4();
-    sqlx::query!(
+) -> Result<SavedSubscriber, sqlx::Error> {
+    // The no-op update locks the existing row, and lets RETURNING see it
+    let row = sqlx::query!(
         r#"
         INSERT INTO subscriptions (id, email, name, subscribed_at, status)
         VALUES ($1, $2, $3, $4, 'pending_confirmation')
+        ON CONFLICT (email) DO UPDATE SET email = EXCLUDED.email
+        RETURNING id, status, xmax = 0 AS "inserted!"
         "#,
-        subscriber_id,
+        Uuid::new_v4(),
         new_subscriber.email.as_ref(),
         new_subscriber.name.as_ref(),
         Utc::now()
     )
-    .execute(transaction)
+    .fetch_one(transaction)
     .await?;
-    Ok(subscriber_id)
+    Ok(if row.inserted {
+        SavedSubscriber::New(row.id)
+    } else {
+        SavedSubscriber::Existing(row.id, row.status)
+    })
 }
 
 #[tracing::instrument(
@@ -130,16 +395,20 @@ pub async fn save_subscriber(
 )]
 pub async fn save_token(
     subscriber_id: Uuid,
+    list_id: Uuid,
     subscribe_token: &str,
+    expires_at: DateTime<Utc>,
     transaction: &mut Transaction<'_, Postgres>,
 ) -> Result<(), StoreTokenError> {
     sqlx::query!(
         r#"
-        INSERT INTO subscription_tokens (subscription_token, subscriber_id)
-        VALUES ($1, $2)
+        INSERT INTO subscription_tokens (subscription_token, subscriber_id, list_id, expires_at)
+        VALUES ($1, $2, $3, $4)
         "#,
         subscribe_token,
-        subscriber_id
+        subscriber_id,
+        list_id,
+        expires_at
     )
     .execute(transaction)
     .await
@@ -147,8 +416,29 @@ pub async fn save_token(
     Ok(())
 }
 
+#[tracing::instrument(name = "Recording subscription event", skip(executor))]
+pub async fn record_subscription_event(
+    executor: impl PgExecutor<'_>,
+    subscriber_id: Uuid,
+    list_id: Option<Uuid>,
+    event: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO subscription_events (subscriber_id, list_id, event, occurred_at)
+        VALUES ($1, $2, $3, now())
+        "#,
+        subscriber_id,
+        list_id,
+        event
+    )
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
 // Generates a random 25-character case-sensitive token
-fn generate_subscribe_token() -> String {
+pub fn generate_subscribe_token() -> String {
     let mut rng = thread_rng();
     std::iter::repeat_with(|| rng.sample(Alphanumeric))
         .map(char::from)
@@ -1,66 +1,229 @@
-use actix_web::{web, HttpResponse};
-use sqlx::PgPool;
+use actix_web::error::InternalError;
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, Postgres, Transaction};
 use uuid::Uuid;
 
-#[derive(serde::Deserialize)]
+use super::{error_chain_fmt, record_subscription_event};
+use crate::lists::set_list_status;
+use crate::templates::{html_response, MessagePage};
+use crate::utils::prefers_html;
+
+#[derive(serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct Parameters {
     subscription_token: String,
 }
 
-#[tracing::instrument(name = "Confirm a pending subscriber", skip(parameters))]
+struct StoredToken {
+    subscriber_id: Uuid,
+    list_id: Uuid,
+    expires_at: DateTime<Utc>,
+    consumed_at: Option<DateTime<Utc>>,
+    // Of the address
+    status: String,
+    // On the list the token is for
+    list_status: Option<String>,
+}
+
+enum ConfirmOutcome {
+    Confirmed,
+    AlreadyConfirmed,
+}
+
+#[derive(thiserror::Error)]
+pub enum ConfirmError {
+    #[error("This confirmation link is not valid")]
+    InvalidToken,
+    #[error("This confirmation link has expired")]
+    ExpiredToken,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for ConfirmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for ConfirmError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ConfirmError::InvalidToken => StatusCode::UNAUTHORIZED,
+            ConfirmError::ExpiredToken => StatusCode::GONE,
+            ConfirmError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/subscriptions/confirm",
+    tag = "subscriptions",
+    params(Parameters),
+    responses(
+        (status = 200, description = "The subscriber has been confirmed, or already was. HTML page for browsers, `{\"status\": \"confirmed\" | \"already_confirmed\"}` otherwise"),
+        (status = 400, description = "The subscription token is missing"),
+        (status = 401, description = "The subscription token is unknown or has already been used"),
+        (status = 410, description = "The subscription token has expired"),
+        (status = 500, description = "The subscriber could not be confirmed")
+    )
+)]
+#[tracing::instrument(name = "Confirm a pending subscriber", skip(request, parameters))]
 pub async fn confirm(
+    request: HttpRequest,
     parameters: web::Query<Parameters>,
     connection_pool: web::Data<PgPool>,
-) -> HttpResponse {
-    // Get token from params
-    let token = &parameters.subscription_token;
-    // Get subscriber id from provided token
-    let subscriber_id = match get_subscriber_id_from_token(token, &connection_pool).await {
-        Ok(id) => id,
-        Err(_) => {
-            return HttpResponse::InternalServerError().finish();
+) -> Result<HttpResponse, InternalError<ConfirmError>> {
+    let html = prefers_html(&request);
+    match try_confirm(&parameters.subscription_token, &connection_pool).await {
+        Ok(outcome) => {
+            let (status, message) = match outcome {
+                ConfirmOutcome::Confirmed => (
+                    "confirmed",
+                    "Your subscription is confirmed. Thanks for subscribing!",
+                ),
+                ConfirmOutcome::AlreadyConfirmed => (
+                    "already_confirmed",
+                    "Your subscription was already confirmed, there is nothing else to do.",
+                ),
+            };
+            if html {
+                Ok(html_response(
+                    StatusCode::OK,
+                    &MessagePage {
+                        title: "Subscription confirmed",
+                        paragraphs: &[message],
+                    },
+                ))
+            } else {
+                Ok(HttpResponse::Ok().json(serde_json::json!({ "status": status })))
+            }
         }
-    };
-    // Validate returned id has a value
-    let id = match subscriber_id {
-        Some(val) => val,
-        None => {
-            return HttpResponse::Unauthorized().finish();
+        Err(e) => {
+            let status = e.status_code();
+            let message = match &e {
+                ConfirmError::InvalidToken => "This confirmation link is not valid.",
+                ConfirmError::ExpiredToken => {
+                    "This confirmation link has expired. Subscribe again with the same email \
+                    address and we will send you a new one."
+                }
+                ConfirmError::UnexpectedError(_) => {
+                    "Something went wrong on our side. Please try again later."
+                }
+            };
+            let response = if html {
+                html_response(
+                    status,
+                    &MessagePage {
+                        title: "Subscription not confirmed",
+                        paragraphs: &[message],
+                    },
+                )
+            } else {
+                HttpResponse::build(status).json(serde_json::json!({ "error": message }))
+            };
+            Err(InternalError::from_response(e, response))
         }
-    };
-    // Update subscriber status with found id
-    if update_subscriber_status(&id, &connection_pool)
+    }
+}
+
+async fn try_confirm(
+    token: &str,
+    connection_pool: &PgPool,
+) -> Result<ConfirmOutcome, ConfirmError> {
+    let mut transaction = connection_pool
+        .begin()
+        .await
+        .context("Failed to get a connection from the pool")?;
+    // Get the stored token, locked so it can only be consumed once
+    let stored_token = get_token(token, &mut transaction)
         .await
-        .is_err()
+        .context("Failed to fetch the subscription token")?
+        .ok_or(ConfirmError::InvalidToken)?;
+    if stored_token.status == "confirmed"
+        && stored_token.list_status.as_deref() == Some("confirmed")
     {
-        return HttpResponse::InternalServerError().finish();
+        // Clicking the link twice, or after an admin confirmed the subscriber, is fine
+        consume_tokens(
+            &stored_token.subscriber_id,
+            &stored_token.list_id,
+            &mut transaction,
+        )
+        .await
+        .context("Failed to consume the subscription tokens")?;
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit the confirmation")?;
+        return Ok(ConfirmOutcome::AlreadyConfirmed);
     }
-    HttpResponse::Ok().finish()
+    if stored_token.consumed_at.is_some() {
+        return Err(ConfirmError::InvalidToken);
+    }
+    if stored_token.expires_at < Utc::now() {
+        return Err(ConfirmError::ExpiredToken);
+    }
+    let id = stored_token.subscriber_id;
+    let list_id = stored_token.list_id;
+    update_subscriber_status(&id, &mut transaction)
+        .await
+        .context("Failed to confirm the subscriber")?;
+    set_list_status(&mut transaction, list_id, id, "confirmed")
+        .await
+        .context("Failed to confirm the list subscription")?;
+    consume_tokens(&id, &list_id, &mut transaction)
+        .await
+        .context("Failed to consume the subscription tokens")?;
+    record_subscription_event(&mut transaction, id, Some(list_id), "confirmed")
+        .await
+        .context("Failed to record the confirmation")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the confirmation")?;
+    Ok(ConfirmOutcome::Confirmed)
 }
 
-#[tracing::instrument(name = "Getting subscriber id from token", skip(token))]
-pub async fn get_subscriber_id_from_token(
+#[tracing::instrument(name = "Getting subscription token", skip(token, transaction))]
+async fn get_token(
     token: &str,
-    connection_pool: &PgPool,
-) -> Result<Option<Uuid>, sqlx::Error> {
-    let result = sqlx::query!(
-        "SELECT subscriber_id FROM subscription_tokens WHERE subscription_token = $1",
+    transaction: &mut Transaction<'_, Postgres>,
+) -> Result<Option<StoredToken>, sqlx::Error> {
+    sqlx::query_as!(
+        StoredToken,
+        r#"
+        SELECT
+            t.subscriber_id,
+            t.list_id,
+            t.expires_at,
+            t.consumed_at,
+            s.status,
+            l.status AS "list_status?"
+        FROM subscription_tokens t
+        JOIN subscriptions s ON s.id = t.subscriber_id
+        LEFT JOIN list_subscriptions l
+            ON l.subscriber_id = t.subscriber_id AND l.list_id = t.list_id
+        WHERE t.subscription_token = $1
+        FOR UPDATE OF t, s
+        "#,
         token
     )
-    .fetch_optional(connection_pool)
+    .fetch_optional(transaction)
     .await
     .map_err(|err| {
         tracing::error!("Could not execute query: {:?}", err);
         err
-    })?;
-    // result is a db row, with the selected fields as properties to access of the result struct
-    Ok(result.map(|res| res.subscriber_id))
+    })
 }
 
-#[tracing::instrument(name = "Updating subscriber status", skip(subscriber_id))]
+#[tracing::instrument(name = "Updating subscriber status", skip(subscriber_id, transaction))]
 pub async fn update_subscriber_status(
     subscriber_id: &Uuid,
-    connection_pool: &PgPool,
+    transaction: &mut Transaction<'_, Postgres>,
 ) -> Result<(), sqlx::Error> {
     sqlx::query!(
         r#"
@@ -70,7 +233,35 @@ pub async fn update_subscriber_status(
         "#,
         subscriber_id,
     )
-    .execute(connection_pool)
+    .execute(transaction)
+    .await
+    .map_err(|err| {
+        tracing::error!("Failed to execute query: {:?}", err);
+        err
+    })?;
+    Ok(())
+}
+
+// Once confirmed, none of the subscriber's outstanding tokens for the list can be used again
+#[tracing::instrument(
+    name = "Consuming subscription tokens",
+    skip(subscriber_id, list_id, transaction)
+)]
+async fn consume_tokens(
+    subscriber_id: &Uuid,
+    list_id: &Uuid,
+    transaction: &mut Transaction<'_, Postgres>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE subscription_tokens
+        SET consumed_at = now()
+        WHERE subscriber_id = $1 AND list_id = $2 AND consumed_at IS NULL
+        "#,
+        subscriber_id,
+        list_id,
+    )
+    .execute(transaction)
     .await
     .map_err(|err| {
         tracing::error!("Failed to execute query: {:?}", err);
@@ -0,0 +1,155 @@
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use askama::Template;
+use secrecy::Secret;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::record_subscription_event;
+use crate::lists::MANAGE_SUBSCRIPTIONS_PURPOSE;
+use crate::signed_token;
+use crate::startup::HmacSecret;
+use crate::templates::{html_response, MessagePage};
+use crate::utils::{err401, err404, err500};
+
+#[derive(serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ManageParameters {
+    /// Signed token from the link at the bottom of every issue
+    token: String,
+}
+
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+pub struct UnsubscribeForm {
+    /// Signed token from the link at the bottom of every issue
+    token: String,
+    /// Slug of the list to leave
+    list: String,
+}
+
+struct ListSubscription {
+    slug: String,
+    name: String,
+    status: String,
+}
+
+#[derive(Template)]
+#[template(path = "subscriptions/unsubscribe.html")]
+struct ManageTemplate<'a> {
+    token: &'a str,
+    lists: Vec<ListSubscription>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/subscriptions/unsubscribe",
+    tag = "subscriptions",
+    params(ManageParameters),
+    responses(
+        (status = 200, description = "The lists the subscriber is on, each with an unsubscribe button", body = String, content_type = "text/html"),
+        (status = 401, description = "The token is invalid or has expired")
+    )
+)]
+#[tracing::instrument(name = "Show a subscriber's lists", skip_all)]
+pub async fn manage_subscriptions(
+    parameters: web::Query<ManageParameters>,
+    connection_pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber_id = verify_token(&hmac_secret.0, &parameters.token)?;
+    let lists = sqlx::query_as!(
+        ListSubscription,
+        r#"
+        SELECT l.slug, l.name, s.status
+        FROM list_subscriptions s
+        JOIN lists l ON l.id = s.list_id
+        WHERE s.subscriber_id = $1
+        ORDER BY l.name
+        "#,
+        subscriber_id
+    )
+    .fetch_all(connection_pool.as_ref())
+    .await
+    .context("Failed to fetch the list subscriptions")
+    .map_err(err500)?;
+    Ok(html_response(
+        StatusCode::OK,
+        &ManageTemplate {
+            token: &parameters.token,
+            lists,
+        },
+    ))
+}
+
+#[utoipa::path(
+    post,
+    path = "/subscriptions/unsubscribe",
+    tag = "subscriptions",
+    request_body(
+        content = inline(UnsubscribeForm),
+        content_type = "application/x-www-form-urlencoded"
+    ),
+    responses(
+        (status = 200, description = "The subscriber left the list. Other lists are not affected", body = String, content_type = "text/html"),
+        (status = 401, description = "The token is invalid or has expired"),
+        (status = 404, description = "The subscriber is not on this list")
+    )
+)]
+#[tracing::instrument(name = "Unsubscribe from a list", skip_all, fields(list = %form.list))]
+pub async fn unsubscribe(
+    form: web::Form<UnsubscribeForm>,
+    connection_pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber_id = verify_token(&hmac_secret.0, &form.token)?;
+    let mut transaction = connection_pool
+        .begin()
+        .await
+        .context("Failed to get a connection from the pool")
+        .map_err(err500)?;
+    let list = sqlx::query!(
+        r#"
+        UPDATE list_subscriptions s
+        SET status = 'unsubscribed'
+        FROM lists l
+        WHERE l.id = s.list_id AND l.slug = $1 AND s.subscriber_id = $2
+        RETURNING l.id, l.name
+        "#,
+        form.list,
+        subscriber_id
+    )
+    .fetch_optional(&mut transaction)
+    .await
+    .context("Failed to unsubscribe from the list")
+    .map_err(err500)?
+    .ok_or_else(|| err404("You are not subscribed to this list"))?;
+    record_subscription_event(
+        &mut transaction,
+        subscriber_id,
+        Some(list.id),
+        "unsubscribed",
+    )
+    .await
+    .context("Failed to record the unsubscription")
+    .map_err(err500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the unsubscription")
+        .map_err(err500)?;
+    let message = format!("You will no longer receive {}.", list.name);
+    Ok(html_response(
+        StatusCode::OK,
+        &MessagePage {
+            title: "Unsubscribed",
+            paragraphs: &[&message],
+        },
+    ))
+}
+
+fn verify_token(hmac_secret: &Secret<String>, token: &str) -> Result<Uuid, actix_web::Error> {
+    let subject =
+        signed_token::verify(hmac_secret, MANAGE_SUBSCRIPTIONS_PURPOSE, token).map_err(err401)?;
+    subject.parse().map_err(err401)
+}
@@ -0,0 +1,126 @@
+use actix_web::http::header::{CacheControl, CacheDirective};
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use sqlx::PgPool;
+
+use crate::startup::HmacSecret;
+use crate::tracking::{TrackedEmail, CLICK_PURPOSE, OPEN_PURPOSE};
+use crate::utils::{err401, err404, err500, see_other};
+
+// A transparent 1x1 GIF
+const PIXEL: &[u8] = &[
+    0x47, 0x49, 0x46, 0x38, 0x39, 0x61, 0x01, 0x00, 0x01, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0xff, 0xff, 0xff, 0x21, 0xf9, 0x04, 0x01, 0x00, 0x00, 0x00, 0x00, 0x2c, 0x00, 0x00, 0x00, 0x00,
+    0x01, 0x00, 0x01, 0x00, 0x00, 0x02, 0x02, 0x44, 0x01, 0x00, 0x3b,
+];
+
+#[derive(serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct TrackingParameters {
+    /// Signed token identifying the issue, the recipient and the link
+    token: String,
+}
+
+#[utoipa::path(
+    get,
+    path = "/tracking/open",
+    tag = "tracking",
+    params(TrackingParameters),
+    responses((status = 200, description = "A transparent pixel, the open is recorded if the token is valid", content_type = "image/gif"))
+)]
+#[tracing::instrument(name = "Track an issue open", skip_all)]
+pub async fn track_open(
+    parameters: web::Query<TrackingParameters>,
+    connection_pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, actix_web::Error> {
+    // Images are not the place to report errors, an invalid token still gets the pixel
+    if let Some(tracked) = TrackedEmail::verify(&hmac_secret.0, OPEN_PURPOSE, &parameters.token) {
+        record_email_event(&connection_pool, &tracked, "open")
+            .await
+            .map_err(err500)?;
+    }
+    Ok(HttpResponse::Ok()
+        .content_type("image/gif")
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .body(PIXEL))
+}
+
+#[utoipa::path(
+    get,
+    path = "/tracking/click",
+    tag = "tracking",
+    params(TrackingParameters),
+    responses(
+        (status = 303, description = "Records the click and redirects to the link of the issue"),
+        (status = 401, description = "The token is invalid"),
+        (status = 404, description = "The issue or link no longer exists")
+    )
+)]
+#[tracing::instrument(name = "Track a link click", skip_all)]
+pub async fn track_click(
+    parameters: web::Query<TrackingParameters>,
+    connection_pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let tracked = TrackedEmail::verify(&hmac_secret.0, CLICK_PURPOSE, &parameters.token)
+        .ok_or_else(|| err401("Invalid tracking token"))?;
+    let href = sqlx::query!(
+        r#"
+        SELECT href
+        FROM issue_links
+        WHERE newsletter_issue_id = $1 AND link_id = $2
+        "#,
+        tracked.newsletter_issue_id,
+        tracked.link_id
+    )
+    .fetch_optional(connection_pool.as_ref())
+    .await
+    .context("Failed to look up the link")
+    .map_err(err500)?
+    .ok_or_else(|| err404("Link not found"))?
+    .href;
+    record_email_event(&connection_pool, &tracked, "click")
+        .await
+        .map_err(err500)?;
+    // The link was stored as written in the HTML, e.g. with `&amp;`
+    let url = htmlescape::decode_html(&href).unwrap_or(href);
+    Ok(see_other(&url))
+}
+
+// Only recorded while the issue tracks this kind of event and the subscriber is still on one
+// of its lists: nothing is recorded for erased or unsubscribed subscribers
+#[tracing::instrument(skip(connection_pool))]
+async fn record_email_event(
+    connection_pool: &PgPool,
+    tracked: &TrackedEmail,
+    event: &str,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO email_events (newsletter_issue_id, subscriber_id, event, link_id)
+        SELECT n.newsletter_issue_id, s.id, $3, $4
+        FROM newsletter_issues n, subscriptions s
+        WHERE n.newsletter_issue_id = $1
+            AND s.id = $2
+            AND s.status = 'confirmed'
+            AND CASE WHEN $3 = 'open' THEN n.track_opens ELSE n.track_clicks END
+            AND EXISTS (
+                SELECT 1
+                FROM newsletter_issue_lists il
+                JOIN list_subscriptions l ON l.list_id = il.list_id
+                WHERE il.newsletter_issue_id = n.newsletter_issue_id
+                    AND l.subscriber_id = s.id
+                    AND l.status = 'confirmed'
+            )
+        "#,
+        tracked.newsletter_issue_id,
+        tracked.subscriber_id,
+        event,
+        tracked.link_id
+    )
+    .execute(connection_pool)
+    .await
+    .context("Failed to record the email event")?;
+    Ok(())
+}
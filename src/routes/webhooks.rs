@@ -0,0 +1,232 @@
+use actix_web::http::header::{AUTHORIZATION, WWW_AUTHENTICATE};
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use secrecy::ExposeSecret;
+use sha2::{Digest, Sha256};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::configuration::WebhookSettings;
+use crate::domain::SuppressionEntry;
+use crate::routes::record_subscription_event;
+use crate::suppression::suppress;
+use crate::utils::err500;
+
+// The fields we use from Postmark's bounce and spam complaint webhooks.
+// The whole payload is stored alongside them.
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct PostmarkEvent {
+    record_type: String,
+    // Only set for bounces, e.g. `HardBounce` or `SoftBounce`
+    #[serde(rename = "Type")]
+    bounce_type: Option<String>,
+    email: String,
+    #[serde(rename = "MessageID")]
+    message_id: Option<String>,
+}
+
+impl PostmarkEvent {
+    // The status the subscriber ends up in. Temporary failures, such as soft bounces or
+    // auto-responders, are only recorded: the address may work again later.
+    fn subscriber_status(&self) -> Option<&'static str> {
+        match (self.record_type.as_str(), self.bounce_type.as_deref()) {
+            ("SpamComplaint", _) => Some("complained"),
+            ("Bounce", Some("HardBounce" | "BadEmailAddress" | "ManuallyDeactivated")) => {
+                Some("bounced")
+            }
+            _ => None,
+        }
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/webhooks/postmark",
+    tag = "webhooks",
+    request_body(
+        content = Object,
+        description = "A Postmark bounce or spam complaint webhook payload",
+        content_type = "application/json"
+    ),
+    responses(
+        (status = 200, description = "The event was recorded, hard bounces and complaints stop further emails to the address"),
+        (status = 400, description = "The payload is not a bounce or spam complaint"),
+        (status = 401, description = "The webhook credentials are missing or wrong")
+    ),
+    security(("webhook_basic_auth" = []))
+)]
+#[tracing::instrument(name = "Receive a Postmark webhook", skip_all)]
+pub async fn postmark_webhook(
+    request: HttpRequest,
+    payload: web::Json<serde_json::Value>,
+    connection_pool: web::Data<PgPool>,
+    settings: web::Data<WebhookSettings>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if !has_valid_credentials(&request, &settings) {
+        return Ok(HttpResponse::Unauthorized()
+            .insert_header((WWW_AUTHENTICATE, r#"Basic realm="webhooks""#))
+            .finish());
+    }
+    let payload = payload.into_inner();
+    let event: PostmarkEvent = serde_json::from_value(payload.clone())
+        .map_err(|e| actix_web::error::ErrorBadRequest(e.to_string()))?;
+    record_bounce_event(&connection_pool, &event, payload)
+        .await
+        .map_err(err500)?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+fn has_valid_credentials(request: &HttpRequest, settings: &WebhookSettings) -> bool {
+    let Some(credentials) = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Basic "))
+        .and_then(|encoded| STANDARD.decode(encoded.trim()).ok())
+        .and_then(|decoded| String::from_utf8(decoded).ok())
+    else {
+        return false;
+    };
+    let Some((username, password)) = credentials.split_once(':') else {
+        return false;
+    };
+    // Comparing digests keeps the time taken independent of how much of the password matches
+    username == settings.username
+        && Sha256::digest(password.as_bytes())
+            == Sha256::digest(settings.password.expose_secret().as_bytes())
+}
+
+// Records the event and, for hard bounces and complaints, stops emailing the address
+#[tracing::instrument(skip(connection_pool, payload))]
+async fn record_bounce_event(
+    connection_pool: &PgPool,
+    event: &PostmarkEvent,
+    payload: serde_json::Value,
+) -> Result<(), anyhow::Error> {
+    let mut transaction = connection_pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let subscriber_id = sqlx::query!(
+        "SELECT id FROM subscriptions WHERE email = $1 FOR UPDATE",
+        event.email
+    )
+    .fetch_optional(&mut transaction)
+    .await
+    .context("Failed to look up the subscriber")?
+    .map(|r| r.id);
+    sqlx::query!(
+        r#"
+        INSERT INTO bounce_events (
+            subscriber_id, email, record_type, bounce_type, message_id, payload
+        )
+        VALUES ($1, $2, $3, $4, $5, $6)
+        "#,
+        subscriber_id,
+        event.email,
+        event.record_type,
+        event.bounce_type,
+        event.message_id,
+        payload
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to record the bounce event")?;
+    if let Some(status) = event.subscriber_status() {
+        stop_emailing(&mut transaction, event, subscriber_id, status).await?;
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the bounce event")?;
+    Ok(())
+}
+
+// Adds the address to the suppression list, changes the subscriber's status and drops their
+// queued deliveries
+#[tracing::instrument(skip(transaction, event))]
+async fn stop_emailing(
+    transaction: &mut Transaction<'_, Postgres>,
+    event: &PostmarkEvent,
+    subscriber_id: Option<Uuid>,
+    status: &str,
+) -> Result<(), anyhow::Error> {
+    match SuppressionEntry::parse(&event.email) {
+        Ok(entry) => {
+            let reason = match status {
+                "complained" => "spam_complaint",
+                _ => "hard_bounce",
+            };
+            suppress(&mut *transaction, &entry, reason)
+                .await
+                .context("Failed to add the address to the suppression list")?;
+        }
+        Err(e) => tracing::warn!(error.message = %e, "Not suppressing an invalid address"),
+    }
+    if let Some(subscriber_id) = subscriber_id {
+        sqlx::query!(
+            "UPDATE subscriptions SET status = $2 WHERE id = $1",
+            subscriber_id,
+            status
+        )
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to update the subscriber status")?;
+        sqlx::query!(
+            r#"
+            DELETE FROM jobs
+            WHERE job_type = 'deliver_issue' AND payload ->> 'subscriber_email' = $1
+            "#,
+            event.email
+        )
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to drop queued deliveries")?;
+        record_subscription_event(&mut *transaction, subscriber_id, None, status)
+            .await
+            .context("Failed to record the subscription event")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PostmarkEvent;
+
+    fn event(record_type: &str, bounce_type: Option<&str>) -> PostmarkEvent {
+        PostmarkEvent {
+            record_type: record_type.into(),
+            bounce_type: bounce_type.map(Into::into),
+            email: "ann@test.com".into(),
+            message_id: None,
+        }
+    }
+
+    #[test]
+    fn only_permanent_failures_change_the_subscriber_status() {
+        assert_eq!(
+            event("SpamComplaint", Some("SpamComplaint")).subscriber_status(),
+            Some("complained")
+        );
+        assert_eq!(
+            event("Bounce", Some("HardBounce")).subscriber_status(),
+            Some("bounced")
+        );
+        assert_eq!(
+            event("Bounce", Some("BadEmailAddress")).subscriber_status(),
+            Some("bounced")
+        );
+        assert_eq!(
+            event("Bounce", Some("SoftBounce")).subscriber_status(),
+            None
+        );
+        assert_eq!(
+            event("Bounce", Some("AutoResponder")).subscriber_status(),
+            None
+        );
+        assert_eq!(event("Delivery", None).subscriber_status(), None);
+    }
+}
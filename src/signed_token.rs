@@ -0,0 +1,168 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum SignedTokenError {
+    #[error("The token is malformed")]
+    Malformed,
+    #[error("The token signature is invalid")]
+    InvalidSignature,
+    #[error("The token has expired")]
+    Expired,
+}
+
+// A URL-safe token binding a subject (e.g. a subscriber email) to a purpose and an expiry time.
+// Format: `<base64 subject>.<expiry unix timestamp>.<base64 HMAC-SHA256 signature>`.
+// The purpose is part of the signed payload but not of the token, so a token issued for one
+// purpose can't be replayed for another.
+pub fn sign(
+    secret: &Secret<String>,
+    purpose: &str,
+    subject: &str,
+    expires_at: DateTime<Utc>,
+) -> String {
+    let payload = format!(
+        "{}.{}",
+        URL_SAFE_NO_PAD.encode(subject),
+        expires_at.timestamp()
+    );
+    let signature = URL_SAFE_NO_PAD.encode(mac(secret, purpose, &payload).finalize().into_bytes());
+    format!("{payload}.{signature}")
+}
+
+// Returns the subject of a token if its signature is valid for `purpose` and it hasn't expired.
+pub fn verify(
+    secret: &Secret<String>,
+    purpose: &str,
+    token: &str,
+) -> Result<String, SignedTokenError> {
+    let (payload, signature) = token.rsplit_once('.').ok_or(SignedTokenError::Malformed)?;
+    let signature = URL_SAFE_NO_PAD
+        .decode(signature)
+        .map_err(|_| SignedTokenError::Malformed)?;
+    mac(secret, purpose, payload)
+        .verify_slice(&signature)
+        .map_err(|_| SignedTokenError::InvalidSignature)?;
+    let (subject, expires_at) = payload.split_once('.').ok_or(SignedTokenError::Malformed)?;
+    let expires_at: i64 = expires_at
+        .parse()
+        .map_err(|_| SignedTokenError::Malformed)?;
+    if expires_at < Utc::now().timestamp() {
+        return Err(SignedTokenError::Expired);
+    }
+    let subject = URL_SAFE_NO_PAD
+        .decode(subject)
+        .map_err(|_| SignedTokenError::Malformed)?;
+    String::from_utf8(subject).map_err(|_| SignedTokenError::Malformed)
+}
+
+fn mac(secret: &Secret<String>, purpose: &str, payload: &str) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.expose_secret().as_bytes())
+        .expect("HMAC can take a key of any size");
+    mac.update(purpose.as_bytes());
+    mac.update(b".");
+    mac.update(payload.as_bytes());
+    mac
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{sign, verify, SignedTokenError};
+    use chrono::{Duration, Utc};
+    use claims::{assert_err_eq, assert_ok_eq};
+    use secrecy::Secret;
+
+    fn secret() -> Secret<String> {
+        Secret::new("a-very-secret-key".to_string())
+    }
+
+    #[test]
+    fn a_signed_token_can_be_verified() {
+        let token = sign(
+            &secret(),
+            "purpose",
+            "bob@test.com",
+            Utc::now() + Duration::hours(1),
+        );
+        assert_ok_eq!(
+            verify(&secret(), "purpose", &token),
+            "bob@test.com".to_string()
+        );
+    }
+
+    #[test]
+    fn a_token_signed_for_another_purpose_is_rejected() {
+        let token = sign(
+            &secret(),
+            "purpose",
+            "bob@test.com",
+            Utc::now() + Duration::hours(1),
+        );
+        assert_err_eq!(
+            verify(&secret(), "another-purpose", &token),
+            SignedTokenError::InvalidSignature
+        );
+    }
+
+    #[test]
+    fn a_token_signed_with_another_secret_is_rejected() {
+        let token = sign(
+            &secret(),
+            "purpose",
+            "bob@test.com",
+            Utc::now() + Duration::hours(1),
+        );
+        let other_secret = Secret::new("another-secret".to_string());
+        assert_err_eq!(
+            verify(&other_secret, "purpose", &token),
+            SignedTokenError::InvalidSignature
+        );
+    }
+
+    #[test]
+    fn a_tampered_subject_is_rejected() {
+        let token = sign(
+            &secret(),
+            "purpose",
+            "bob@test.com",
+            Utc::now() + Duration::hours(1),
+        );
+        let forged = sign(&secret(), "purpose", "alice@test.com", Utc::now());
+        let (_, signature) = token.rsplit_once('.').unwrap();
+        let (forged_payload, _) = forged.rsplit_once('.').unwrap();
+        assert_err_eq!(
+            verify(
+                &secret(),
+                "purpose",
+                &format!("{forged_payload}.{signature}")
+            ),
+            SignedTokenError::InvalidSignature
+        );
+    }
+
+    #[test]
+    fn an_expired_token_is_rejected() {
+        let token = sign(
+            &secret(),
+            "purpose",
+            "bob@test.com",
+            Utc::now() - Duration::hours(1),
+        );
+        assert_err_eq!(
+            verify(&secret(), "purpose", &token),
+            SignedTokenError::Expired
+        );
+    }
+
+    #[test]
+    fn garbage_is_rejected() {
+        assert_err_eq!(
+            verify(&secret(), "purpose", "garbage"),
+            SignedTokenError::Malformed
+        );
+    }
+}
@@ -1,10 +1,23 @@
-use crate::authentication::reject_anonymous_users;
+use crate::authentication::{reject_anonymous_users, reject_invalid_api_tokens};
 use crate::configuration::{DatabaseSettings, Settings};
 use crate::email_client::EmailClient;
+use crate::email_templates::EmailTemplates;
+use crate::idempotency::save_idempotent_responses;
 use crate::routes::{
-    admin_dashboard, change_password, change_password_form, confirm, health_check, home, log_out,
-    login, login_form, publish_newsletter, publish_newsletter_form, subscribe,
+    add_suppression, admin_dashboard, api_get_subscriber, api_update_subscriber, change_password,
+    change_password_form, confirm, confirm_subscriber, confirmation_email_form,
+    count_newsletter_recipients, create_api_token, create_list, delete_subscriber,
+    download_subscriber_data, edit_subscriber, edit_subscriber_attributes, email_provider_health,
+    erase_subscriber_data, erase_subscriber_data_form, export_subscribers, health_check, home,
+    import_subscribers, import_subscribers_form, issue_details, list_api_tokens, list_issues,
+    list_lists, list_subscribers, list_suppressions, log_out, login, login_form,
+    manage_subscriptions, openapi_json, postmark_webhook, preview_confirmation_email,
+    publish_newsletter, publish_newsletter_form, remove_suppression, replay_newsletter_submissions,
+    request_subscriber_data, reset_confirmation_email, revoke_api_token, save_confirmation_email,
+    send_test_confirmation_email, subscribe, subscriber_details, track_click, track_open,
+    unsubscribe, unsubscribe_subscriber,
 };
+use actix_multipart::form::MultipartFormConfig;
 use actix_session::storage::RedisSessionStore;
 use actix_session::SessionMiddleware;
 use actix_web::cookie::Key;
@@ -18,35 +31,36 @@ use secrecy::{ExposeSecret, Secret};
 use sqlx::postgres::PgPoolOptions;
 use sqlx::PgPool;
 use std::net::TcpListener;
+use std::path::Path;
 use tracing_actix_web::TracingLogger;
 
 pub struct Application {
     port: u16,
     server: Server,
+    email_client: EmailClient,
 }
 
 impl Application {
     pub async fn build(config: Settings) -> Result<Self, anyhow::Error> {
         let connection_pool = get_connection_pool(&config.database);
-        let base_url = config.application.base_url;
-        let email_client = config.email_client.client();
+        let email_client = config.email_client.clone().client();
         let address = format!("{}:{}", config.application.host, config.application.port);
         let listener = TcpListener::bind(address)?;
         let port = listener.local_addr().unwrap().port();
-        let server = run(
-            listener,
-            connection_pool,
+        let server = run(listener, connection_pool, email_client.clone(), config).await?;
+        Ok(Self {
+            port,
+            server,
             email_client,
-            base_url,
-            config.application.hmac_secret,
-            config.redis_uri,
-        )
-        .await?;
-        Ok(Self { port, server })
+        })
     }
     pub fn port(&self) -> u16 {
         self.port
     }
+    // Shares the circuit breaker with the application, e.g. for the delivery worker
+    pub fn email_client(&self) -> EmailClient {
+        self.email_client.clone()
+    }
     pub async fn run_until_stopped(self) -> Result<(), std::io::Error> {
         self.server.await
     }
@@ -60,23 +74,36 @@ pub fn get_connection_pool(config: &DatabaseSettings) -> PgPool {
 
 pub struct ApplicationBaseUrl(pub String);
 
+// Key used to sign links sent to subscribers, shared with the cookie signing key
+pub struct HmacSecret(pub Secret<String>);
+
 pub async fn run(
     listener: TcpListener,
     connection_pool: PgPool,
     email_client: EmailClient,
-    base_url: String,
-    hmac_secret: Secret<String>,
-    redis_uri: Secret<String>,
+    config: Settings,
 ) -> Result<Server, anyhow::Error> {
+    let email_templates = match &config.application.email_templates_directory {
+        Some(directory) => EmailTemplates::load(Path::new(directory))?,
+        None => EmailTemplates::builtin(),
+    };
+    let base_url = config.application.base_url;
+    let hmac_secret = config.application.hmac_secret;
+    let redis_uri = config.redis_uri;
     let connection_pool = Data::new(connection_pool);
     let email_client = Data::new(email_client);
+    let email_templates = Data::new(email_templates);
     let redis_store = RedisSessionStore::new(redis_uri.expose_secret()).await?;
     let secret_key = Key::from(hmac_secret.expose_secret().as_bytes());
     let message_store = CookieMessageStore::builder(secret_key.clone()).build();
     let message_framework = FlashMessagesFramework::builder(message_store).build();
     let base_url = Data::new(ApplicationBaseUrl(base_url));
+    let hmac_secret = Data::new(HmacSecret(hmac_secret));
+    let subscription_settings = Data::new(config.subscriptions);
+    let webhook_settings = Data::new(config.webhooks);
+    let idempotency_settings = Data::new(config.idempotency);
     let server = HttpServer::new(move || {
-        App::new()
+        let app = App::new()
             .wrap(TracingLogger::default())
             .wrap(message_framework.clone())
             .wrap(SessionMiddleware::new(
@@ -87,21 +114,151 @@ pub async fn run(
             .route("/login", web::get().to(login_form))
             .route("/login", web::post().to(login))
             .route("/health_check", web::get().to(health_check))
+            .route(
+                "/health_check/email_provider",
+                web::get().to(email_provider_health),
+            )
             .route("/subscriptions", web::post().to(subscribe))
             .route("/subscriptions/confirm", web::get().to(confirm))
+            .route(
+                "/subscriptions/unsubscribe",
+                web::get().to(manage_subscriptions),
+            )
+            .route("/subscriptions/unsubscribe", web::post().to(unsubscribe))
+            .route(
+                "/subscriptions/data_request",
+                web::post().to(request_subscriber_data),
+            )
+            .route(
+                "/subscriptions/data",
+                web::get().to(download_subscriber_data),
+            )
+            .route(
+                "/subscriptions/erase",
+                web::get().to(erase_subscriber_data_form),
+            )
+            .route(
+                "/subscriptions/erase",
+                web::post().to(erase_subscriber_data),
+            )
+            .route("/tracking/open", web::get().to(track_open))
+            .route("/tracking/click", web::get().to(track_click))
+            .route("/webhooks/postmark", web::post().to(postmark_webhook))
+            .route("/openapi.json", web::get().to(openapi_json))
             .service(
                 web::scope("/admin")
                     .wrap(from_fn(reject_anonymous_users))
                     .route("/dashboard", web::get().to(admin_dashboard))
                     .route("/logout", web::post().to(log_out))
                     .route("/newsletters", web::get().to(publish_newsletter_form))
-                    .route("/newsletters", web::post().to(publish_newsletter))
+                    .route(
+                        "/newsletters",
+                        web::post()
+                            .to(publish_newsletter)
+                            .wrap(from_fn(replay_newsletter_submissions)),
+                    )
+                    .route(
+                        "/newsletters/recipients",
+                        web::post().to(count_newsletter_recipients),
+                    )
+                    .route("/issues", web::get().to(list_issues))
+                    .route("/issues/{issue_id}", web::get().to(issue_details))
                     .route("/password", web::get().to(change_password_form))
-                    .route("/password", web::post().to(change_password)),
+                    .route("/password", web::post().to(change_password))
+                    .route(
+                        "/emails/confirmation",
+                        web::get().to(confirmation_email_form),
+                    )
+                    .route(
+                        "/emails/confirmation",
+                        web::post().to(save_confirmation_email),
+                    )
+                    .route(
+                        "/emails/confirmation/preview",
+                        web::post().to(preview_confirmation_email),
+                    )
+                    .route(
+                        "/emails/confirmation/test",
+                        web::post().to(send_test_confirmation_email),
+                    )
+                    .route(
+                        "/emails/confirmation/reset",
+                        web::post().to(reset_confirmation_email),
+                    )
+                    .route("/api_tokens", web::get().to(list_api_tokens))
+                    .route("/api_tokens", web::post().to(create_api_token))
+                    .route(
+                        "/api_tokens/{token_id}/revoke",
+                        web::post().to(revoke_api_token),
+                    )
+                    .route("/lists", web::get().to(list_lists))
+                    .route("/lists", web::post().to(create_list))
+                    .route("/suppressions", web::get().to(list_suppressions))
+                    .route("/suppressions", web::post().to(add_suppression))
+                    .route(
+                        "/suppressions/{suppression_id}/delete",
+                        web::post().to(remove_suppression),
+                    )
+                    .route("/subscribers", web::get().to(list_subscribers))
+                    // Registered before /subscribers/{subscriber_id} so they are matched first
+                    .service(
+                        web::resource("/subscribers/import")
+                            // The file may be up to 10 MiB, more than the 2 MiB kept in memory
+                            // by default
+                            .app_data(MultipartFormConfig::default().memory_limit(10 * 1024 * 1024))
+                            .route(web::get().to(import_subscribers_form))
+                            .route(web::post().to(import_subscribers)),
+                    )
+                    .route("/subscribers/export", web::get().to(export_subscribers))
+                    .route(
+                        "/subscribers/{subscriber_id}",
+                        web::get().to(subscriber_details),
+                    )
+                    .route(
+                        "/subscribers/{subscriber_id}",
+                        web::post().to(edit_subscriber),
+                    )
+                    .route(
+                        "/subscribers/{subscriber_id}/attributes",
+                        web::post().to(edit_subscriber_attributes),
+                    )
+                    .route(
+                        "/subscribers/{subscriber_id}/confirm",
+                        web::post().to(confirm_subscriber),
+                    )
+                    .route(
+                        "/subscribers/{subscriber_id}/unsubscribe",
+                        web::post().to(unsubscribe_subscriber),
+                    )
+                    .route(
+                        "/subscribers/{subscriber_id}/delete",
+                        web::post().to(delete_subscriber),
+                    ),
+            )
+            .service(
+                web::scope("/api")
+                    .wrap(from_fn(save_idempotent_responses))
+                    .wrap(from_fn(reject_invalid_api_tokens))
+                    .route(
+                        "/subscribers/{subscriber_id}",
+                        web::get().to(api_get_subscriber),
+                    )
+                    .route(
+                        "/subscribers/{subscriber_id}",
+                        web::patch().to(api_update_subscriber),
+                    ),
             )
             .app_data(connection_pool.clone())
             .app_data(email_client.clone())
+            .app_data(email_templates.clone())
             .app_data(base_url.clone())
+            .app_data(hmac_secret.clone())
+            .app_data(subscription_settings.clone())
+            .app_data(webhook_settings.clone())
+            .app_data(idempotency_settings.clone());
+        #[cfg(feature = "swagger-ui")]
+        let app = app.service(crate::routes::swagger_ui());
+        app
     })
     .listen(listener)?
     .run();
@@ -0,0 +1,49 @@
+use sqlx::PgExecutor;
+use uuid::Uuid;
+
+use crate::domain::SuppressionEntry;
+
+// Whether the address, or its domain, is on the suppression list.
+// Every email sent to a subscriber must be checked against it first.
+#[tracing::instrument(name = "Checking the suppression list", skip(executor))]
+pub async fn is_suppressed(
+    executor: impl PgExecutor<'_>,
+    email: &str,
+) -> Result<bool, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT EXISTS (
+            SELECT 1
+            FROM suppressions
+            WHERE entry = lower($1) OR entry = lower(split_part($1, '@', 2))
+        ) AS "suppressed!"
+        "#,
+        email
+    )
+    .fetch_one(executor)
+    .await?;
+    Ok(row.suppressed)
+}
+
+// Adds the entry to the suppression list, unless it is already on it.
+// Returns whether it was added.
+#[tracing::instrument(name = "Adding to the suppression list", skip(executor))]
+pub async fn suppress(
+    executor: impl PgExecutor<'_>,
+    entry: &SuppressionEntry,
+    reason: &str,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO suppressions (id, entry, reason)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (entry) DO NOTHING
+        "#,
+        Uuid::new_v4(),
+        entry.as_ref(),
+        reason
+    )
+    .execute(executor)
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
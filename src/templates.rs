@@ -0,0 +1,27 @@
+use actix_web::http::header::ContentType;
+use actix_web::http::StatusCode;
+use actix_web::HttpResponse;
+use askama::Template;
+
+// Page templates live in `templates/` and are checked at compile time. Interpolated values are
+// HTML-escaped unless a template explicitly opts out.
+
+// A page with a title and a few paragraphs of text
+#[derive(Template)]
+#[template(path = "message.html")]
+pub struct MessagePage<'a> {
+    pub title: &'a str,
+    pub paragraphs: &'a [&'a str],
+}
+
+pub fn html_response(status: StatusCode, template: &impl Template) -> HttpResponse {
+    match template.render() {
+        Ok(body) => HttpResponse::build(status)
+            .content_type(ContentType::html())
+            .body(body),
+        Err(e) => {
+            tracing::error!(error.message = %e, "Failed to render a template");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
@@ -0,0 +1,208 @@
+use chrono::{Duration, Utc};
+use htmlescape::encode_minimal;
+use secrecy::Secret;
+use uuid::Uuid;
+
+use crate::signed_token;
+
+pub const OPEN_PURPOSE: &str = "issue_open";
+pub const CLICK_PURPOSE: &str = "issue_click";
+// Like unsubscribe links, tracked links should keep working in old issues
+const TRACKING_LINK_TTL_DAYS: i64 = 365;
+
+// Which events are recorded for an issue, chosen when publishing it
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TrackingSettings {
+    pub opens: bool,
+    pub clicks: bool,
+}
+
+// A link of an issue whose clicks are tracked
+pub struct IssueLink {
+    pub link_id: i32,
+    // As written in the HTML content, i.e. still HTML-escaped
+    pub href: String,
+}
+
+// What a tracking token was issued for
+#[derive(Debug, PartialEq, Eq)]
+pub struct TrackedEmail {
+    pub newsletter_issue_id: Uuid,
+    pub subscriber_id: Uuid,
+    pub link_id: Option<i32>,
+}
+
+impl TrackedEmail {
+    fn subject(&self) -> String {
+        match self.link_id {
+            Some(link_id) => format!(
+                "{}:{}:{link_id}",
+                self.newsletter_issue_id, self.subscriber_id
+            ),
+            None => format!("{}:{}", self.newsletter_issue_id, self.subscriber_id),
+        }
+    }
+
+    fn parse(subject: &str) -> Option<TrackedEmail> {
+        let mut parts = subject.split(':');
+        let newsletter_issue_id = parts.next()?.parse().ok()?;
+        let subscriber_id = parts.next()?.parse().ok()?;
+        let link_id = match parts.next() {
+            Some(link_id) => Some(link_id.parse().ok()?),
+            None => None,
+        };
+        if parts.next().is_some() {
+            return None;
+        }
+        Some(TrackedEmail {
+            newsletter_issue_id,
+            subscriber_id,
+            link_id,
+        })
+    }
+
+    pub fn sign(&self, hmac_secret: &Secret<String>, purpose: &str) -> String {
+        signed_token::sign(
+            hmac_secret,
+            purpose,
+            &self.subject(),
+            Utc::now() + Duration::days(TRACKING_LINK_TTL_DAYS),
+        )
+    }
+
+    // `None` if the token is invalid, expired or was not issued for `purpose`
+    pub fn verify(
+        hmac_secret: &Secret<String>,
+        purpose: &str,
+        token: &str,
+    ) -> Option<TrackedEmail> {
+        let subject = signed_token::verify(hmac_secret, purpose, token).ok()?;
+        Self::parse(&subject)
+    }
+}
+
+// The `href`s of the web links in an issue, in order of appearance and without duplicates.
+// Links built from placeholders, such as `{{ unsubscribe_url }}`, are not tracked.
+pub fn trackable_links(html: &str) -> Vec<String> {
+    let mut links: Vec<String> = Vec::new();
+    for part in html.split("href=\"").skip(1) {
+        let Some(end) = part.find('"') else {
+            continue;
+        };
+        let href = &part[..end];
+        let is_trackable =
+            (href.starts_with("https://") || href.starts_with("http://")) && !href.contains("{{");
+        if is_trackable && !links.iter().any(|link| link == href) {
+            links.push(href.to_string());
+        }
+    }
+    links
+}
+
+// Points the tracked links of `html` to the click redirect, for a single recipient
+pub fn rewrite_links(
+    html: &str,
+    links: &[IssueLink],
+    base_url: &str,
+    hmac_secret: &Secret<String>,
+    newsletter_issue_id: Uuid,
+    subscriber_id: Uuid,
+) -> String {
+    let mut html = html.to_string();
+    for link in links {
+        let token = TrackedEmail {
+            newsletter_issue_id,
+            subscriber_id,
+            link_id: Some(link.link_id),
+        }
+        .sign(hmac_secret, CLICK_PURPOSE);
+        let redirect = format!("{base_url}/tracking/click?token={token}");
+        html = html.replace(
+            &format!(r#"href="{}""#, link.href),
+            &format!(r#"href="{}""#, encode_minimal(&redirect)),
+        );
+    }
+    html
+}
+
+// An invisible image, whose loading records that the recipient opened the issue
+pub fn open_pixel(
+    base_url: &str,
+    hmac_secret: &Secret<String>,
+    newsletter_issue_id: Uuid,
+    subscriber_id: Uuid,
+) -> String {
+    let token = TrackedEmail {
+        newsletter_issue_id,
+        subscriber_id,
+        link_id: None,
+    }
+    .sign(hmac_secret, OPEN_PURPOSE);
+    format!(
+        r#"<img src="{}" width="1" height="1" alt="">"#,
+        encode_minimal(&format!("{base_url}/tracking/open?token={token}"))
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        rewrite_links, trackable_links, IssueLink, TrackedEmail, CLICK_PURPOSE, OPEN_PURPOSE,
+    };
+    use secrecy::Secret;
+    use uuid::Uuid;
+
+    fn secret() -> Secret<String> {
+        Secret::new("a-very-secret-key".into())
+    }
+
+    #[test]
+    fn only_web_links_without_placeholders_are_tracked() {
+        let html = r#"<a href="https://a.com">A</a> <a href="mailto:x@y.com">M</a>
+            <a href="{{ unsubscribe_url }}">U</a> <a href="http://b.com?x=1&amp;y=2">B</a>
+            <a href="https://a.com">A again</a>"#;
+
+        assert_eq!(
+            trackable_links(html),
+            vec!["https://a.com", "http://b.com?x=1&amp;y=2"]
+        );
+    }
+
+    #[test]
+    fn links_are_rewritten_with_a_token_for_the_recipient() {
+        let issue_id = Uuid::new_v4();
+        let subscriber_id = Uuid::new_v4();
+        let links = [IssueLink {
+            link_id: 1,
+            href: "https://a.com".into(),
+        }];
+
+        let html = rewrite_links(
+            r#"<a href="https://a.com">A</a> <a href="https://other.com">B</a>"#,
+            &links,
+            "http://app",
+            &secret(),
+            issue_id,
+            subscriber_id,
+        );
+
+        let token = html
+            .split("/tracking/click?token=")
+            .nth(1)
+            .unwrap()
+            .split('"')
+            .next()
+            .unwrap();
+        assert_eq!(
+            TrackedEmail::verify(&secret(), CLICK_PURPOSE, token),
+            Some(TrackedEmail {
+                newsletter_issue_id: issue_id,
+                subscriber_id,
+                link_id: Some(1),
+            })
+        );
+        assert!(html.contains(r#"<a href="https://other.com">"#));
+        // A click token can't be used to record an open
+        assert_eq!(TrackedEmail::verify(&secret(), OPEN_PURPOSE, token), None);
+    }
+}
@@ -1,5 +1,5 @@
-use actix_web::http::header::LOCATION;
-use actix_web::HttpResponse;
+use actix_web::http::header::{Accept, Header, LOCATION};
+use actix_web::{HttpRequest, HttpResponse};
 
 pub fn err400<T>(err: T) -> actix_web::Error
 where
@@ -8,6 +8,20 @@ where
     actix_web::error::ErrorBadRequest(err)
 }
 
+pub fn err401<T>(err: T) -> actix_web::Error
+where
+    T: std::fmt::Debug + std::fmt::Display + 'static,
+{
+    actix_web::error::ErrorUnauthorized(err)
+}
+
+pub fn err404<T>(err: T) -> actix_web::Error
+where
+    T: std::fmt::Debug + std::fmt::Display + 'static,
+{
+    actix_web::error::ErrorNotFound(err)
+}
+
 pub fn err500<T>(err: T) -> actix_web::Error
 where
     T: std::fmt::Debug + std::fmt::Display + 'static,
@@ -20,3 +34,21 @@ pub fn see_other(location: &str) -> HttpResponse {
         .insert_header((LOCATION, location))
         .finish()
 }
+
+// Browsers list text/html before application/json in their Accept header. Anything else, including
+// no Accept header or `*/*`, is treated as an API client and gets JSON.
+pub fn prefers_html(request: &HttpRequest) -> bool {
+    let accept = match Accept::parse(request) {
+        Ok(accept) => accept,
+        Err(_) => return false,
+    };
+    accept
+        .ranked()
+        .iter()
+        .find_map(|m| match m.essence_str() {
+            "text/html" => Some(true),
+            "application/json" => Some(false),
+            _ => None,
+        })
+        .unwrap_or(false)
+}
@@ -36,3 +36,21 @@ async fn logout_clears_session_state() {
     let response = app.get_admin_dashboard().await;
     assert_is_redirect_to(&response, "/login")
 }
+
+#[tokio::test]
+async fn the_dashboard_escapes_the_username_and_links_every_action() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    sqlx::query!(
+        "UPDATE users SET username = '<script>alert(1)</script>' WHERE user_id = $1",
+        app.test_user.user_id
+    )
+    .execute(&app.connection_pool)
+    .await
+    .unwrap();
+
+    let html_page = app.get_admin_dashboard_html().await;
+
+    assert!(html_page.contains("Welcome &lt;script&gt;alert(1)&lt;/script&gt;!"));
+    assert!(html_page.contains(r#"<a href="/admin/newsletters">Publish newsletter</a>"#));
+}
@@ -0,0 +1,180 @@
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{assert_is_redirect_to, spawn_app};
+
+fn custom_email() -> serde_json::Value {
+    serde_json::json!({
+        "subject": "Hello {{ name }}",
+        "html": "<p>Hi {{ name }}, <a href=\"{{ confirmation_link }}\">confirm</a></p>",
+        "text": "Hi {{name}}, confirm at {{confirmation_link}}",
+    })
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_edit_the_confirmation_email() {
+    let app = spawn_app().await;
+
+    let response = app.post_confirmation_email("", &custom_email()).await;
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn the_form_shows_the_default_content_until_it_is_edited() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let html_page = app.get_confirmation_email_html().await;
+
+    assert!(html_page.contains("It is using the default content."));
+    assert!(html_page.contains(r#"name="subject" value="Welcome!""#));
+}
+
+#[tokio::test]
+async fn new_subscribers_receive_the_saved_confirmation_email() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let response = app.post_confirmation_email("", &custom_email()).await;
+    assert_is_redirect_to(&response, "/admin/emails/confirmation");
+    let html_page = app.get_confirmation_email_html().await;
+    assert!(html_page.contains("The confirmation email has been saved"));
+    assert!(html_page.contains("It has been customized."));
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.post_subscription("name=bob%20%26%20co&email=bob%40test.com".into())
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["Subject"], "Hello bob & co");
+    assert!(body["HtmlBody"]
+        .as_str()
+        .unwrap()
+        .starts_with("<p>Hi bob &amp; co, <a href="));
+    assert!(body["TextBody"]
+        .as_str()
+        .unwrap()
+        .starts_with("Hi bob & co, confirm at "));
+    // The link still confirms the subscription
+    let links = app.get_confirmation_links(email_request);
+    let response = reqwest::get(links.html).await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn unknown_placeholders_are_rejected_on_save() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let mut email = custom_email();
+    email["text"] = "Confirm at {{ link }}".into();
+
+    let response = app.post_confirmation_email("", &email).await;
+
+    assert_eq!(response.status().as_u16(), 400);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("Unknown placeholder `link`"));
+    // The submitted content is kept in the form
+    assert!(html_page.contains("Confirm at {{ link }}"));
+    let saved = sqlx::query!("SELECT email FROM custom_emails")
+        .fetch_optional(&app.connection_pool)
+        .await
+        .unwrap();
+    assert!(saved.is_none());
+}
+
+#[tokio::test]
+async fn preview_renders_the_unsaved_content_without_sending_it() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_confirmation_email("/preview", &custom_email())
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("Subject: Hello Jane Doe"));
+    assert!(html_page.contains("Hi Jane Doe, confirm at "));
+    assert!(html_page.contains("It is using the default content."));
+}
+
+#[tokio::test]
+async fn send_test_emails_the_unsaved_content_to_the_given_address() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let mut email = custom_email();
+    email["test_email"] = "admin@test.com".into();
+
+    let response = app.post_confirmation_email("/test", &email).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("A test email has been sent to admin@test.com"));
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["To"], "admin@test.com");
+    assert_eq!(body["Subject"], "Hello Jane Doe");
+}
+
+#[tokio::test]
+async fn send_test_rejects_an_invalid_address() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let mut email = custom_email();
+    email["test_email"] = "not-an-email".into();
+
+    let response = app.post_confirmation_email("/test", &email).await;
+
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn reset_restores_the_default_confirmation_email() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    app.post_confirmation_email("", &custom_email()).await;
+
+    let response = app
+        .post_confirmation_email("/reset", &serde_json::json!({}))
+        .await;
+    assert_is_redirect_to(&response, "/admin/emails/confirmation");
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.post_subscription("name=bob&email=bob%40test.com".into())
+        .await;
+    app.dispatch_all_pending_emails().await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["Subject"], "Welcome!");
+}
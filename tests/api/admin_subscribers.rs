@@ -0,0 +1,257 @@
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+
+async fn create_subscriber(app: &TestApp, name: &str, email: &str) -> Uuid {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount_as_scoped(&app.email_server)
+        .await;
+    let body = serde_urlencoded::to_string([("name", name), ("email", email)]).unwrap();
+    app.post_subscription(body)
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+    sqlx::query!("SELECT id FROM subscriptions WHERE email = $1", email)
+        .fetch_one(&app.connection_pool)
+        .await
+        .unwrap()
+        .id
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_see_subscribers() {
+    let app = spawn_app().await;
+
+    let response = app.get_admin_subscribers("").await;
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_delete_a_subscriber() {
+    let app = spawn_app().await;
+    let subscriber_id = create_subscriber(&app, "bob", "bob@test.com").await;
+
+    let response = app.post_subscriber_action(subscriber_id, "delete").await;
+
+    assert_is_redirect_to(&response, "/login");
+    let n_subscribers = sqlx::query!(r#"SELECT COUNT(*) as "count!" FROM subscriptions"#)
+        .fetch_one(&app.connection_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_subscribers, 1);
+}
+
+#[tokio::test]
+async fn subscribers_can_be_searched_and_filtered_by_status() {
+    let app = spawn_app().await;
+    let alice = create_subscriber(&app, "alice", "alice@test.com").await;
+    create_subscriber(&app, "bob", "bob@test.com").await;
+    app.test_user.login(&app).await;
+    app.post_subscriber_action(alice, "confirm").await;
+
+    let html_page = app.get_admin_subscribers_html("search=ALICE").await;
+    assert!(html_page.contains("alice@test.com"));
+    assert!(!html_page.contains("bob@test.com"));
+
+    let html_page = app
+        .get_admin_subscribers_html("status=pending_confirmation")
+        .await;
+    assert!(html_page.contains("bob@test.com"));
+    assert!(!html_page.contains("alice@test.com"));
+}
+
+#[tokio::test]
+async fn out_of_range_pages_show_the_closest_page() {
+    let app = spawn_app().await;
+    create_subscriber(&app, "alice", "alice@test.com").await;
+    app.test_user.login(&app).await;
+
+    for page in [i64::MAX, i64::MIN, 0, 2] {
+        let response = app.get_admin_subscribers(&format!("page={page}")).await;
+
+        assert_eq!(response.status().as_u16(), 200);
+        let html_page = response.text().await.unwrap();
+        assert!(html_page.contains("alice@test.com"));
+        assert!(html_page.contains("Page 1 of 1"));
+    }
+}
+
+#[tokio::test]
+async fn subscriber_details_show_status_history_and_token() {
+    let app = spawn_app().await;
+    let subscriber_id = create_subscriber(&app, "bob", "bob@test.com").await;
+    app.test_user.login(&app).await;
+
+    let response = app.post_subscriber_action(subscriber_id, "confirm").await;
+    assert_is_redirect_to(&response, &format!("/admin/subscribers/{subscriber_id}"));
+
+    let html_page = app.get_subscriber_details_html(subscriber_id).await;
+    assert!(html_page.contains("<p><i>Subscriber confirmed</i></p>"));
+    assert!(html_page.contains("Status: confirmed"));
+    assert!(html_page.contains("1 confirmation token(s) issued"));
+    assert!(html_page.contains("subscribed"));
+    assert!(html_page.contains("confirmed_by_admin"));
+}
+
+#[tokio::test]
+async fn unsubscribed_subscribers_do_not_receive_newsletters() {
+    let app = spawn_app().await;
+    let subscriber_id = create_subscriber(&app, "bob", "bob@test.com").await;
+    app.test_user.login(&app).await;
+    app.post_subscriber_action(subscriber_id, "confirm").await;
+
+    app.post_subscriber_action(subscriber_id, "unsubscribe")
+        .await;
+
+    let saved = sqlx::query!(
+        "SELECT status FROM subscriptions WHERE id = $1",
+        subscriber_id
+    )
+    .fetch_one(&app.connection_pool)
+    .await
+    .unwrap();
+    assert_eq!(saved.status, "unsubscribed");
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    app.post_newsletter(&serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": Uuid::new_v4().to_string()
+    }))
+    .await;
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn deleting_a_subscriber_removes_their_tokens() {
+    let app = spawn_app().await;
+    let subscriber_id = create_subscriber(&app, "bob", "bob@test.com").await;
+    app.test_user.login(&app).await;
+
+    let response = app.post_subscriber_action(subscriber_id, "delete").await;
+
+    assert_is_redirect_to(&response, "/admin/subscribers");
+    let n_subscribers = sqlx::query!(r#"SELECT COUNT(*) as "count!" FROM subscriptions"#)
+        .fetch_one(&app.connection_pool)
+        .await
+        .unwrap()
+        .count;
+    let n_tokens = sqlx::query!(r#"SELECT COUNT(*) as "count!" FROM subscription_tokens"#)
+        .fetch_one(&app.connection_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_subscribers, 0);
+    assert_eq!(n_tokens, 0);
+}
+
+#[tokio::test]
+async fn deleting_a_subscriber_removes_their_queued_and_failed_emails() {
+    let app = spawn_app().await;
+    let subscriber_id = create_subscriber(&app, "bob", "bob@test.com").await;
+    app.test_user.login(&app).await;
+    // A confirmation email that could not be sent, and one still waiting
+    sqlx::query!(
+        r#"
+        INSERT INTO jobs (job_type, payload, max_attempts, status)
+        VALUES
+            ('send_confirmation_email', jsonb_build_object('subscriber_id', $1::uuid), 1, 'failed'),
+            ('send_confirmation_email', jsonb_build_object('subscriber_id', $1::uuid), 1, 'queued')
+        "#,
+        subscriber_id
+    )
+    .execute(&app.connection_pool)
+    .await
+    .unwrap();
+
+    let response = app.post_subscriber_action(subscriber_id, "delete").await;
+
+    assert_is_redirect_to(&response, "/admin/subscribers");
+    let n_jobs = sqlx::query!(r#"SELECT COUNT(*) as "count!" FROM jobs"#)
+        .fetch_one(&app.connection_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_jobs, 0);
+}
+
+#[tokio::test]
+async fn confirmation_emails_are_not_sent_once_their_token_is_gone() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let body = serde_urlencoded::to_string([("name", "bob"), ("email", "bob@test.com")]).unwrap();
+    app.post_subscription(body)
+        .await
+        .error_for_status()
+        .unwrap();
+    sqlx::query!("DELETE FROM subscription_tokens")
+        .execute(&app.connection_pool)
+        .await
+        .unwrap();
+
+    app.dispatch_all_pending_emails().await;
+
+    let n_jobs = sqlx::query!(r#"SELECT COUNT(*) as "count!" FROM jobs"#)
+        .fetch_one(&app.connection_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_jobs, 0);
+}
+
+#[tokio::test]
+async fn editing_a_subscriber_validates_the_new_details() {
+    let app = spawn_app().await;
+    let subscriber_id = create_subscriber(&app, "bob", "bob@test.com").await;
+    create_subscriber(&app, "alice", "alice@test.com").await;
+    app.test_user.login(&app).await;
+
+    let test_cases = vec![
+        (
+            serde_json::json!({"name": "bob", "email": "not-an-email"}),
+            "not-an-email is not a valid email",
+        ),
+        (
+            serde_json::json!({"name": "bob", "email": "alice@test.com"}),
+            "alice@test.com is already subscribed",
+        ),
+    ];
+    for (body, error_message) in test_cases {
+        let response = app.post_edit_subscriber(subscriber_id, &body).await;
+        assert_is_redirect_to(&response, &format!("/admin/subscribers/{subscriber_id}"));
+        let html_page = app.get_subscriber_details_html(subscriber_id).await;
+        assert!(html_page.contains(error_message));
+    }
+
+    let response = app
+        .post_edit_subscriber(
+            subscriber_id,
+            &serde_json::json!({"name": "Robert", "email": "robert@test.com"}),
+        )
+        .await;
+    assert_is_redirect_to(&response, &format!("/admin/subscribers/{subscriber_id}"));
+    let saved = sqlx::query!(
+        "SELECT email, name FROM subscriptions WHERE id = $1",
+        subscriber_id
+    )
+    .fetch_one(&app.connection_pool)
+    .await
+    .unwrap();
+    assert_eq!(saved.email, "robert@test.com");
+    assert_eq!(saved.name, "Robert");
+}
@@ -1,14 +1,75 @@
-use crate::helpers::spawn_app;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{spawn_app, TestApp};
 
 #[tokio::test]
 async fn health_check_works() {
     let test_app = spawn_app().await;
     let client = reqwest::Client::new();
     let response = client
-        .get(&format!("{}/health_check", &test_app.address))
+        .get(format!("{}/health_check", &test_app.address))
         .send()
         .await
         .expect("Failed to reach health check endpoint");
     assert!(response.status().is_success());
     assert_eq!(Some(0), response.content_length());
 }
+
+async fn get_email_provider_health(app: &TestApp) -> reqwest::Response {
+    app.api_client
+        .get(format!("{}/health_check/email_provider", &app.address))
+        .send()
+        .await
+        .expect("Failed to reach health check endpoint")
+}
+
+#[tokio::test]
+async fn the_email_provider_is_not_called_after_repeated_failures() {
+    let app = spawn_app().await;
+    let response = get_email_provider_health(&app).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let health: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(health["circuit"], "closed");
+    // As many failures as `circuit_failure_threshold` in the base configuration
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(503))
+        .expect(5)
+        .mount(&app.email_server)
+        .await;
+    for i in 0..5 {
+        let body = format!("name=bob&email=bob{i}%40test.com");
+        app.post_subscription(body)
+            .await
+            .error_for_status()
+            .unwrap();
+    }
+    app.dispatch_all_pending_emails().await;
+
+    let response = get_email_provider_health(&app).await;
+    assert_eq!(response.status().as_u16(), 503);
+    let health: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(health["circuit"], "open");
+    assert!(health["retry_in_seconds"].as_u64().unwrap() <= 30);
+
+    // Confirmation emails are still queued, but not sent while the circuit is open
+    app.post_subscription("name=bob&email=bob%40test.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    sqlx::query!("UPDATE jobs SET run_at = now()")
+        .execute(&app.connection_pool)
+        .await
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+    let n_pending =
+        sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM jobs WHERE status = 'queued'"#)
+            .fetch_one(&app.connection_pool)
+            .await
+            .unwrap()
+            .count;
+    assert_eq!(n_pending, 6);
+}
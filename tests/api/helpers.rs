@@ -2,13 +2,14 @@ use argon2::password_hash::SaltString;
 use argon2::{Argon2, Params, PasswordHasher};
 use once_cell::sync::Lazy;
 use reqwest::Url;
+use secrecy::ExposeSecret;
 use sqlx::{Connection, Executor, PgConnection, PgPool};
 use uuid::Uuid;
 use wiremock::MockServer;
 use zero2prod::{
-    configuration::{get_configuration, DatabaseSettings},
+    configuration::{get_configuration, DatabaseSettings, WebhookSettings},
     email_client::EmailClient,
-    issue_delivery_worker::{try_execute_task, ExecutionOutcome},
+    jobs::{try_execute_job, ExecutionOutcome, JobContext, SubscriberLinks},
     startup::{get_connection_pool, Application},
     telemetry::{get_subscriber, init_subscriber},
 };
@@ -25,6 +26,8 @@ static TRACING: Lazy<()> = Lazy::new(|| {
     }
 });
 
+const BROWSER_ACCEPT: &str = "text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8";
+
 pub struct ConfirmationLinks {
     pub html: reqwest::Url,
     pub plain_text: reqwest::Url,
@@ -38,18 +41,96 @@ pub struct TestApp {
     pub port: u16,
     pub test_user: TestUser,
     pub api_client: reqwest::Client,
+    pub subscriber_links: SubscriberLinks,
+    pub webhook_settings: WebhookSettings,
 }
 
 impl TestApp {
     pub async fn post_subscription(&self, body: String) -> reqwest::Response {
         self.api_client
-            .post(&format!("{}/subscriptions", &self.address))
+            .post(format!("{}/subscriptions", &self.address))
             .header("Content-Type", "application/x-www-form-urlencoded")
             .body(body)
             .send()
             .await
             .expect("Failed to execute request")
     }
+    // Sends a webhook payload with the credentials configured for the email provider
+    pub async fn post_postmark_webhook(&self, payload: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/webhooks/postmark", &self.address))
+            .basic_auth(
+                &self.webhook_settings.username,
+                Some(self.webhook_settings.password.expose_secret()),
+            )
+            .header("Content-Type", "application/json")
+            .body(payload.to_string())
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    // Sends the Accept header of a browser, which gets HTML pages instead of JSON
+    pub async fn get_as_browser(&self, url: reqwest::Url) -> reqwest::Response {
+        self.api_client
+            .get(url)
+            .header("Accept", BROWSER_ACCEPT)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_subscription_as_browser(&self, body: String) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/subscriptions", &self.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .header("Accept", BROWSER_ACCEPT)
+            .body(body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_create_list(&self, slug: &str, name: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/lists", &self.address))
+            .form(&serde_json::json!({ "slug": slug, "name": name }))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_confirmation_email_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/emails/confirmation", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    // `action` is the path segment after /admin/emails/confirmation, empty to save
+    pub async fn post_confirmation_email<Body>(
+        &self,
+        action: &str,
+        body: &Body,
+    ) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!(
+                "{}/admin/emails/confirmation{}",
+                &self.address, action
+            ))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
     pub fn get_confirmation_links(&self, email_request: &wiremock::Request) -> ConfirmationLinks {
         let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
         let get_link = |s: &str| {
@@ -70,7 +151,7 @@ impl TestApp {
     }
     pub async fn get_login_html(&self) -> String {
         self.api_client
-            .get(&format!("{}/login", &self.address))
+            .get(format!("{}/login", &self.address))
             .send()
             .await
             .expect("Failed to execute request")
@@ -80,7 +161,7 @@ impl TestApp {
     }
     pub async fn get_admin_dashboard(&self) -> reqwest::Response {
         self.api_client
-            .get(&format!("{}/admin/dashboard", &self.address))
+            .get(format!("{}/admin/dashboard", &self.address))
             .send()
             .await
             .expect("Failed to execute request")
@@ -90,7 +171,7 @@ impl TestApp {
     }
     pub async fn get_change_password(&self) -> reqwest::Response {
         self.api_client
-            .get(&format!("{}/admin/password", &self.address))
+            .get(format!("{}/admin/password", &self.address))
             .send()
             .await
             .expect("Failed to execute request")
@@ -103,7 +184,7 @@ impl TestApp {
         Body: serde::Serialize,
     {
         self.api_client
-            .post(&format!("{}/login", &self.address))
+            .post(format!("{}/login", &self.address))
             .form(body)
             .send()
             .await
@@ -111,14 +192,14 @@ impl TestApp {
     }
     pub async fn post_logout(&self) -> reqwest::Response {
         self.api_client
-            .post(&format!("{}/admin/logout", &self.address))
+            .post(format!("{}/admin/logout", &self.address))
             .send()
             .await
             .expect("Failed to execute request")
     }
     pub async fn get_publish_newsletter(&self) -> reqwest::Response {
         self.api_client
-            .get(&format!("{}/admin/newsletters", &self.address))
+            .get(format!("{}/admin/newsletters", &self.address))
             .send()
             .await
             .expect("Failed to execute request.")
@@ -133,7 +214,7 @@ impl TestApp {
         Body: serde::Serialize,
     {
         self.api_client
-            .post(&format!("{}/admin/newsletters", &self.address))
+            .post(format!("{}/admin/newsletters", &self.address))
             .form(body)
             .send()
             .await
@@ -144,24 +225,195 @@ impl TestApp {
         Body: serde::Serialize,
     {
         self.api_client
-            .post(&format!("{}/admin/password", &self.address))
+            .post(format!("{}/admin/password", &self.address))
             .form(body)
             .send()
             .await
             .expect("Failed to execute request")
     }
 
-    pub async fn dispatch_all_pending_emails(&self) {
-        loop {
-            if let ExecutionOutcome::EmptyQueue =
-                try_execute_task(&self.connection_pool, &self.email_client)
-                    .await
-                    .unwrap()
-            {
-                break;
-            }
+    pub async fn get_admin_subscribers(&self, query: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/subscribers?{}", &self.address, query))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_admin_subscribers_html(&self, query: &str) -> String {
+        self.get_admin_subscribers(query)
+            .await
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn get_subscriber_details_html(&self, subscriber_id: Uuid) -> String {
+        self.api_client
+            .get(format!(
+                "{}/admin/subscribers/{}",
+                &self.address, subscriber_id
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_edit_subscriber<Body>(
+        &self,
+        subscriber_id: Uuid,
+        body: &Body,
+    ) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!(
+                "{}/admin/subscribers/{}",
+                &self.address, subscriber_id
+            ))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_subscriber_action(
+        &self,
+        subscriber_id: Uuid,
+        action: &str,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(format!(
+                "{}/admin/subscribers/{}/{}",
+                &self.address, subscriber_id, action
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_import_subscribers(
+        &self,
+        csv: &str,
+        initial_status: &str,
+    ) -> reqwest::Response {
+        self.post_import_subscribers_to_list(csv, initial_status, "newsletter")
+            .await
+    }
+
+    pub async fn post_import_subscribers_to_list(
+        &self,
+        csv: &str,
+        initial_status: &str,
+        list: &str,
+    ) -> reqwest::Response {
+        let boundary = "zero2prod-test-boundary";
+        let body = format!(
+            "--{boundary}\r\n\
+            Content-Disposition: form-data; name=\"initial_status\"\r\n\r\n\
+            {initial_status}\r\n\
+            --{boundary}\r\n\
+            Content-Disposition: form-data; name=\"list\"\r\n\r\n\
+            {list}\r\n\
+            --{boundary}\r\n\
+            Content-Disposition: form-data; name=\"file\"; filename=\"subscribers.csv\"\r\n\
+            Content-Type: text/csv\r\n\r\n\
+            {csv}\r\n\
+            --{boundary}--\r\n"
+        );
+        self.api_client
+            .post(format!("{}/admin/subscribers/import", &self.address))
+            .header(
+                "Content-Type",
+                format!("multipart/form-data; boundary={boundary}"),
+            )
+            .body(body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_export_subscribers(&self, query: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!(
+                "{}/admin/subscribers/export?{}",
+                &self.address, query
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_openapi_json(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/openapi.json", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_data_request(&self, email: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/subscriptions/data_request", &self.address))
+            .form(&[("email", email)])
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    // The (download, erase) links from a data request email
+    pub fn get_data_request_links(
+        &self,
+        email_request: &wiremock::Request,
+    ) -> (reqwest::Url, reqwest::Url) {
+        let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+        let mut links: Vec<_> = linkify::LinkFinder::new()
+            .links(body["TextBody"].as_str().unwrap())
+            .filter(|l| *l.kind() == linkify::LinkKind::Url)
+            .map(|l| {
+                let mut link = Url::parse(l.as_str()).unwrap();
+                assert_eq!(link.host_str().unwrap(), "127.0.0.1");
+                link.set_port(Some(self.port)).unwrap();
+                link
+            })
+            .collect();
+        assert_eq!(links.len(), 2);
+        let erase_link = links.pop().unwrap();
+        let download_link = links.pop().unwrap();
+        (download_link, erase_link)
+    }
+
+    pub async fn post_erase_subscriber_data(&self, token: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/subscriptions/erase", &self.address))
+            .form(&[("token", token)])
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    // The context the worker in `main` runs jobs with
+    pub fn job_context(&self) -> JobContext {
+        JobContext {
+            connection_pool: self.connection_pool.clone(),
+            email_client: self.email_client.clone(),
+            links: self.subscriber_links.clone(),
         }
     }
+
+    // Runs every job that is due. Failed jobs are rescheduled for later, so they don't stop
+    // the loop.
+    pub async fn dispatch_all_pending_emails(&self) {
+        let context = self.job_context();
+        while !matches!(
+            try_execute_job(&context).await,
+            Ok(ExecutionOutcome::EmptyQueue)
+        ) {}
+    }
 }
 
 pub struct TestUser {
@@ -229,8 +481,10 @@ pub async fn spawn_app() -> TestApp {
         .await
         .expect("Failed to build application");
     let port = application.port();
+    // Shared with the application like the worker's in `main`, for the same circuit breaker
+    let email_client = application.email_client();
     let address = format!("http://127.0.0.1:{}", application.port());
-    let _ = tokio::spawn(application.run_until_stopped());
+    tokio::spawn(application.run_until_stopped());
     let api_client = reqwest::Client::builder()
         .redirect(reqwest::redirect::Policy::none())
         .cookie_store(true)
@@ -240,10 +494,15 @@ pub async fn spawn_app() -> TestApp {
         address,
         connection_pool: get_connection_pool(&config.database),
         email_server,
-        email_client: config.email_client.client(),
+        email_client,
         port,
         test_user: TestUser::generate(),
         api_client,
+        subscriber_links: SubscriberLinks {
+            base_url: config.application.base_url.clone(),
+            hmac_secret: config.application.hmac_secret.clone(),
+        },
+        webhook_settings: config.webhooks.clone(),
     };
     app.test_user.store(&app.connection_pool).await;
     app
@@ -0,0 +1,96 @@
+use crate::helpers::spawn_app;
+use zero2prod::jobs::{schedule, try_execute_job, CleanUpIdempotencyKeys, ExecutionOutcome};
+
+#[tokio::test]
+async fn scheduled_jobs_run_once_they_are_due() {
+    let app = spawn_app().await;
+    sqlx::query!(
+        r#"
+        INSERT INTO idempotency (scope_id, idempotency_key, created_at)
+        VALUES ($1, 'old', now() - interval '2 days'), ($1, 'recent', now())
+        "#,
+        app.test_user.user_id
+    )
+    .execute(&app.connection_pool)
+    .await
+    .unwrap();
+    let job = CleanUpIdempotencyKeys {
+        max_age_seconds: 24 * 60 * 60,
+    };
+    schedule(
+        &app.connection_pool,
+        &job,
+        chrono::Utc::now() + chrono::Duration::hours(1),
+    )
+    .await
+    .unwrap();
+
+    let outcome = try_execute_job(&app.job_context()).await.unwrap();
+    assert!(matches!(outcome, ExecutionOutcome::EmptyQueue));
+
+    sqlx::query!("UPDATE jobs SET run_at = now()")
+        .execute(&app.connection_pool)
+        .await
+        .unwrap();
+    let outcome = try_execute_job(&app.job_context()).await.unwrap();
+    assert!(matches!(outcome, ExecutionOutcome::TaskCompleted));
+    let keys = sqlx::query!("SELECT idempotency_key FROM idempotency")
+        .fetch_all(&app.connection_pool)
+        .await
+        .unwrap();
+    assert_eq!(keys.len(), 1);
+    assert_eq!(keys[0].idempotency_key, "recent");
+    let n_jobs = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM jobs"#)
+        .fetch_one(&app.connection_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_jobs, 0);
+}
+
+#[tokio::test]
+async fn jobs_without_a_handler_fail_right_away() {
+    let app = spawn_app().await;
+    sqlx::query!("INSERT INTO jobs (job_type, payload, max_attempts) VALUES ('unknown', '{}', 10)")
+        .execute(&app.connection_pool)
+        .await
+        .unwrap();
+
+    let outcome = try_execute_job(&app.job_context()).await;
+
+    assert!(outcome.is_err());
+    let job = sqlx::query!("SELECT status, n_attempts, last_error FROM jobs")
+        .fetch_one(&app.connection_pool)
+        .await
+        .unwrap();
+    assert_eq!(job.status, "failed");
+    assert_eq!(job.n_attempts, 1);
+    assert!(job
+        .last_error
+        .unwrap()
+        .contains("no handler for unknown jobs"));
+}
+
+#[tokio::test]
+async fn jobs_with_a_higher_priority_run_first() {
+    let app = spawn_app().await;
+    sqlx::query!(
+        r#"
+        INSERT INTO jobs (job_type, payload, priority, max_attempts, run_at)
+        VALUES
+            ('unknown_low', '{}', 0, 1, now() - interval '1 minute'),
+            ('unknown_high', '{}', 10, 1, now())
+        "#
+    )
+    .execute(&app.connection_pool)
+    .await
+    .unwrap();
+
+    let _ = try_execute_job(&app.job_context()).await;
+
+    let failed = sqlx::query!("SELECT job_type FROM jobs WHERE status = 'failed'")
+        .fetch_one(&app.connection_pool)
+        .await
+        .unwrap();
+    assert_eq!(failed.job_type, "unknown_high");
+}
@@ -0,0 +1,273 @@
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+
+// Every email is accepted, tests look at what was sent
+async fn accept_all_emails(app: &TestApp) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+}
+
+async fn sent_emails(app: &TestApp) -> Vec<serde_json::Value> {
+    app.email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .map(|r| serde_json::from_slice(&r.body).unwrap())
+        .collect()
+}
+
+async fn subscribe_and_confirm(app: &TestApp, email: &str, list: &str) {
+    let body = format!("name=bob&email={}&list={list}", urlencoding::encode(email));
+    app.post_subscription(body)
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+    let requests = app.email_server.received_requests().await.unwrap();
+    let links = app.get_confirmation_links(requests.last().unwrap());
+    reqwest::get(links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
+async fn publish(app: &TestApp, lists: &[&str]) {
+    let mut body = vec![
+        ("title", "Issue #1".to_string()),
+        ("text_content", "Text".to_string()),
+        ("html_content", "<p>HTML</p>".to_string()),
+        ("idempotency_key", uuid::Uuid::new_v4().to_string()),
+    ];
+    for list in lists {
+        body.push(("list", list.to_string()));
+    }
+    let response = app.post_newsletter(&body).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    app.dispatch_all_pending_emails().await;
+}
+
+// Recipients of the issues sent so far
+async fn issue_recipients(app: &TestApp) -> Vec<String> {
+    let mut recipients: Vec<String> = sent_emails(app)
+        .await
+        .into_iter()
+        .filter(|e| e["Subject"] == "Issue #1")
+        .map(|e| e["To"].as_str().unwrap().to_owned())
+        .collect();
+    recipients.sort();
+    recipients
+}
+
+#[tokio::test]
+async fn admins_can_create_lists() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = app.post_create_list("weekly", "Weekly digest").await;
+    assert_is_redirect_to(&response, "/admin/lists");
+
+    let html_page = app
+        .api_client
+        .get(format!("{}/admin/lists", app.address))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains("The list Weekly digest has been created"));
+    assert!(html_page.contains("<code>weekly</code>"));
+    assert!(html_page.contains("<code>newsletter</code>"));
+}
+
+#[tokio::test]
+async fn invalid_or_duplicate_list_identifiers_are_rejected() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    app.post_create_list("Not Valid", "Weekly").await;
+    app.post_create_list("newsletter", "Another newsletter")
+        .await;
+
+    let n_lists = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM lists"#)
+        .fetch_one(&app.connection_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_lists, 1);
+}
+
+#[tokio::test]
+async fn subscribing_to_an_unknown_list_is_rejected() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_subscription("name=bob&email=bob%40test.com&list=nope".into())
+        .await;
+
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn confirming_a_subscription_only_confirms_its_list() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    app.post_create_list("weekly", "Weekly digest").await;
+    accept_all_emails(&app).await;
+
+    subscribe_and_confirm(&app, "bob@test.com", "weekly").await;
+
+    let rows = sqlx::query!(
+        r#"
+        SELECT l.slug, s.status
+        FROM list_subscriptions s JOIN lists l ON l.id = s.list_id
+        "#
+    )
+    .fetch_all(&app.connection_pool)
+    .await
+    .unwrap();
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0].slug, "weekly");
+    assert_eq!(rows[0].status, "confirmed");
+}
+
+#[tokio::test]
+async fn confirmed_subscribers_confirm_each_new_list() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    app.post_create_list("weekly", "Weekly digest").await;
+    accept_all_emails(&app).await;
+    subscribe_and_confirm(&app, "bob@test.com", "newsletter").await;
+
+    app.post_subscription("name=bob&email=bob%40test.com&list=weekly".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    // A second confirmation email was sent, and the issue waits for it
+    assert_eq!(sent_emails(&app).await.len(), 2);
+    publish(&app, &["weekly"]).await;
+    assert!(issue_recipients(&app).await.is_empty());
+}
+
+#[tokio::test]
+async fn issues_are_only_delivered_to_the_lists_they_target() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    app.post_create_list("weekly", "Weekly digest").await;
+    app.post_create_list("monthly", "Monthly digest").await;
+    accept_all_emails(&app).await;
+    subscribe_and_confirm(&app, "weekly@test.com", "weekly").await;
+    subscribe_and_confirm(&app, "monthly@test.com", "monthly").await;
+    subscribe_and_confirm(&app, "both@test.com", "weekly").await;
+    subscribe_and_confirm(&app, "both@test.com", "newsletter").await;
+
+    publish(&app, &["weekly", "newsletter"]).await;
+
+    // Once each, even when on several of the lists
+    assert_eq!(
+        issue_recipients(&app).await,
+        vec!["both@test.com", "weekly@test.com"]
+    );
+}
+
+#[tokio::test]
+async fn publishing_to_an_unknown_list_is_rejected() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = app
+        .post_newsletter(&serde_json::json!({
+            "title": "Issue #1",
+            "text_content": "Text",
+            "html_content": "<p>HTML</p>",
+            "idempotency_key": uuid::Uuid::new_v4().to_string(),
+            "list": "nope"
+        }))
+        .await;
+
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    assert!(app
+        .get_publish_newsletter_html()
+        .await
+        .contains("There is no list nope"));
+    let n_issues = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM newsletter_issues"#)
+        .fetch_one(&app.connection_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_issues, 0);
+}
+
+#[tokio::test]
+async fn subscribers_can_leave_one_list_from_the_link_in_an_issue() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    app.post_create_list("weekly", "Weekly digest").await;
+    accept_all_emails(&app).await;
+    subscribe_and_confirm(&app, "bob@test.com", "weekly").await;
+    subscribe_and_confirm(&app, "bob@test.com", "newsletter").await;
+    publish(&app, &["weekly"]).await;
+    let issue = sent_emails(&app).await.pop().unwrap();
+    let text = issue["TextBody"].as_str().unwrap();
+    let link = text.rsplit_once("Unsubscribe: ").unwrap().1;
+    let mut link = reqwest::Url::parse(link).unwrap();
+    link.set_port(Some(app.port)).unwrap();
+
+    let html_page = reqwest::get(link.clone())
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains("Unsubscribe from Weekly digest"));
+    assert!(html_page.contains("Unsubscribe from Newsletter"));
+    let token = link
+        .query_pairs()
+        .find(|(k, _)| k == "token")
+        .unwrap()
+        .1
+        .into_owned();
+    let response = reqwest::Client::new()
+        .post(format!("{}/subscriptions/unsubscribe", app.address))
+        .form(&serde_json::json!({ "token": token, "list": "weekly" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("You will no longer receive Weekly digest."));
+
+    // Still on the other list
+    app.email_server.reset().await;
+    accept_all_emails(&app).await;
+    publish(&app, &["weekly"]).await;
+    assert!(issue_recipients(&app).await.is_empty());
+    publish(&app, &["newsletter"]).await;
+    assert_eq!(issue_recipients(&app).await, vec!["bob@test.com"]);
+}
+
+#[tokio::test]
+async fn an_invalid_unsubscribe_token_is_rejected() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(format!(
+        "{}/subscriptions/unsubscribe?token=forged",
+        app.address
+    ))
+    .await
+    .unwrap();
+
+    assert_eq!(response.status().as_u16(), 401);
+}
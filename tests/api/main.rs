@@ -1,8 +1,19 @@
 mod admin_dashboard;
+mod admin_emails;
+mod admin_subscribers;
 mod change_password;
 mod health_check;
 mod helpers;
+mod jobs;
+mod lists;
 mod login;
 mod newsletter;
+mod openapi;
+mod segments;
+mod subscriber_data;
+mod subscribers_import_export;
 mod subscriptions;
 mod subscriptions_confirm;
+mod suppressions;
+mod tracking;
+mod webhooks;
@@ -2,9 +2,17 @@ use crate::helpers::{assert_is_redirect_to, spawn_app, ConfirmationLinks, TestAp
 use fake::faker::internet::en::SafeEmail;
 use fake::faker::name::en::Name;
 use fake::Fake;
+use secrecy::Secret;
 use std::time::Duration;
 use wiremock::matchers::{any, method, path};
 use wiremock::{Mock, MockBuilder, ResponseTemplate};
+use zero2prod::configuration::IdempotencySettings;
+use zero2prod::domain::SubscriberEmail;
+use zero2prod::email_client::EmailClient;
+use zero2prod::jobs::{
+    clean_up_idempotency_keys_periodically, try_execute_job, worker_loop, ExecutionOutcome,
+    JobContext,
+};
 
 #[tokio::test]
 async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
@@ -42,8 +50,7 @@ async fn newsletters_are_delivered_to_confirmed_subscribers() {
     create_confirmed_subscriber(&app).await;
     app.test_user.login(&app).await;
 
-    Mock::given(path("/email"))
-        .and(method("POST"))
+    when_sending_an_email()
         .respond_with(ResponseTemplate::new(200))
         .expect(1)
         .mount(&app.email_server)
@@ -92,8 +99,7 @@ async fn newsletter_creation_is_idempotent() {
     create_confirmed_subscriber(&app).await;
     app.test_user.login(&app).await;
 
-    Mock::given(path("/email"))
-        .and(method("POST"))
+    when_sending_an_email()
         .respond_with(ResponseTemplate::new(200))
         .expect(1)
         .mount(&app.email_server)
@@ -128,14 +134,162 @@ async fn newsletter_creation_is_idempotent() {
     // Mock verifies on shutdown that only 1 http reqest is made
 }
 
+#[tokio::test]
+async fn an_issue_is_not_published_if_its_response_could_not_be_saved() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    // Saving the response fails after the handler is done with the issue
+    sqlx::query!(
+        r#"
+        CREATE FUNCTION fail_to_save_responses() RETURNS trigger AS $$
+        BEGIN
+            RAISE EXCEPTION 'The response could not be saved';
+        END;
+        $$ LANGUAGE plpgsql
+        "#
+    )
+    .execute(&app.connection_pool)
+    .await
+    .unwrap();
+    sqlx::query!(
+        r#"
+        CREATE TRIGGER fail_to_save_responses
+        BEFORE UPDATE OF response_status_code ON idempotency
+        FOR EACH ROW EXECUTE FUNCTION fail_to_save_responses()
+        "#
+    )
+    .execute(&app.connection_pool)
+    .await
+    .unwrap();
+    let form = newsletter_form();
+
+    let response = app.post_newsletter(&form).await;
+
+    assert_eq!(response.status().as_u16(), 500);
+    let n_issues = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM newsletter_issues"#)
+        .fetch_one(&app.connection_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_issues, 0);
+
+    // Sent again with the same key once responses can be saved, it is published once
+    sqlx::query!("DROP TRIGGER fail_to_save_responses ON idempotency")
+        .execute(&app.connection_pool)
+        .await
+        .unwrap();
+    let response = app.post_newsletter(&form).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    let response = app.post_newsletter(&form).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    app.dispatch_all_pending_emails().await;
+    let n_issues = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM newsletter_issues"#)
+        .fetch_one(&app.connection_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_issues, 1);
+}
+
+#[tokio::test]
+async fn reusing_an_idempotency_key_for_a_different_issue_is_rejected() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let form_body = newsletter_form();
+    let response = app.post_newsletter(&form_body).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    let mut other_issue = form_body.clone();
+    other_issue["title"] = "Another title".into();
+    let response = app.post_newsletter(&other_issue).await;
+
+    assert_eq!(response.status().as_u16(), 422);
+    let n_issues = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM newsletter_issues"#)
+        .fetch_one(&app.connection_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_issues, 1);
+}
+
+#[tokio::test]
+async fn replayed_submissions_do_not_leak_the_cookies_of_the_first_session() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let credentials = serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password
+    });
+    let response = app.post_login(&credentials).await;
+    let first_session = session_cookies(&response);
+    assert!(!first_session.is_empty());
+    let form_body = newsletter_form();
+    let response = app.post_newsletter(&form_body).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    // The same admin, from another browser
+    let other_browser = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .cookie_store(true)
+        .build()
+        .unwrap();
+    other_browser
+        .post(format!("{}/login", app.address))
+        .form(&credentials)
+        .send()
+        .await
+        .unwrap();
+    let response = other_browser
+        .post(format!("{}/admin/newsletters", app.address))
+        .form(&form_body)
+        .send()
+        .await
+        .unwrap();
+
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    assert_eq!(response.headers()["Idempotent-Replayed"], "true");
+    for cookie in session_cookies(&response) {
+        assert!(!first_session.contains(&cookie));
+    }
+    let saved = sqlx::query!(
+        r#"
+        SELECT
+            ARRAY(SELECT (h).name FROM unnest(response_headers) AS h) AS "header_names!",
+            response_body_gzipped
+        FROM idempotency
+        "#
+    )
+    .fetch_one(&app.connection_pool)
+    .await
+    .unwrap();
+    assert_eq!(saved.header_names, vec!["location".to_string()]);
+    assert!(saved.response_body_gzipped);
+}
+
+fn session_cookies(response: &reqwest::Response) -> Vec<String> {
+    response
+        .headers()
+        .get_all("Set-Cookie")
+        .iter()
+        .map(|value| value.to_str().unwrap().to_string())
+        .filter(|cookie| cookie.starts_with("id="))
+        .collect()
+}
+
 #[tokio::test]
 async fn concurrent_form_submission_is_handled_gracefully() {
     let app = spawn_app().await;
     create_confirmed_subscriber(&app).await;
     app.test_user.login(&app).await;
 
-    Mock::given(path("/email"))
-        .and(method("POST"))
+    when_sending_an_email()
         // Add artificial delay to ensure 2nd request comes in before first finishes
         .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(2)))
         .expect(1)
@@ -159,7 +313,497 @@ async fn concurrent_form_submission_is_handled_gracefully() {
         response1.text().await.unwrap(),
         response2.text().await.unwrap()
     );
+    let n_issues = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM newsletter_issues"#)
+        .fetch_one(&app.connection_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_issues, 1);
+    app.dispatch_all_pending_emails().await;
+}
+
+// Locks the key like a request still being processed, until the transaction ends
+async fn start_processing(
+    app: &TestApp,
+    idempotency_key: &str,
+) -> sqlx::Transaction<'static, sqlx::Postgres> {
+    let mut transaction = app.connection_pool.begin().await.unwrap();
+    sqlx::query!(
+        "INSERT INTO idempotency (scope_id, idempotency_key, created_at) VALUES ($1, $2, now())",
+        app.test_user.user_id,
+        idempotency_key
+    )
+    .execute(&mut transaction)
+    .await
+    .unwrap();
+    transaction
+}
+
+#[tokio::test]
+async fn a_submission_waits_for_a_concurrent_one_with_the_same_key_and_replays_its_response() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let form_body = newsletter_form();
+    let idempotency_key = form_body["idempotency_key"].as_str().unwrap();
+    let mut in_flight = start_processing(&app, idempotency_key).await;
+
+    let finish_in_flight = async {
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        sqlx::query!(
+            r#"
+            UPDATE idempotency
+            SET
+                response_status_code = 303,
+                response_headers = ARRAY[ROW('location', '/admin/newsletters'::bytea)::header_pair],
+                response_body = ''
+            WHERE scope_id = $1 AND idempotency_key = $2
+            "#,
+            app.test_user.user_id,
+            idempotency_key
+        )
+        .execute(&mut in_flight)
+        .await
+        .unwrap();
+        in_flight.commit().await.unwrap();
+    };
+    let (response, _) = tokio::join!(app.post_newsletter(&form_body), finish_in_flight);
+
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    let n_issues = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM newsletter_issues"#)
+        .fetch_one(&app.connection_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_issues, 0);
+}
+
+#[tokio::test]
+async fn a_submission_gives_up_waiting_for_a_concurrent_one_with_the_same_key() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let form_body = newsletter_form();
+    let in_flight = start_processing(&app, form_body["idempotency_key"].as_str().unwrap()).await;
+
+    let response = app.post_newsletter(&form_body).await;
+
+    assert_eq!(response.status().as_u16(), 409);
+    assert_eq!(response.headers().get("Retry-After").unwrap(), "1");
+    in_flight.rollback().await.unwrap();
+    // Once the other request is gone, the form can be submitted again
+    let response = app.post_newsletter(&form_body).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+}
+
+#[tokio::test]
+async fn an_expired_idempotency_key_is_treated_as_new() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let form_body = newsletter_form();
+    let response = app.post_newsletter(&form_body).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    // Keys are kept for a day
+    sqlx::query!("UPDATE idempotency SET created_at = now() - interval '25 hours'")
+        .execute(&app.connection_pool)
+        .await
+        .unwrap();
+    let response = app.post_newsletter(&form_body).await;
+
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    let n_issues = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM newsletter_issues"#)
+        .fetch_one(&app.connection_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_issues, 2);
+}
+
+#[tokio::test]
+async fn the_worker_deletes_expired_idempotency_keys() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let response = app.post_newsletter(&newsletter_form()).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    sqlx::query!("UPDATE idempotency SET created_at = now() - interval '25 hours'")
+        .execute(&app.connection_pool)
+        .await
+        .unwrap();
+    let response = app.post_newsletter(&newsletter_form()).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    let settings = IdempotencySettings {
+        key_ttl_hours: 24,
+        clean_up_interval_seconds: 3600,
+        in_flight_wait_milliseconds: 5000.try_into().unwrap(),
+        max_saved_body_bytes: 262144,
+        saved_headers: vec![],
+    };
+    let clean_up = tokio::spawn(clean_up_idempotency_keys_periodically(
+        app.connection_pool.clone(),
+        settings,
+    ));
+    let worker = tokio::spawn(worker_loop(app.job_context()));
+
+    // Only the recent key is left
+    let cleaned_up = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            let n_keys = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM idempotency"#)
+                .fetch_one(&app.connection_pool)
+                .await
+                .unwrap()
+                .count;
+            if n_keys == 1 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await;
+    clean_up.abort();
+    worker.abort();
+    assert!(cleaned_up.is_ok());
+}
+
+#[tokio::test]
+async fn issues_are_personalized_for_each_recipient() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let response = app
+        .post_import_subscribers(
+            "email,name,country\nann@test.com,Ann,DE\nbob@test.com,Bob,\n",
+            "confirmed",
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = serde_json::json!({
+        "title": "News for {{ name }}",
+        "text_content": "From {{ attributes.country | default: \"somewhere\" }}. Leave: {{ unsubscribe_url }}",
+        "html_content": "<p>Hi {{ name }} ({{ email }})</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+    let response = app.post_newsletter(&newsletter_request_body).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
     app.dispatch_all_pending_emails().await;
+
+    let mut emails: Vec<serde_json::Value> = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .map(|r| serde_json::from_slice(&r.body).unwrap())
+        .collect();
+    emails.sort_by_key(|e| e["To"].as_str().unwrap().to_owned());
+    assert_eq!(emails[0]["Subject"], "News for Ann");
+    assert!(emails[0]["HtmlBody"]
+        .as_str()
+        .unwrap()
+        .starts_with("<p>Hi Ann (ann@test.com)</p>"));
+    assert!(emails[0]["TextBody"]
+        .as_str()
+        .unwrap()
+        .starts_with("From DE. Leave: http"));
+    assert_eq!(emails[1]["Subject"], "News for Bob");
+    assert!(emails[1]["TextBody"]
+        .as_str()
+        .unwrap()
+        .starts_with("From somewhere."));
+    // The text body links to the unsubscribe page itself, the HTML one gets a footer
+    for email in &emails {
+        assert_eq!(
+            email["TextBody"]
+                .as_str()
+                .unwrap()
+                .matches("/subscriptions/unsubscribe?token=")
+                .count(),
+            1
+        );
+        assert!(email["HtmlBody"]
+            .as_str()
+            .unwrap()
+            .contains("/subscriptions/unsubscribe?token="));
+    }
+}
+
+#[tokio::test]
+async fn issues_with_unknown_placeholders_are_rejected() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Hi {{ first_name }}",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+    let response = app.post_newsletter(&newsletter_request_body).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    let html_page = app.get_publish_newsletter_html().await;
+    assert!(html_page.contains("Unknown placeholder `first_name`"));
+    app.dispatch_all_pending_emails().await;
+    let n_issues = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM newsletter_issues"#)
+        .fetch_one(&app.connection_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_issues, 0);
+}
+
+#[tokio::test]
+async fn deliveries_are_logged_with_the_provider_message_id() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let subscriber_id = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_one(&app.connection_pool)
+        .await
+        .unwrap()
+        .id;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "MessageID": "b7bc2f4a-e38e-4336-af7d-e6c392c2f817",
+            "ErrorCode": 0,
+            "Message": "OK"
+        })))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_newsletter(&newsletter_form()).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    app.dispatch_all_pending_emails().await;
+
+    let delivery = sqlx::query!(
+        "SELECT newsletter_issue_id, subscriber_id, outcome, message_id FROM deliveries"
+    )
+    .fetch_one(&app.connection_pool)
+    .await
+    .unwrap();
+    assert_eq!(delivery.subscriber_id, subscriber_id);
+    assert_eq!(delivery.outcome, "delivered");
+    assert_eq!(
+        delivery.message_id.as_deref(),
+        Some("b7bc2f4a-e38e-4336-af7d-e6c392c2f817")
+    );
+    let request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+    assert_eq!(
+        body["Metadata"]["idempotency_key"],
+        format!("{}:{subscriber_id}", delivery.newsletter_issue_id)
+    );
+}
+
+#[tokio::test]
+async fn an_issue_is_not_sent_twice_after_the_worker_crashed_mid_delivery() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let response = app.post_newsletter(&newsletter_form()).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    // The worker logged the delivery and sent the email, then died before deleting the task
+    sqlx::query!(
+        r#"
+        INSERT INTO deliveries (newsletter_issue_id, subscriber_id, subscriber_email, outcome)
+        SELECT (j.payload->>'newsletter_issue_id')::uuid, s.id, s.email, 'sending'
+        FROM jobs j
+        JOIN subscriptions s ON s.email = j.payload->>'subscriber_email'
+        "#
+    )
+    .execute(&app.connection_pool)
+    .await
+    .unwrap();
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    app.dispatch_all_pending_emails().await;
+
+    let n_queued = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM jobs"#)
+        .fetch_one(&app.connection_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_queued, 0);
+}
+
+#[tokio::test]
+async fn deliveries_that_failed_temporarily_are_retried() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let response = app.post_newsletter(&newsletter_form()).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    let outage = when_sending_an_email()
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    let outcome = try_execute_job(&app.job_context()).await;
+
+    assert!(outcome.is_err());
+    drop(outage);
+    // Skip the backoff
+    sqlx::query!("UPDATE jobs SET run_at = now()")
+        .execute(&app.connection_pool)
+        .await
+        .unwrap();
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+    let outcome = sqlx::query!("SELECT outcome FROM deliveries")
+        .fetch_one(&app.connection_pool)
+        .await
+        .unwrap()
+        .outcome;
+    assert_eq!(outcome, "delivered");
+}
+
+#[tokio::test]
+async fn deliveries_that_timed_out_are_not_sent_again() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let response = app.post_newsletter(&newsletter_form()).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    // Postmark may accept the email and answer too late
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(5)))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let context = JobContext {
+        email_client: EmailClient::new(
+            app.email_server.uri(),
+            SubscriberEmail::parse("sender@test.com".into()).unwrap(),
+            Secret::new("token".into()),
+            Duration::from_millis(200),
+        ),
+        ..app.job_context()
+    };
+
+    while !matches!(
+        try_execute_job(&context).await,
+        Ok(ExecutionOutcome::EmptyQueue)
+    ) {}
+
+    let outcome = sqlx::query!("SELECT outcome FROM deliveries")
+        .fetch_one(&app.connection_pool)
+        .await
+        .unwrap()
+        .outcome;
+    assert_eq!(outcome, "sending");
+    let n_queued = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM jobs"#)
+        .fetch_one(&app.connection_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_queued, 0);
+}
+
+#[tokio::test]
+async fn deliveries_refused_for_the_recipient_are_not_retried() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(422).set_body_json(serde_json::json!({
+            "ErrorCode": 406,
+            "Message": "You tried to send to a recipient that has been marked as inactive."
+        })))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_newsletter(&newsletter_form()).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    app.dispatch_all_pending_emails().await;
+
+    let outcome = sqlx::query!("SELECT outcome FROM deliveries")
+        .fetch_one(&app.connection_pool)
+        .await
+        .unwrap()
+        .outcome;
+    assert_eq!(outcome, "failed");
+}
+
+#[tokio::test]
+async fn the_worker_is_woken_up_as_soon_as_an_issue_is_published() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let worker = tokio::spawn(worker_loop(app.job_context()));
+    // Let the worker find the queue empty and start waiting
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    let response = app.post_newsletter(&newsletter_form()).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    // Well before the worker would poll again
+    let delivered = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            let n_delivered = sqlx::query!(
+                r#"SELECT COUNT(*) AS "count!" FROM deliveries WHERE outcome = 'delivered'"#
+            )
+            .fetch_one(&app.connection_pool)
+            .await
+            .unwrap()
+            .count;
+            if n_delivered == 1 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await;
+    worker.abort();
+    assert!(delivered.is_ok());
+}
+
+fn newsletter_form() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    })
 }
 
 async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
@@ -170,8 +814,7 @@ async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
         "email": email
     }))
     .unwrap();
-    let _mock_guard = Mock::given(path("/email"))
-        .and(method("POST"))
+    let _mock_guard = when_sending_an_email()
         .respond_with(ResponseTemplate::new(200))
         .named("Create unconfirmed subscriber")
         .expect(1)
@@ -181,6 +824,7 @@ async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
         .await
         .error_for_status()
         .unwrap();
+    app.dispatch_all_pending_emails().await;
     let email_request = &app
         .email_server
         .received_requests()
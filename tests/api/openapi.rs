@@ -0,0 +1,171 @@
+use crate::helpers::spawn_app;
+
+#[tokio::test]
+async fn openapi_document_is_served() {
+    let app = spawn_app().await;
+
+    let response = app.get_openapi_json().await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let spec: serde_json::Value = response.json().await.unwrap();
+    assert!(spec["openapi"].as_str().unwrap().starts_with("3."));
+    assert!(spec["paths"]["/subscriptions"]["post"].is_object());
+    assert!(spec["paths"]["/subscriptions/confirm"]["get"].is_object());
+    assert!(spec["paths"]["/health_check"]["get"].is_object());
+}
+
+#[tokio::test]
+async fn subscription_form_fields_are_documented() {
+    let app = spawn_app().await;
+
+    let spec: serde_json::Value = app.get_openapi_json().await.json().await.unwrap();
+
+    let schema = &spec["paths"]["/subscriptions"]["post"]["requestBody"]["content"]
+        ["application/x-www-form-urlencoded"]["schema"];
+    let required: Vec<&str> = schema["required"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|v| v.as_str().unwrap())
+        .collect();
+    assert!(required.contains(&"email"));
+    assert!(required.contains(&"name"));
+    let params = spec["paths"]["/subscriptions/confirm"]["get"]["parameters"]
+        .as_array()
+        .unwrap();
+    assert!(params
+        .iter()
+        .any(|p| p["name"] == "subscription_token" && p["in"] == "query"));
+}
+
+#[tokio::test]
+async fn every_route_registered_in_startup_is_documented() {
+    let app = spawn_app().await;
+    let spec: serde_json::Value = app.get_openapi_json().await.json().await.unwrap();
+
+    let routes = registered_routes(include_str!("../../src/startup.rs"));
+    assert!(!routes.is_empty());
+    // Registered through `web::resource(...)` rather than `.route(...)`
+    for method in ["get", "post"] {
+        assert!(
+            routes.contains(&(method.to_string(), "/admin/subscribers/import".to_string())),
+            "{} /admin/subscribers/import was not found in startup::run",
+            method.to_uppercase()
+        );
+    }
+
+    for (method, path) in routes {
+        assert!(
+            spec["paths"][&path][&method].is_object(),
+            "{} {} is registered in startup::run but missing from the OpenAPI document",
+            method.to_uppercase(),
+            path
+        );
+    }
+}
+
+// Extract (method, full path) pairs from the routes registered in `startup::run`.
+// It understands these forms, and panics on any other way of registering a route:
+// - `.route("/path", web::<method>()...)`
+// - `.service(web::scope("/prefix")...)`, prefixing the routes nested inside with its path
+// - `.service(web::resource("/path")...route(web::<method>()...))`, whose routes take its path
+// - `.service(crate::routes::swagger_ui())`, which serves the document and is not documented
+fn registered_routes(source: &str) -> Vec<(String, String)> {
+    let start = source
+        .find("pub async fn run(")
+        .expect("startup::run was not found");
+    let mut routes = Vec::new();
+    // (scope or resource path, paren depth it was opened at)
+    let mut scopes: Vec<(String, usize)> = Vec::new();
+    let mut depth = 0;
+    let mut rest = &source[start..];
+    while let Some(c) = rest.chars().next() {
+        for unsupported in [".configure(", ".default_service(", ".external_resource("] {
+            assert!(
+                !rest.starts_with(unsupported),
+                "registered_routes does not understand `{unsupported}`, teach it before using it"
+            );
+        }
+        if let Some(after) = rest.strip_prefix(".service(") {
+            let after = after.trim_start();
+            assert!(
+                [
+                    "web::scope(",
+                    "web::resource(",
+                    "crate::routes::swagger_ui()"
+                ]
+                .iter()
+                .any(|form| after.starts_with(form)),
+                "registered_routes does not understand `.service({}`, teach it before using it",
+                after.lines().next().unwrap_or_default()
+            );
+            depth += 1;
+            rest = after;
+            continue;
+        }
+        if let Some(after) = rest
+            .strip_prefix("web::scope(")
+            .or_else(|| rest.strip_prefix("web::resource("))
+        {
+            let (path, after) = string_literal(after);
+            scopes.push((path.to_string(), depth));
+            depth += 1;
+            rest = after;
+            continue;
+        }
+        if let Some(after) = rest.strip_prefix(".route(") {
+            let after = after.trim_start();
+            let prefix: String = scopes.iter().map(|(p, _)| p.as_str()).collect();
+            // Routes of a resource have no path of their own
+            let (path, after) = if after.starts_with('"') {
+                let (path, after) = string_literal(after);
+                let after = after
+                    .trim_start()
+                    .strip_prefix(',')
+                    .expect("The path of a route must be followed by its handler")
+                    .trim_start();
+                (path, after)
+            } else {
+                ("", after)
+            };
+            let after = after.strip_prefix("web::").unwrap_or_else(|| {
+                panic!(
+                    "registered_routes does not understand the route `{}`, teach it before using it",
+                    after.lines().next().unwrap_or_default()
+                )
+            });
+            let method_end = after.find('(').expect("The method must be called");
+            routes.push((after[..method_end].to_string(), format!("{prefix}{path}")));
+            depth += 1;
+            rest = &after[method_end..];
+            continue;
+        }
+        match c {
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                while scopes.last().is_some_and(|(_, d)| *d > depth) {
+                    scopes.pop();
+                }
+            }
+            _ => {}
+        }
+        rest = &rest[c.len_utf8()..];
+    }
+    assert_eq!(
+        routes.len(),
+        source[start..].matches(".route(").count(),
+        "Some `.route(` calls in startup::run were not understood"
+    );
+    routes
+}
+
+// Split a leading `"..."` literal, possibly after whitespace, off `source`
+fn string_literal(source: &str) -> (&str, &str) {
+    let after = source
+        .trim_start()
+        .strip_prefix('"')
+        .unwrap_or_else(|| panic!("Expected a path literal, found `{source}`"));
+    let end = after.find('"').expect("Unterminated path literal");
+    (&after[..end], &after[end + 1..])
+}
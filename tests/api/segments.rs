@@ -0,0 +1,463 @@
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+
+// Imports confirmed subscribers on the default list
+async fn import_confirmed(app: &TestApp, csv: &str) {
+    let response = app.post_import_subscribers(csv, "confirmed").await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+async fn subscriber_id(app: &TestApp, email: &str) -> uuid::Uuid {
+    sqlx::query!("SELECT id FROM subscriptions WHERE email = $1", email)
+        .fetch_one(&app.connection_pool)
+        .await
+        .unwrap()
+        .id
+}
+
+fn issue_form(segment: &[(&'static str, &str)]) -> Vec<(&'static str, String)> {
+    let mut body = vec![
+        ("title", "Issue #1".to_string()),
+        ("text_content", "Text".to_string()),
+        ("html_content", "<p>HTML</p>".to_string()),
+        ("idempotency_key", uuid::Uuid::new_v4().to_string()),
+    ];
+    body.extend(segment.iter().map(|(k, v)| (*k, v.to_string())));
+    body
+}
+
+// Publishes an issue to the segment and returns who it was sent to
+async fn publish_to(app: &TestApp, segment: &[(&'static str, &str)]) -> Vec<String> {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount_as_scoped(&app.email_server)
+        .await;
+    let n_sent_before = app.email_server.received_requests().await.unwrap().len();
+    let response = app.post_newsletter(&issue_form(segment)).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    app.dispatch_all_pending_emails().await;
+    let mut recipients: Vec<String> = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .skip(n_sent_before)
+        .map(|r| serde_json::from_slice::<serde_json::Value>(&r.body).unwrap())
+        .map(|e| e["To"].as_str().unwrap().to_owned())
+        .collect();
+    recipients.sort();
+    recipients
+}
+
+async fn post_recipient_count(
+    app: &TestApp,
+    segment: &[(&'static str, &str)],
+) -> reqwest::Response {
+    app.api_client
+        .post(format!("{}/admin/newsletters/recipients", app.address))
+        .form(&issue_form(segment))
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn create_api_token(app: &TestApp) -> String {
+    let html_page = app
+        .api_client
+        .post(format!("{}/admin/api_tokens", app.address))
+        .form(&[("name", "CRM sync")])
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    let start = html_page.find("<code>nl_").unwrap() + "<code>".len();
+    let end = start + html_page[start..].find("</code>").unwrap();
+    html_page[start..end].to_string()
+}
+
+#[tokio::test]
+async fn imported_tags_and_attributes_are_stored() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    import_confirmed(
+        &app,
+        "email,name,tags,country,plan\n\
+        ann@test.com,Ann,VIP;beta,DE,\n",
+    )
+    .await;
+
+    let row = sqlx::query!("SELECT tags, attributes FROM subscriptions")
+        .fetch_one(&app.connection_pool)
+        .await
+        .unwrap();
+    assert_eq!(row.tags, vec!["beta", "vip"]);
+    assert_eq!(row.attributes, serde_json::json!({ "country": "DE" }));
+}
+
+#[tokio::test]
+async fn admins_can_edit_tags_and_attributes() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    import_confirmed(&app, "email,name\nann@test.com,Ann\n").await;
+    let id = subscriber_id(&app, "ann@test.com").await;
+
+    let response = app
+        .api_client
+        .post(format!("{}/admin/subscribers/{id}/attributes", app.address))
+        .form(&[
+            ("tags", "news, VIP"),
+            ("attributes", r#"{"country": "FR"}"#),
+        ])
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, &format!("/admin/subscribers/{id}"));
+
+    let html_page = app.get_subscriber_details_html(id).await;
+    assert!(html_page.contains("Tags and attributes updated"));
+    assert!(html_page.contains("news, vip"));
+    let row = sqlx::query!("SELECT tags, attributes FROM subscriptions")
+        .fetch_one(&app.connection_pool)
+        .await
+        .unwrap();
+    assert_eq!(row.tags, vec!["news", "vip"]);
+    assert_eq!(row.attributes, serde_json::json!({ "country": "FR" }));
+
+    // Attributes must be a JSON object
+    app.api_client
+        .post(format!("{}/admin/subscribers/{id}/attributes", app.address))
+        .form(&[("tags", ""), ("attributes", "[1, 2]")])
+        .send()
+        .await
+        .unwrap();
+    let html_page = app.get_subscriber_details_html(id).await;
+    assert!(html_page.contains("Attributes must be a JSON object"));
+    let row = sqlx::query!("SELECT tags FROM subscriptions")
+        .fetch_one(&app.connection_pool)
+        .await
+        .unwrap();
+    assert_eq!(row.tags, vec!["news", "vip"]);
+}
+
+#[tokio::test]
+async fn issues_are_only_sent_to_the_matching_segment() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    import_confirmed(
+        &app,
+        "email,name,tags,country\n\
+        ann@test.com,Ann,vip,DE\n\
+        bob@test.com,Bob,beta,FR\n\
+        cat@test.com,Cat,,DE\n",
+    )
+    .await;
+    sqlx::query!(
+        "UPDATE subscriptions SET subscribed_at = '2025-06-01' WHERE email = 'ann@test.com'"
+    )
+    .execute(&app.connection_pool)
+    .await
+    .unwrap();
+
+    assert_eq!(
+        publish_to(&app, &[("tags", "vip, beta")]).await,
+        vec!["ann@test.com", "bob@test.com"]
+    );
+    assert_eq!(
+        publish_to(
+            &app,
+            &[("attribute_name", "country"), ("attribute_value", "DE")]
+        )
+        .await,
+        vec!["ann@test.com", "cat@test.com"]
+    );
+    assert_eq!(
+        publish_to(&app, &[("subscribed_before", "2026-01-01")]).await,
+        vec!["ann@test.com"]
+    );
+    assert_eq!(
+        publish_to(&app, &[("tags", "vip"), ("subscribed_after", "2026-01-01")]).await,
+        Vec::<String>::new()
+    );
+
+    let segment = sqlx::query!(
+        r#"SELECT segment AS "segment!" FROM newsletter_issues WHERE segment ? 'tags' ORDER BY published_at LIMIT 1"#
+    )
+    .fetch_one(&app.connection_pool)
+    .await
+    .unwrap()
+    .segment;
+    assert_eq!(segment["tags"], serde_json::json!(["beta", "vip"]));
+}
+
+#[tokio::test]
+async fn the_recipient_count_is_previewed_without_publishing() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    import_confirmed(
+        &app,
+        "email,name,tags\n\
+        ann@test.com,Ann,vip\n\
+        bob@test.com,Bob,\n",
+    )
+    .await;
+
+    let response = post_recipient_count(&app, &[("tags", "vip")]).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("This issue would go to 1 subscriber(s)"));
+    // The form keeps what was typed
+    assert!(html_page.contains(r#"value="Issue #1""#));
+    assert!(html_page.contains(r#"name="tags" value="vip""#));
+    let n_issues = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM newsletter_issues"#)
+        .fetch_one(&app.connection_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_issues, 0);
+}
+
+#[tokio::test]
+async fn an_invalid_segment_is_rejected() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = post_recipient_count(&app, &[("subscribed_before", "yesterday")]).await;
+    assert_eq!(response.status().as_u16(), 400);
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("yesterday is not a date"));
+
+    let response = app
+        .post_newsletter(&issue_form(&[("attribute_value", "DE")]))
+        .await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    let n_issues = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM newsletter_issues"#)
+        .fetch_one(&app.connection_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_issues, 0);
+}
+
+#[tokio::test]
+async fn the_api_requires_a_valid_token() {
+    let app = spawn_app().await;
+    let url = format!("{}/api/subscribers/{}", app.address, uuid::Uuid::new_v4());
+
+    let response = app.api_client.get(&url).send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+
+    let response = app
+        .api_client
+        .get(&url)
+        .bearer_auth("nl_not-a-token")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn api_clients_can_update_tags_and_attributes() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    import_confirmed(
+        &app,
+        "email,name,tags,country,plan\nann@test.com,Ann,vip,DE,free\n",
+    )
+    .await;
+    let id = subscriber_id(&app, "ann@test.com").await;
+    let token = create_api_token(&app).await;
+    let url = format!("{}/api/subscribers/{id}", app.address);
+
+    let response = app
+        .api_client
+        .patch(&url)
+        .bearer_auth(&token)
+        .json(&serde_json::json!({
+            "tags": ["beta"],
+            "attributes": { "plan": null, "age": 42 }
+        }))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 200);
+    let subscriber: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(subscriber["email"], "ann@test.com");
+    assert_eq!(subscriber["tags"], serde_json::json!(["beta"]));
+    assert_eq!(
+        subscriber["attributes"],
+        serde_json::json!({ "country": "DE", "age": 42 })
+    );
+
+    // Invalid tags are rejected
+    let response = app
+        .api_client
+        .patch(&url)
+        .bearer_auth(&token)
+        .json(&serde_json::json!({ "tags": ["not a tag!"] }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 400);
+
+    // A revoked token no longer works
+    let token_id = sqlx::query!("SELECT id FROM api_tokens")
+        .fetch_one(&app.connection_pool)
+        .await
+        .unwrap()
+        .id;
+    let response = app
+        .api_client
+        .post(format!(
+            "{}/admin/api_tokens/{token_id}/revoke",
+            app.address
+        ))
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/admin/api_tokens");
+    let response = app
+        .api_client
+        .get(&url)
+        .bearer_auth(&token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn api_requests_sent_again_with_the_same_idempotency_key_are_replayed() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    import_confirmed(&app, "email,name\nann@test.com,Ann\n").await;
+    let id = subscriber_id(&app, "ann@test.com").await;
+    let token = create_api_token(&app).await;
+    let other_token = create_api_token(&app).await;
+    let url = format!("{}/api/subscribers/{id}", app.address);
+    let patch = |token: &str, tags: &[&str]| {
+        app.api_client
+            .patch(&url)
+            .bearer_auth(token)
+            .header("Idempotency-Key", "update-ann")
+            .json(&serde_json::json!({ "tags": tags }))
+            .send()
+    };
+    let response = patch(&token, &["beta"]).await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    sqlx::query!("UPDATE subscriptions SET tags = '{changed}'")
+        .execute(&app.connection_pool)
+        .await
+        .unwrap();
+
+    // The first response is replayed, the update does not run again
+    let response = patch(&token, &["beta"]).await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.headers()["Idempotent-Replayed"], "true");
+    let subscriber: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(subscriber["tags"], serde_json::json!(["beta"]));
+    let tags = sqlx::query!("SELECT tags FROM subscriptions")
+        .fetch_one(&app.connection_pool)
+        .await
+        .unwrap()
+        .tags;
+    assert_eq!(tags, vec!["changed".to_string()]);
+
+    // The key can't be used for another update
+    let response = patch(&token, &["gamma"]).await.unwrap();
+    assert_eq!(response.status().as_u16(), 422);
+
+    // Other tokens have keys of their own
+    let response = patch(&other_token, &["gamma"]).await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    let subscriber: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(subscriber["tags"], serde_json::json!(["gamma"]));
+}
+
+#[tokio::test]
+async fn responses_too_large_to_be_saved_are_replayed_without_their_body() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    import_confirmed(&app, "email,name\nann@test.com,Ann\n").await;
+    let id = subscriber_id(&app, "ann@test.com").await;
+    let token = create_api_token(&app).await;
+    // Over the 256 KiB saved at most
+    sqlx::query!(
+        "UPDATE subscriptions SET attributes = jsonb_build_object('notes', repeat('x', 300000))"
+    )
+    .execute(&app.connection_pool)
+    .await
+    .unwrap();
+    let url = format!("{}/api/subscribers/{id}", app.address);
+    let patch = || {
+        app.api_client
+            .patch(&url)
+            .bearer_auth(&token)
+            .header("Idempotency-Key", "update-ann")
+            .json(&serde_json::json!({ "tags": ["beta"] }))
+            .send()
+    };
+    let response = patch().await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.bytes().await.unwrap().len() > 300000);
+    sqlx::query!("UPDATE subscriptions SET tags = '{changed}'")
+        .execute(&app.connection_pool)
+        .await
+        .unwrap();
+
+    // The update does not run again
+    let response = patch().await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.headers()["Idempotent-Replayed"], "true");
+    assert!(response.bytes().await.unwrap().is_empty());
+    let tags = sqlx::query!("SELECT tags FROM subscriptions")
+        .fetch_one(&app.connection_pool)
+        .await
+        .unwrap()
+        .tags;
+    assert_eq!(tags, vec!["changed".to_string()]);
+}
+
+#[tokio::test]
+async fn deleting_a_subscriber_erases_the_api_responses_saved_for_them() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    import_confirmed(&app, "email,name\nann@test.com,Ann\nbob@test.com,Bob\n").await;
+    let ann = subscriber_id(&app, "ann@test.com").await;
+    let bob = subscriber_id(&app, "bob@test.com").await;
+    let token = create_api_token(&app).await;
+    for (id, key) in [(ann, "update-ann"), (bob, "update-bob")] {
+        let response = app
+            .api_client
+            .patch(format!("{}/api/subscribers/{id}", app.address))
+            .bearer_auth(&token)
+            .header("Idempotency-Key", key)
+            .json(&serde_json::json!({ "tags": ["beta"] }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status().as_u16(), 200);
+    }
+
+    let response = app.post_subscriber_action(ann, "delete").await;
+
+    assert_is_redirect_to(&response, "/admin/subscribers");
+    let keys = sqlx::query!("SELECT idempotency_key FROM idempotency")
+        .fetch_all(&app.connection_pool)
+        .await
+        .unwrap();
+    let keys: Vec<_> = keys.into_iter().map(|k| k.idempotency_key).collect();
+    assert_eq!(keys, ["update-bob"]);
+}
@@ -0,0 +1,235 @@
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{spawn_app, TestApp};
+
+// Subscribes and confirms bob@test.com, then delivers one newsletter issue to him
+async fn create_subscriber_with_a_delivery(app: &TestApp) {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount_as_scoped(&app.email_server)
+        .await;
+    let body = serde_urlencoded::to_string([("name", "bob"), ("email", "bob@test.com")]).unwrap();
+    app.post_subscription(body)
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+    let email_request = &app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let confirmation_links = app.get_confirmation_links(email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    app.test_user.login(app).await;
+    app.post_newsletter(&serde_json::json!({
+        "title": "Issue #1",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    }))
+    .await;
+    app.dispatch_all_pending_emails().await;
+}
+
+// Requests the data of bob@test.com and returns the (download, erase) links from the email
+async fn request_data_links(app: &TestApp) -> (reqwest::Url, reqwest::Url) {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    let response = app.post_data_request("bob@test.com").await;
+    assert_eq!(response.status().as_u16(), 200);
+    app.dispatch_all_pending_emails().await;
+    let email_request = &app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    app.get_data_request_links(email_request)
+}
+
+#[tokio::test]
+async fn a_data_request_for_an_unknown_email_does_not_send_anything() {
+    let app = spawn_app().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_data_request("nobody@test.com").await;
+
+    // Same response as for a subscribed address
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn a_data_request_for_a_suppressed_address_does_not_send_anything() {
+    let app = spawn_app().await;
+    create_subscriber_with_a_delivery(&app).await;
+    sqlx::query!(
+        "INSERT INTO suppressions (id, entry, reason) VALUES ($1, 'bob@test.com', 'manual')",
+        uuid::Uuid::new_v4()
+    )
+    .execute(&app.connection_pool)
+    .await
+    .unwrap();
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_data_request("bob@test.com").await;
+    app.dispatch_all_pending_emails().await;
+
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn a_data_request_gets_the_same_response_when_the_email_provider_is_down() {
+    let app = spawn_app().await;
+    create_subscriber_with_a_delivery(&app).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(500))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_data_request("bob@test.com").await;
+
+    // The email is only sent by the worker
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn a_data_request_with_an_invalid_email_is_rejected() {
+    let app = spawn_app().await;
+
+    let response = app.post_data_request("not-an-email").await;
+
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn the_emailed_link_downloads_all_the_subscriber_data() {
+    let app = spawn_app().await;
+    create_subscriber_with_a_delivery(&app).await;
+    let (download_link, _) = request_data_links(&app).await;
+
+    let response = reqwest::get(download_link).await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.headers()["Content-Disposition"]
+        .to_str()
+        .unwrap()
+        .starts_with("attachment"));
+    let data: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(data["subscription"]["email"], "bob@test.com");
+    assert_eq!(data["subscription"]["status"], "confirmed");
+    assert_eq!(data["confirmation_tokens"].as_array().unwrap().len(), 1);
+    let events: Vec<_> = data["history"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|e| e["event"].as_str().unwrap())
+        .collect();
+    assert_eq!(events, vec!["subscribed", "confirmed"]);
+    assert_eq!(data["delivered_issues"][0]["title"], "Issue #1");
+
+    let n_exports = sqlx::query!(
+        r#"SELECT COUNT(*) as "count!" FROM audit_log WHERE action = 'subscriber_data_exported'"#
+    )
+    .fetch_one(&app.connection_pool)
+    .await
+    .unwrap()
+    .count;
+    assert_eq!(n_exports, 1);
+}
+
+#[tokio::test]
+async fn a_tampered_data_link_is_rejected() {
+    let app = spawn_app().await;
+    create_subscriber_with_a_delivery(&app).await;
+    let (mut download_link, _) = request_data_links(&app).await;
+    let token = download_link
+        .query_pairs()
+        .find(|(k, _)| k == "token")
+        .unwrap()
+        .1
+        .into_owned();
+    download_link.set_query(Some(&format!("token={token}x")));
+
+    let response = reqwest::get(download_link).await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn erasing_removes_the_subscriber_from_every_table_and_is_audited() {
+    let app = spawn_app().await;
+    create_subscriber_with_a_delivery(&app).await;
+    let (_, erase_link) = request_data_links(&app).await;
+
+    // The link leads to a confirmation form
+    let html_page = reqwest::get(erase_link.clone())
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains(r#"<form action="/subscriptions/erase" method="post">"#));
+    let token = erase_link
+        .query_pairs()
+        .find(|(k, _)| k == "token")
+        .unwrap()
+        .1
+        .into_owned();
+    let response = app.post_erase_subscriber_data(&token).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let remaining = sqlx::query!(
+        r#"
+        SELECT
+            (SELECT COUNT(*) FROM subscriptions) as "subscriptions!",
+            (SELECT COUNT(*) FROM subscription_tokens) as "tokens!",
+            (SELECT COUNT(*) FROM subscription_events) as "events!",
+            (SELECT COUNT(*) FROM deliveries) as "deliveries!",
+            (SELECT COUNT(*) FROM jobs) as "queued!"
+        "#
+    )
+    .fetch_one(&app.connection_pool)
+    .await
+    .unwrap();
+    assert_eq!(remaining.subscriptions, 0);
+    assert_eq!(remaining.tokens, 0);
+    assert_eq!(remaining.events, 0);
+    assert_eq!(remaining.deliveries, 0);
+    assert_eq!(remaining.queued, 0);
+
+    let audit =
+        sqlx::query!("SELECT actor, details FROM audit_log WHERE action = 'subscriber_erased'")
+            .fetch_one(&app.connection_pool)
+            .await
+            .unwrap();
+    assert!(audit.actor.starts_with("subscriber:"));
+    assert!(!audit.details.to_string().contains("bob@test.com"));
+
+    // Erasing again is harmless
+    let response = app.post_erase_subscriber_data(&token).await;
+    assert_eq!(response.status().as_u16(), 200);
+}
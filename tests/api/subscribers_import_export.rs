@@ -0,0 +1,249 @@
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{assert_is_redirect_to, spawn_app};
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_import_subscribers() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_import_subscribers("email,name\nbob@test.com,bob", "confirmed")
+        .await;
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn import_reports_row_errors_and_skips_duplicates() {
+    let app = spawn_app().await;
+    let alice_id = uuid::Uuid::new_v4();
+    sqlx::query!(
+        "INSERT INTO subscriptions (id, email, name, subscribed_at, status) \
+        VALUES ($1, 'alice@test.com', 'alice', now(), 'confirmed')",
+        alice_id
+    )
+    .execute(&app.connection_pool)
+    .await
+    .unwrap();
+    sqlx::query!(
+        "INSERT INTO list_subscriptions (list_id, subscriber_id, status, subscribed_at) \
+        SELECT id, $1, 'confirmed', now() FROM lists WHERE slug = 'newsletter'",
+        alice_id
+    )
+    .execute(&app.connection_pool)
+    .await
+    .unwrap();
+    app.test_user.login(&app).await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let csv = "name,email\n\
+        alice,alice@test.com\n\
+        bob,bob@test.com\n\
+        bob,bob@test.com\n\
+        carol,not-an-email\n\
+        ,dave@test.com";
+
+    let response = app.post_import_subscribers(csv, "confirmed").await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("Imported 1 subscriber(s) as confirmed"));
+    assert!(html_page.contains("Skipped 2 duplicate or already subscribed address(es)"));
+    assert!(html_page.contains("Line 5: not-an-email is not a valid email"));
+    assert!(html_page.contains("Line 6:  is not a valid name"));
+    let saved = sqlx::query!("SELECT name, status FROM subscriptions WHERE email = 'bob@test.com'")
+        .fetch_one(&app.connection_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.name, "bob");
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn existing_subscribers_are_added_to_the_list_they_are_imported_into() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    app.post_create_list("weekly", "Weekly digest").await;
+    let csv = "email,name\nalice@test.com,alice";
+    app.post_import_subscribers(csv, "confirmed").await;
+
+    let response = app
+        .post_import_subscribers_to_list(csv, "confirmed", "weekly")
+        .await;
+
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("Imported 1 subscriber(s) as confirmed"));
+    assert!(html_page.contains("Skipped 0 duplicate or already subscribed address(es)"));
+    let lists = sqlx::query!(
+        r#"
+        SELECT lists.slug
+        FROM list_subscriptions
+        JOIN lists ON lists.id = list_subscriptions.list_id
+        ORDER BY lists.slug
+        "#
+    )
+    .fetch_all(&app.connection_pool)
+    .await
+    .unwrap();
+    let lists: Vec<_> = lists.into_iter().map(|l| l.slug).collect();
+    assert_eq!(lists, ["newsletter", "weekly"]);
+
+    // Imported into the same list again, it is a duplicate
+    let response = app
+        .post_import_subscribers_to_list(csv, "confirmed", "weekly")
+        .await;
+
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("Imported 0 subscriber(s) as confirmed"));
+    assert!(html_page.contains("Skipped 1 duplicate or already subscribed address(es)"));
+}
+
+#[tokio::test]
+async fn pending_imports_are_sent_a_confirmation_email_in_the_background() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    let csv = "email,name\nbob@test.com,bob\nalice@test.com,alice";
+
+    let response = app
+        .post_import_subscribers(csv, "pending_confirmation")
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // Emails are queued with the imported subscribers, and sent by the worker
+    app.dispatch_all_pending_emails().await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    let statuses = sqlx::query!("SELECT status FROM subscriptions ORDER BY status")
+        .fetch_all(&app.connection_pool)
+        .await
+        .unwrap();
+    assert_eq!(statuses[0].status, "confirmed");
+    assert_eq!(statuses[1].status, "pending_confirmation");
+}
+
+#[tokio::test]
+async fn a_csv_without_email_and_name_columns_is_rejected() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = app
+        .post_import_subscribers("address\nbob@test.com", "confirmed")
+        .await;
+
+    assert_is_redirect_to(&response, "/admin/subscribers/import");
+    let html_page = app
+        .api_client
+        .get(format!("{}/admin/subscribers/import", &app.address))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains("must have a header row with `email` and `name` columns"));
+}
+
+#[tokio::test]
+async fn files_larger_than_the_default_multipart_memory_limit_can_be_imported() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    // Padded with an attribute column to go over 2 MiB
+    let padding = "x".repeat(1000);
+    let mut csv = String::from("email,name,notes\n");
+    for i in 0..3000 {
+        csv.push_str(&format!(
+            "subscriber{i}@test.com,subscriber {i},{padding}\n"
+        ));
+    }
+    assert!(csv.len() > 2 * 1024 * 1024);
+
+    let response = app.post_import_subscribers(&csv, "confirmed").await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("Imported 3000 subscriber(s) as confirmed"));
+}
+
+#[tokio::test]
+async fn export_returns_the_filtered_subscriber_list_as_csv() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    app.post_import_subscribers("email,name\nbob@test.com,bob", "confirmed")
+        .await;
+    sqlx::query!(
+        "INSERT INTO subscriptions (id, email, name, subscribed_at, status) \
+        VALUES ($1, 'alice@test.com', 'alice', now(), 'pending_confirmation')",
+        uuid::Uuid::new_v4()
+    )
+    .execute(&app.connection_pool)
+    .await
+    .unwrap();
+
+    let response = app.get_export_subscribers("status=confirmed").await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.headers().get("Content-Type").unwrap(),
+        "text/csv; charset=utf-8"
+    );
+    let body = response.text().await.unwrap();
+    let mut lines = body.lines();
+    assert_eq!(lines.next(), Some("email,name,status,subscribed_at,tags"));
+    assert!(lines
+        .next()
+        .unwrap()
+        .starts_with("bob@test.com,bob,confirmed,"));
+    assert_eq!(lines.next(), None);
+}
+
+#[tokio::test]
+async fn exported_subscribers_can_be_imported_back_with_their_tags_and_attributes() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let csv = "email,name,tags,country,plan\n\
+        alice@test.com,alice,beta;vip,DE,pro\n\
+        bob@test.com,bob,,FR,\n";
+    app.post_import_subscribers(csv, "confirmed").await;
+    let saved_subscribers = || async {
+        sqlx::query!("SELECT email, name, tags, attributes FROM subscriptions ORDER BY email")
+            .fetch_all(&app.connection_pool)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|s| (s.email, s.name, s.tags, s.attributes))
+            .collect::<Vec<_>>()
+    };
+    let imported = saved_subscribers().await;
+
+    let export = app.get_export_subscribers("").await.text().await.unwrap();
+    sqlx::query!("DELETE FROM subscriptions")
+        .execute(&app.connection_pool)
+        .await
+        .unwrap();
+    let response = app.post_import_subscribers(&export, "confirmed").await;
+
+    assert!(export.starts_with("email,name,status,subscribed_at,tags,country,plan\n"));
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("Imported 2 subscriber(s) as confirmed"));
+    assert_eq!(saved_subscribers().await, imported);
+    assert_eq!(
+        imported[0].3,
+        serde_json::json!({"country": "DE", "plan": "pro"})
+    );
+    assert_eq!(imported[0].2, ["beta", "vip"]);
+}
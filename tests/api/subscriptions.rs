@@ -9,12 +9,6 @@ use crate::helpers::spawn_app;
 async fn subsribe_returns_200_for_valid_req() {
     let app = spawn_app().await;
     let body = "name=bob%20bobbington&email=bob%40test.com";
-    Mock::given(path("/email"))
-        .and(method("POST"))
-        .respond_with(ResponseTemplate::new(200))
-        .expect(1)
-        .mount(&app.email_server)
-        .await;
 
     let response = app.post_subscription(body.into()).await;
     assert_eq!(200, response.status().as_u16());
@@ -53,6 +47,7 @@ async fn subscribe_sends_a_confirmation_email_for_valid_data() {
         .await;
 
     app.post_subscription(body.into()).await;
+    app.dispatch_all_pending_emails().await;
 }
 
 #[tokio::test]
@@ -67,6 +62,7 @@ async fn subscribe_sends_a_confirmation_email_with_a_link() {
         .await;
     // Make mock requestm get the received request from mock email server.
     app.post_subscription(body.into()).await;
+    app.dispatch_all_pending_emails().await;
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
     // Get confirm links out of request
     let confirm_links = app.get_confirmation_links(email_request);
@@ -124,3 +120,246 @@ async fn subscribe_fails_if_there_is_a_fatal_db_error() {
     let response = app.post_subscription(body.into()).await;
     assert_eq!(response.status().as_u16(), 500);
 }
+
+#[tokio::test]
+async fn subscribing_during_an_email_provider_outage_succeeds_and_the_email_is_retried() {
+    let app = spawn_app().await;
+    let body = "name=bob%20bobbington&email=bob%40test.com";
+    let outage = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(503))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    let response = app.post_subscription(body.into()).await;
+    app.dispatch_all_pending_emails().await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    drop(outage);
+    let job = sqlx::query!(
+        "SELECT status, n_attempts FROM jobs WHERE job_type = 'send_confirmation_email'"
+    )
+    .fetch_one(&app.connection_pool)
+    .await
+    .unwrap();
+    assert_eq!(job.status, "queued");
+    assert_eq!(job.n_attempts, 1);
+
+    // Once the retry is due, the email goes out
+    sqlx::query!("UPDATE jobs SET run_at = now()")
+        .execute(&app.connection_pool)
+        .await
+        .unwrap();
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+    let n_jobs = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM jobs"#)
+        .fetch_one(&app.connection_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_jobs, 0);
+}
+
+#[tokio::test]
+async fn confirmation_emails_refused_by_the_email_provider_are_not_retried() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(422).set_body_json(serde_json::json!({
+            "ErrorCode": 406,
+            "Message": "You tried to send to a recipient that has been marked as inactive."
+        })))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    let body = "name=bob&email=bob%40test.com";
+
+    let response = app.post_subscription(body.into()).await;
+    app.dispatch_all_pending_emails().await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let status = sqlx::query!("SELECT status FROM jobs WHERE job_type = 'send_confirmation_email'")
+        .fetch_one(&app.connection_pool)
+        .await
+        .unwrap()
+        .status;
+    assert_eq!(status, "failed");
+
+    // The unsent token is forgotten, subscribing again doesn't wait for the resend interval
+    app.post_subscription(body.into()).await;
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn subscribing_twice_while_pending_does_not_resend_within_the_resend_interval() {
+    let app = spawn_app().await;
+    let body = "name=bob%20bobbington&email=bob%40test.com";
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscription(body.into()).await;
+    let response = app.post_subscription(body.into()).await;
+    app.dispatch_all_pending_emails().await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    // Mock verifies on Drop that only one confirmation email was sent
+}
+
+#[tokio::test]
+async fn subscribing_again_while_pending_sends_a_fresh_confirmation_link() {
+    let app = spawn_app().await;
+    let body = "name=bob%20bobbington&email=bob%40test.com";
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    app.post_subscription(body.into()).await;
+    app.dispatch_all_pending_emails().await;
+    // Pretend the first email was sent long ago
+    sqlx::query!("UPDATE subscription_tokens SET created_at = now() - INTERVAL '1 day'")
+        .execute(&app.connection_pool)
+        .await
+        .unwrap();
+
+    let response = app.post_subscription(body.into()).await;
+    app.dispatch_all_pending_emails().await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let email_requests = app.email_server.received_requests().await.unwrap();
+    let first_link = app.get_confirmation_links(&email_requests[0]).plain_text;
+    let second_link = app.get_confirmation_links(&email_requests[1]).plain_text;
+    assert_ne!(first_link, second_link);
+}
+
+#[tokio::test]
+async fn subscribing_an_already_confirmed_email_does_not_send_an_email() {
+    let app = spawn_app().await;
+    let body = "name=bob%20bobbington&email=bob%40test.com";
+    let mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_subscription(body.into()).await;
+    app.dispatch_all_pending_emails().await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    drop(mock_guard);
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_subscription(body.into()).await;
+    app.dispatch_all_pending_emails().await;
+
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn invalid_details_re_render_the_form_with_the_error_in_browsers() {
+    let app = spawn_app().await;
+    let body = "name=%3Cb%3Ebob%3C%2Fb%3E&email=bob%40test.com";
+
+    let response = app.post_subscription_as_browser(body.into()).await;
+
+    assert_eq!(response.status().as_u16(), 400);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("<p><i>&lt;b&gt;bob&lt;/b&gt; is not a valid name</i></p>"));
+    assert!(html_page.contains(r#"<form action="/subscriptions" method="post">"#));
+    // What was submitted is filled back in, escaped
+    assert!(html_page.contains(r#"value="&lt;b&gt;bob&lt;/b&gt;""#));
+    assert!(html_page.contains(r#"value="bob@test.com""#));
+}
+
+#[tokio::test]
+async fn invalid_details_get_a_json_error_for_api_clients() {
+    let app = spawn_app().await;
+    let body = "name=bob&email=not-an-email";
+
+    let response = app.post_subscription(body.into()).await;
+
+    assert_eq!(response.status().as_u16(), 400);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["error"], "not-an-email is not a valid email");
+}
+
+#[tokio::test]
+async fn browsers_get_a_page_asking_to_check_their_inbox() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_subscription_as_browser("name=bob&email=bob%40test.com".into())
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("Check your inbox for a link to confirm your subscription."));
+}
+
+#[tokio::test]
+async fn the_home_page_has_the_subscription_form() {
+    let app = spawn_app().await;
+
+    let html_page = reqwest::get(&app.address)
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+
+    assert!(html_page.contains(r#"<form action="/subscriptions" method="post">"#));
+}
+
+#[tokio::test]
+async fn concurrent_subscriptions_for_the_same_new_email_both_succeed() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let body = "name=bob%20bobbington&email=bob%40test.com";
+
+    let (response1, response2) = tokio::join!(
+        app.post_subscription(body.into()),
+        app.post_subscription(body.into())
+    );
+
+    assert_eq!(response1.status().as_u16(), 200);
+    assert_eq!(response2.status().as_u16(), 200);
+    let n_subscribers = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM subscriptions"#)
+        .fetch_one(&app.connection_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_subscribers, 1);
+}
@@ -25,6 +25,7 @@ async fn the_link_returned_by_subscribe_returns_a_200_if_called() {
         .await;
     // Make initial request to get confirm link
     app.post_subscription(body.into()).await;
+    app.dispatch_all_pending_emails().await;
 
     // Get the received request off of the mock email server and the confirm link out of it
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
@@ -46,6 +47,7 @@ async fn clicking_on_a_confirm_link_confirms_a_subscriber_in_db() {
         .await;
     // Make inital request to get confirm link
     app.post_subscription(body.into()).await;
+    app.dispatch_all_pending_emails().await;
 
     // Get the received request off of the mock email server and the confirm link out of it
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
@@ -66,3 +68,127 @@ async fn clicking_on_a_confirm_link_confirms_a_subscriber_in_db() {
     assert_eq!(saved.name, "bob bobbington");
     assert_eq!(saved.status, "confirmed");
 }
+
+#[tokio::test]
+async fn clicking_a_confirmation_link_twice_reports_an_already_confirmed_subscription() {
+    let app = spawn_app().await;
+    let body = "name=bob%20bobbington&email=bob%40test.com";
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_subscription(body.into()).await;
+    app.dispatch_all_pending_emails().await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirm_links = app.get_confirmation_links(email_request);
+
+    let response = app.get_as_browser(confirm_links.html.clone()).await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("Your subscription is confirmed."));
+    let response = app.get_as_browser(confirm_links.html).await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("Your subscription was already confirmed"));
+
+    let n_confirmations = sqlx::query!(
+        r#"SELECT COUNT(*) as "count!" FROM subscription_events WHERE event = 'confirmed'"#
+    )
+    .fetch_one(&app.connection_pool)
+    .await
+    .unwrap()
+    .count;
+    assert_eq!(n_confirmations, 1);
+}
+
+#[tokio::test]
+async fn a_used_confirmation_link_cannot_confirm_the_subscriber_again() {
+    let app = spawn_app().await;
+    let body = "name=bob%20bobbington&email=bob%40test.com";
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_subscription(body.into()).await;
+    app.dispatch_all_pending_emails().await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirm_links = app.get_confirmation_links(email_request);
+    reqwest::get(confirm_links.html.clone())
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    sqlx::query!("UPDATE subscriptions SET status = 'unsubscribed'")
+        .execute(&app.connection_pool)
+        .await
+        .unwrap();
+
+    let response = reqwest::get(confirm_links.html).await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn an_unknown_token_gets_an_html_page_in_browsers_and_json_otherwise() {
+    let app = spawn_app().await;
+    let link = reqwest::Url::parse(&format!(
+        "{}/subscriptions/confirm?subscription_token=unknown",
+        app.address
+    ))
+    .unwrap();
+
+    let response = app.get_as_browser(link.clone()).await;
+    assert_eq!(response.status().as_u16(), 401);
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "text/html; charset=utf-8"
+    );
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("<p>This confirmation link is not valid.</p>"));
+
+    let response = reqwest::get(link).await.unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["error"], "This confirmation link is not valid.");
+}
+
+#[tokio::test]
+async fn an_expired_confirmation_link_is_rejected_with_an_explanation() {
+    let app = spawn_app().await;
+    let body = "name=bob%20bobbington&email=bob%40test.com";
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_subscription(body.into()).await;
+    app.dispatch_all_pending_emails().await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirm_links = app.get_confirmation_links(email_request);
+    sqlx::query!("UPDATE subscription_tokens SET expires_at = now() - INTERVAL '1 minute'")
+        .execute(&app.connection_pool)
+        .await
+        .unwrap();
+
+    let response = app.get_as_browser(confirm_links.html).await;
+
+    assert_eq!(response.status().as_u16(), 410);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("This confirmation link has expired."));
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.connection_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "pending_confirmation");
+}
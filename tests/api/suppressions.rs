@@ -0,0 +1,186 @@
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+
+const SPAM_COMPLAINT: &str = include_str!("fixtures/postmark_spam_complaint.json");
+
+async fn post_suppression(app: &TestApp, entry: &str) -> reqwest::Response {
+    app.api_client
+        .post(format!("{}/admin/suppressions", app.address))
+        .form(&[("entry", entry)])
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn get_suppressions_html(app: &TestApp) -> String {
+    app.api_client
+        .get(format!("{}/admin/suppressions", app.address))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn admins_can_add_and_remove_suppressions() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = post_suppression(&app, "Ann@Test.com").await;
+    assert_is_redirect_to(&response, "/admin/suppressions");
+    let html_page = get_suppressions_html(&app).await;
+    assert!(html_page.contains("Nothing will be sent to ann@test.com"));
+    assert!(html_page.contains("<td>ann@test.com</td>"));
+
+    post_suppression(&app, "ann@test.com").await;
+    assert!(get_suppressions_html(&app)
+        .await
+        .contains("ann@test.com is already suppressed"));
+
+    post_suppression(&app, "not a domain").await;
+    assert!(get_suppressions_html(&app)
+        .await
+        .contains("not a domain is neither an email address nor a domain"));
+
+    let id = sqlx::query!("SELECT id FROM suppressions")
+        .fetch_one(&app.connection_pool)
+        .await
+        .unwrap()
+        .id;
+    let response = app
+        .api_client
+        .post(format!("{}/admin/suppressions/{id}/delete", app.address))
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/admin/suppressions");
+    let html_page = get_suppressions_html(&app).await;
+    assert!(html_page.contains("ann@test.com is no longer suppressed"));
+    assert!(!html_page.contains("<td>ann@test.com</td>"));
+}
+
+#[tokio::test]
+async fn issues_are_not_sent_to_suppressed_addresses_and_domains() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let response = app
+        .post_import_subscribers(
+            "email,name\nann@test.com,Ann\nbob@blocked.com,Bob\ncat@test.com,Cat\n",
+            "confirmed",
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    post_suppression(&app, "ANN@test.com").await;
+    post_suppression(&app, "blocked.com").await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_newsletter(&[
+            ("title", "Issue #1".to_string()),
+            ("text_content", "Text".to_string()),
+            ("html_content", "<p>HTML</p>".to_string()),
+            ("idempotency_key", uuid::Uuid::new_v4().to_string()),
+        ])
+        .await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    app.dispatch_all_pending_emails().await;
+
+    let request = &app.email_server.received_requests().await.unwrap()[0];
+    let email: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+    assert_eq!(email["To"], "cat@test.com");
+    let issue_id = sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_one(&app.connection_pool)
+        .await
+        .unwrap()
+        .newsletter_issue_id;
+    let html_page = app
+        .api_client
+        .get(format!("{}/admin/issues/{issue_id}", app.address))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains("Delivered to 1 subscriber(s), 0 still queued."));
+    assert!(html_page.contains("Not sent to 2 suppressed address(es)."));
+}
+
+#[tokio::test]
+async fn subscribing_again_does_not_email_an_address_that_complained() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let response = app
+        .post_import_subscribers("email,name\nann@test.com,Ann\n", "confirmed")
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let response = app.post_postmark_webhook(SPAM_COMPLAINT).await;
+    assert_eq!(response.status().as_u16(), 200);
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_subscription("name=Ann&email=ann%40test.com".into())
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let reason = sqlx::query!("SELECT reason FROM suppressions WHERE entry = 'ann@test.com'")
+        .fetch_one(&app.connection_pool)
+        .await
+        .unwrap()
+        .reason;
+    assert_eq!(reason, "spam_complaint");
+    let status = sqlx::query!("SELECT status FROM subscriptions WHERE email = 'ann@test.com'")
+        .fetch_one(&app.connection_pool)
+        .await
+        .unwrap()
+        .status;
+    assert_eq!(status, "complained");
+}
+
+#[tokio::test]
+async fn confirmation_emails_are_not_sent_to_suppressed_addresses() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let response = post_suppression(&app, "ann@test.com").await;
+    assert_is_redirect_to(&response, "/admin/suppressions");
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_subscription("name=Ann&email=ann%40test.com".into())
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let n_suppressed = sqlx::query!(
+        r#"
+        SELECT COUNT(*) AS "count!"
+        FROM subscription_events
+        WHERE event = 'confirmation_suppressed'
+        "#
+    )
+    .fetch_one(&app.connection_pool)
+    .await
+    .unwrap()
+    .count;
+    assert_eq!(n_suppressed, 1);
+}
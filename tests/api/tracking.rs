@@ -0,0 +1,151 @@
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+
+// Imports ann@test.com as a confirmed subscriber, publishes an issue and returns its HTML body
+async fn send_issue(app: &TestApp, tracking: &[(&'static str, &str)]) -> String {
+    app.test_user.login(app).await;
+    let response = app
+        .post_import_subscribers("email,name\nann@test.com,Ann\n", "confirmed")
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let mut body = vec![
+        ("title", "Issue #1".to_string()),
+        ("text_content", "Text".to_string()),
+        (
+            "html_content",
+            r#"<a href="https://example.com/a?x=1&amp;y=2">A</a> <a href="https://example.com/b">B</a>"#
+                .to_string(),
+        ),
+        ("idempotency_key", uuid::Uuid::new_v4().to_string()),
+    ];
+    body.extend(tracking.iter().map(|(k, v)| (*k, v.to_string())));
+    let response = app.post_newsletter(&body).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    app.dispatch_all_pending_emails().await;
+
+    let request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let email: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+    email["HtmlBody"].as_str().unwrap().to_owned()
+}
+
+// The tracking URLs of the email, pointing to the test application
+fn tracking_urls(app: &TestApp, html: &str, endpoint: &str) -> Vec<reqwest::Url> {
+    html.split('"')
+        .filter(|part| part.contains(endpoint))
+        .map(|part| {
+            let mut url = reqwest::Url::parse(part).unwrap();
+            url.set_port(Some(app.port)).unwrap();
+            url
+        })
+        .collect()
+}
+
+async fn n_events(app: &TestApp) -> i64 {
+    sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM email_events"#)
+        .fetch_one(&app.connection_pool)
+        .await
+        .unwrap()
+        .count
+}
+
+#[tokio::test]
+async fn issues_are_not_tracked_unless_asked_to() {
+    let app = spawn_app().await;
+
+    let html = send_issue(&app, &[]).await;
+
+    assert!(!html.contains("/tracking/"));
+    assert!(html.contains(r#"<a href="https://example.com/b">"#));
+}
+
+#[tokio::test]
+async fn opens_and_clicks_are_recorded_and_shown_on_the_issue_page() {
+    let app = spawn_app().await;
+    let html = send_issue(&app, &[("track_opens", "on"), ("track_clicks", "on")]).await;
+    let pixel = tracking_urls(&app, &html, "/tracking/open").pop().unwrap();
+    let links = tracking_urls(&app, &html, "/tracking/click");
+    assert_eq!(links.len(), 2);
+
+    for _ in 0..2 {
+        let response = app.api_client.get(pixel.clone()).send().await.unwrap();
+        assert_eq!(response.status().as_u16(), 200);
+        assert_eq!(response.headers()["Content-Type"], "image/gif");
+    }
+    for _ in 0..2 {
+        let response = app.api_client.get(links[0].clone()).send().await.unwrap();
+        assert_is_redirect_to(&response, "https://example.com/a?x=1&y=2");
+    }
+
+    let issue_id = sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_one(&app.connection_pool)
+        .await
+        .unwrap()
+        .newsletter_issue_id;
+    let html_page = app
+        .api_client
+        .get(format!("{}/admin/issues/{issue_id}", app.address))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains("Opened by 1 subscriber(s)"));
+    assert!(html_page.contains(
+        "<td>https://example.com/a?x=1&amp;y=2</td>\n                <td>2</td>\n                <td>1</td>"
+    ));
+    assert!(html_page.contains(
+        "<td>https://example.com/b</td>\n                <td>0</td>\n                <td>0</td>"
+    ));
+}
+
+#[tokio::test]
+async fn nothing_is_recorded_for_unsubscribed_or_erased_subscribers() {
+    let app = spawn_app().await;
+    let html = send_issue(&app, &[("track_opens", "on"), ("track_clicks", "on")]).await;
+    let pixel = tracking_urls(&app, &html, "/tracking/open").pop().unwrap();
+    let link = tracking_urls(&app, &html, "/tracking/click").pop().unwrap();
+    let subscriber_id = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_one(&app.connection_pool)
+        .await
+        .unwrap()
+        .id;
+
+    app.post_subscriber_action(subscriber_id, "unsubscribe")
+        .await;
+    app.api_client.get(pixel).send().await.unwrap();
+    assert_eq!(n_events(&app).await, 0);
+
+    app.post_subscriber_action(subscriber_id, "delete").await;
+    // The link keeps working
+    let response = app.api_client.get(link).send().await.unwrap();
+    assert_is_redirect_to(&response, "https://example.com/b");
+    assert_eq!(n_events(&app).await, 0);
+}
+
+#[tokio::test]
+async fn a_forged_click_token_is_rejected() {
+    let app = spawn_app().await;
+
+    let response = app
+        .api_client
+        .get(format!("{}/tracking/click?token=forged", app.address))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 401);
+}
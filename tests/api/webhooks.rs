@@ -0,0 +1,204 @@
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+
+const HARD_BOUNCE: &str = include_str!("fixtures/postmark_hard_bounce.json");
+const SOFT_BOUNCE: &str = include_str!("fixtures/postmark_soft_bounce.json");
+const SPAM_COMPLAINT: &str = include_str!("fixtures/postmark_spam_complaint.json");
+
+// Imports ann@test.com, the address used in the fixtures, and bob@test.com as confirmed subscribers
+async fn import_subscribers(app: &TestApp) {
+    app.test_user.login(app).await;
+    let response = app
+        .post_import_subscribers(
+            "email,name\nann@test.com,Ann\nbob@test.com,Bob\n",
+            "confirmed",
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+async fn status_of(app: &TestApp, email: &str) -> String {
+    sqlx::query!("SELECT status FROM subscriptions WHERE email = $1", email)
+        .fetch_one(&app.connection_pool)
+        .await
+        .unwrap()
+        .status
+}
+
+async fn n_bounce_events(app: &TestApp) -> i64 {
+    sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM bounce_events"#)
+        .fetch_one(&app.connection_pool)
+        .await
+        .unwrap()
+        .count
+}
+
+#[tokio::test]
+async fn a_hard_bounce_stops_issues_to_the_address() {
+    let app = spawn_app().await;
+    import_subscribers(&app).await;
+
+    let response = app.post_postmark_webhook(HARD_BOUNCE).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(status_of(&app, "ann@test.com").await, "bounced");
+    let event = sqlx::query!("SELECT record_type, bounce_type, message_id FROM bounce_events")
+        .fetch_one(&app.connection_pool)
+        .await
+        .unwrap();
+    assert_eq!(event.record_type, "Bounce");
+    assert_eq!(event.bounce_type.as_deref(), Some("HardBounce"));
+    assert_eq!(
+        event.message_id.as_deref(),
+        Some("883953f4-6105-42a2-a16a-77a8eac79483")
+    );
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let response = app
+        .post_newsletter(&[
+            ("title", "Issue #1".to_string()),
+            ("text_content", "Text".to_string()),
+            ("html_content", "<p>HTML</p>".to_string()),
+            ("idempotency_key", uuid::Uuid::new_v4().to_string()),
+        ])
+        .await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    app.dispatch_all_pending_emails().await;
+    let request = &app.email_server.received_requests().await.unwrap()[0];
+    let email: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+    assert_eq!(email["To"], "bob@test.com");
+}
+
+#[tokio::test]
+async fn a_spam_complaint_marks_the_subscriber_as_complained() {
+    let app = spawn_app().await;
+    import_subscribers(&app).await;
+
+    let response = app.post_postmark_webhook(SPAM_COMPLAINT).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(status_of(&app, "ann@test.com").await, "complained");
+    let id = sqlx::query!("SELECT id FROM subscriptions WHERE email = 'ann@test.com'")
+        .fetch_one(&app.connection_pool)
+        .await
+        .unwrap()
+        .id;
+    let html_page = app.get_subscriber_details_html(id).await;
+    assert!(html_page.contains("complained"));
+}
+
+#[tokio::test]
+async fn subscribing_again_does_not_reset_a_bounced_address() {
+    let app = spawn_app().await;
+    import_subscribers(&app).await;
+    let response = app.post_postmark_webhook(HARD_BOUNCE).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = app
+        .post_subscription("name=Ann&email=ann%40test.com".into())
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(status_of(&app, "ann@test.com").await, "bounced");
+    let n_tokens = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM subscription_tokens"#)
+        .fetch_one(&app.connection_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_tokens, 0);
+}
+
+#[tokio::test]
+async fn an_admin_cannot_confirm_a_bounced_or_complained_subscriber() {
+    let app = spawn_app().await;
+    import_subscribers(&app).await;
+    for payload in [HARD_BOUNCE, SPAM_COMPLAINT] {
+        let response = app.post_postmark_webhook(payload).await;
+        assert_eq!(response.status().as_u16(), 200);
+        let status = status_of(&app, "ann@test.com").await;
+        let id = sqlx::query!("SELECT id FROM subscriptions WHERE email = 'ann@test.com'")
+            .fetch_one(&app.connection_pool)
+            .await
+            .unwrap()
+            .id;
+
+        let response = app.post_subscriber_action(id, "confirm").await;
+
+        assert_is_redirect_to(&response, &format!("/admin/subscribers/{id}"));
+        let html_page = app.get_subscriber_details_html(id).await;
+        assert!(html_page.contains(&format!(
+            "The subscriber is {status}, they cannot be confirmed"
+        )));
+        assert_eq!(status_of(&app, "ann@test.com").await, status);
+    }
+}
+
+#[tokio::test]
+async fn a_soft_bounce_is_only_recorded() {
+    let app = spawn_app().await;
+    import_subscribers(&app).await;
+
+    let response = app.post_postmark_webhook(SOFT_BOUNCE).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(n_bounce_events(&app).await, 1);
+    assert_eq!(status_of(&app, "ann@test.com").await, "confirmed");
+}
+
+#[tokio::test]
+async fn events_about_unknown_addresses_are_accepted() {
+    let app = spawn_app().await;
+
+    let response = app.post_postmark_webhook(HARD_BOUNCE).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(n_bounce_events(&app).await, 1);
+}
+
+#[tokio::test]
+async fn webhooks_without_valid_credentials_are_rejected() {
+    let app = spawn_app().await;
+    import_subscribers(&app).await;
+    let url = format!("{}/webhooks/postmark", app.address);
+
+    let requests = [
+        app.api_client.post(&url),
+        app.api_client
+            .post(&url)
+            .basic_auth(&app.webhook_settings.username, Some("wrong-password")),
+        app.api_client.post(&url).bearer_auth("a-token"),
+    ];
+    for request in requests {
+        let response = request
+            .header("Content-Type", "application/json")
+            .body(HARD_BOUNCE)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status().as_u16(), 401);
+        assert!(response.headers()["WWW-Authenticate"]
+            .to_str()
+            .unwrap()
+            .starts_with("Basic"));
+    }
+
+    assert_eq!(n_bounce_events(&app).await, 0);
+    assert_eq!(status_of(&app, "ann@test.com").await, "confirmed");
+}
+
+#[tokio::test]
+async fn payloads_that_are_not_bounces_are_rejected() {
+    let app = spawn_app().await;
+
+    let response = app.post_postmark_webhook(r#"{"Hello": "world"}"#).await;
+
+    assert_eq!(response.status().as_u16(), 400);
+    assert_eq!(n_bounce_events(&app).await, 0);
+}